@@ -1,10 +1,21 @@
 #[derive(Debug, Clone, PartialEq)]
 pub enum AttributeValue {
     String(String),
+    /// 重複のない集合 (昇順)。new_set で作る
     Set(Vec<String>),
     Boolean(bool),
 }
 
+impl AttributeValue {
+    /// 要素を昇順に並べて重複を取り除いた Set を作る
+    pub fn new_set<I: IntoIterator<Item = String>>(items: I) -> AttributeValue {
+        let mut items: Vec<String> = items.into_iter().collect();
+        items.sort();
+        items.dedup();
+        AttributeValue::Set(items)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum UserAttributeKey {
     Position,
@@ -14,6 +25,39 @@ pub enum UserAttributeKey {
     IsChair,
 }
 
+impl UserAttributeKey {
+    pub const ALL: [UserAttributeKey; 5] = [
+        UserAttributeKey::Position,
+        UserAttributeKey::Department,
+        UserAttributeKey::CrsTaken,
+        UserAttributeKey::CrsTaught,
+        UserAttributeKey::IsChair,
+    ];
+
+    /// ルールファイル上の属性名 ("position" など) から変換する
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "position" => Some(UserAttributeKey::Position),
+            "department" => Some(UserAttributeKey::Department),
+            "crsTaken" => Some(UserAttributeKey::CrsTaken),
+            "crsTaught" => Some(UserAttributeKey::CrsTaught),
+            "isChair" => Some(UserAttributeKey::IsChair),
+            _ => None,
+        }
+    }
+
+    /// from_name の逆
+    pub fn name(&self) -> &'static str {
+        match self {
+            UserAttributeKey::Position => "position",
+            UserAttributeKey::Department => "department",
+            UserAttributeKey::CrsTaken => "crsTaken",
+            UserAttributeKey::CrsTaught => "crsTaught",
+            UserAttributeKey::IsChair => "isChair",
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ResourceAttributeKey {
     Type,
@@ -22,6 +66,19 @@ pub enum ResourceAttributeKey {
     Departments,
 }
 
+impl ResourceAttributeKey {
+    /// ルールファイル上の属性名 ("type" など) から変換する
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "type" => Some(ResourceAttributeKey::Type),
+            "crs" => Some(ResourceAttributeKey::Crs),
+            "student" => Some(ResourceAttributeKey::Student),
+            "departments" => Some(ResourceAttributeKey::Departments),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct UserAttribute {
     pub user_id: String,
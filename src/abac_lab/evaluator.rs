@@ -0,0 +1,159 @@
+use crate::abac_lab::attr_val::*;
+
+fn user_value<'a>(user: &'a UserAttribute, name: &str) -> Option<&'a AttributeValue> {
+    user.attributes.get(&UserAttributeKey::from_name(name)?)
+}
+
+fn resource_value<'a>(resource: &'a ResourceAttribute, name: &str) -> Option<&'a AttributeValue> {
+    resource.attributes.get(&ResourceAttributeKey::from_name(name)?)
+}
+
+// 真偽値はルールファイルと同じ "True" / "False" として比較する
+fn as_text(value: &AttributeValue) -> Option<String> {
+    match value {
+        AttributeValue::String(s) => Some(s.clone()),
+        AttributeValue::Boolean(b) => Some(if *b { "True" } else { "False" }.to_string()),
+        AttributeValue::Set(_) => None,
+    }
+}
+
+/// 属性値が values に合うか。単一値は values のいずれかと一致、集合値は values を全て含む
+fn value_matches(value: &AttributeValue, values: &[String]) -> bool {
+    match value {
+        AttributeValue::Set(items) => values.iter().all(|v| items.contains(v)),
+        other => as_text(other).is_some_and(|s| values.contains(&s)),
+    }
+}
+
+/// user 側の値が resource 側の値を含むか (集合 ⊇ 集合、集合 ∋ 単一値、単一値 ∈ 集合)
+fn user_contains_resource(user: &AttributeValue, resource: &AttributeValue) -> bool {
+    match (user, resource) {
+        (AttributeValue::Set(u), AttributeValue::Set(r)) => r.iter().all(|v| u.contains(v)),
+        (AttributeValue::Set(u), r) => as_text(r).is_some_and(|r| u.contains(&r)),
+        (u, AttributeValue::Set(r)) => as_text(u).is_some_and(|u| r.contains(&u)),
+        (u, r) => as_text(u).is_some() && as_text(u) == as_text(r),
+    }
+}
+
+fn condition_matches(cond: &RuleCondition, user: &UserAttribute, resource: &ResourceAttribute) -> bool {
+    match cond {
+        // key はまず user の属性として、無ければ resource の属性として探す
+        RuleCondition::AttributeMatch { key, values } => user_value(user, key)
+            .or_else(|| resource_value(resource, key))
+            .is_some_and(|v| value_matches(v, values)),
+        // user と resource の属性値が等しい
+        RuleCondition::AttributeComparison { user_key, resource_key } => {
+            match (user_value(user, user_key), resource_value(resource, resource_key)) {
+                (Some(u), Some(r)) => match (u, r) {
+                    (AttributeValue::Set(a), AttributeValue::Set(b)) => {
+                        let (mut a, mut b) = (a.clone(), b.clone());
+                        a.sort();
+                        b.sort();
+                        a == b
+                    }
+                    _ => as_text(u).is_some() && as_text(u) == as_text(r),
+                },
+                _ => false,
+            }
+        }
+        // user の属性値が resource の属性値を含む
+        RuleCondition::UserResourceMatch { user_key, resource_key } => {
+            match (user_value(user, user_key), resource_value(resource, resource_key)) {
+                (Some(u), Some(r)) => user_contains_resource(u, r),
+                _ => false,
+            }
+        }
+    }
+}
+
+/// rule の全ての条件を満たすか。resource_type があれば resource の type も一致する必要がある。
+/// 属性を持たない場合はその条件を満たさないものとする
+pub fn rule_matches(rule: &Rule, user: &UserAttribute, resource: &ResourceAttribute) -> bool {
+    if let Some(ty) = &rule.resource_type
+        && resource_value(resource, "type").and_then(as_text).as_ref() != Some(ty)
+    {
+        return false;
+    }
+    rule.conditions.iter().all(|c| condition_matches(c, user, resource))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abac_lab::parser::Parser;
+
+    fn fixtures() -> (UserAttribute, ResourceAttribute) {
+        let mut parser = Parser::new();
+        parser
+            .parse_line("userAttrib(alice, position=faculty, department=cs, crsTaught={cs101 cs102}, isChair=True)")
+            .unwrap();
+        parser
+            .parse_line("resourceAttrib(gradebook1, type=gradebook, crs=cs101, departments={cs math})")
+            .unwrap();
+        (parser.users.remove(0), parser.resources.remove(0))
+    }
+
+    fn rule(conditions: Vec<RuleCondition>) -> Rule {
+        Rule { conditions, actions: vec!["read".to_string()], resource_type: None }
+    }
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn attribute_match_checks_user_then_resource_values() {
+        let (user, resource) = fixtures();
+        let matching = rule(vec![
+            RuleCondition::AttributeMatch { key: "position".into(), values: strings(&["faculty", "staff"]) },
+            RuleCondition::AttributeMatch { key: "isChair".into(), values: strings(&["True"]) },
+            RuleCondition::AttributeMatch { key: "crsTaught".into(), values: strings(&["cs101"]) },
+            RuleCondition::AttributeMatch { key: "type".into(), values: strings(&["gradebook"]) },
+        ]);
+        assert!(rule_matches(&matching, &user, &resource));
+
+        let wrong = rule(vec![RuleCondition::AttributeMatch { key: "position".into(), values: strings(&["student"]) }]);
+        assert!(!rule_matches(&wrong, &user, &resource));
+        let missing = rule(vec![RuleCondition::AttributeMatch { key: "crsTaken".into(), values: strings(&["cs101"]) }]);
+        assert!(!rule_matches(&missing, &user, &resource));
+    }
+
+    #[test]
+    fn attribute_comparison_requires_equal_values() {
+        let (user, mut resource) = fixtures();
+        let cond = rule(vec![RuleCondition::AttributeComparison {
+            user_key: "department".into(),
+            resource_key: "departments".into(),
+        }]);
+        assert!(!rule_matches(&cond, &user, &resource));
+
+        resource.attributes.insert(ResourceAttributeKey::Departments, AttributeValue::String("cs".into()));
+        assert!(rule_matches(&cond, &user, &resource));
+    }
+
+    #[test]
+    fn user_resource_match_checks_containment() {
+        let (user, resource) = fixtures();
+        let taught = rule(vec![RuleCondition::UserResourceMatch {
+            user_key: "crsTaught".into(),
+            resource_key: "crs".into(),
+        }]);
+        assert!(rule_matches(&taught, &user, &resource));
+
+        let in_departments = rule(vec![RuleCondition::UserResourceMatch {
+            user_key: "department".into(),
+            resource_key: "departments".into(),
+        }]);
+        assert!(rule_matches(&in_departments, &user, &resource));
+
+        let not_taken = rule(vec![RuleCondition::UserResourceMatch {
+            user_key: "crsTaken".into(),
+            resource_key: "crs".into(),
+        }]);
+        assert!(!rule_matches(&not_taken, &user, &resource));
+
+        let mut typed = taught.clone();
+        typed.resource_type = Some("transcript".into());
+        assert!(!rule_matches(&typed, &user, &resource));
+    }
+}
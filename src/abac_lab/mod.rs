@@ -1,2 +1,3 @@
 pub mod attr_val;
 pub mod parser;
+pub mod evaluator;
\ No newline at end of file
@@ -1,18 +1,26 @@
 use crate::abac_lab::attr_val::*;
+use crate::util::normalization::NormalizationConfig;
 use std::collections::HashMap;
 
 pub struct Parser {
     pub users: Vec<UserAttribute>,
     pub resources: Vec<ResourceAttribute>,
     pub rules: Vec<Rule>,
+    /// 属性値にかける正規化（既定では無効）
+    pub normalization: NormalizationConfig,
 }
 
 impl Parser {
     pub fn new() -> Self {
+        Self::with_normalization(NormalizationConfig::default())
+    }
+
+    pub fn with_normalization(normalization: NormalizationConfig) -> Self {
         Parser {
             users: Vec::new(),
             resources: Vec::new(),
             rules: Vec::new(),
+            normalization,
         }
     }
 
@@ -56,14 +64,8 @@ impl Parser {
                 let key = key.trim();
                 let value = value.trim();
 
-                let attr_key = match key {
-                    "position" => UserAttributeKey::Position,
-                    "department" => UserAttributeKey::Department,
-                    "crsTaken" => UserAttributeKey::CrsTaken,
-                    "crsTaught" => UserAttributeKey::CrsTaught,
-                    "isChair" => UserAttributeKey::IsChair,
-                    _ => return Err(format!("Unknown user attribute key: {}", key))
-                };
+                let attr_key = UserAttributeKey::from_name(key)
+                    .ok_or_else(|| format!("Unknown user attribute key: {}", key))?;
 
                 let attr_value = self.parse_attribute_value(value)?;
                 attributes.insert(attr_key, attr_value);
@@ -95,13 +97,8 @@ impl Parser {
                 let key = key.trim();
                 let value = value.trim();
 
-                let attr_key = match key {
-                    "type" => ResourceAttributeKey::Type,
-                    "crs" => ResourceAttributeKey::Crs,
-                    "student" => ResourceAttributeKey::Student,
-                    "departments" => ResourceAttributeKey::Departments,
-                    _ => return Err(format!("Unknown resource attribute key: {}", key)),
-                };
+                let attr_key = ResourceAttributeKey::from_name(key)
+                    .ok_or_else(|| format!("Unknown resource attribute key: {}", key))?;
 
                 let attr_value = self.parse_attribute_value(value)?;
                 attributes.insert(attr_key, attr_value);
@@ -136,13 +133,34 @@ impl Parser {
 
         if value.starts_with('{') && value.ends_with('}') {
             let content = &value[1..value.len() - 1];
-            let items: Vec<String> = content
+            let items = content
                 .split_whitespace()
-                .map(|s| s.to_string())
-                .collect();
-            return Ok(AttributeValue::Set(items));
+                .map(|s| self.normalization.apply(s));
+            return Ok(AttributeValue::new_set(items));
         }
 
-        Ok(AttributeValue::String(value.to_string()))
+        Ok(AttributeValue::String(self.normalization.apply(value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalization_lowercases_values() {
+        let line = "userAttrib(bob, position=Faculty, crsTaken={CS101 cs101})";
+        let mut parser = Parser::with_normalization(NormalizationConfig { lowercase: true, ..Default::default() });
+        parser.parse_line(line).unwrap();
+        let user = &parser.users[0];
+        assert_eq!(user.attributes[&UserAttributeKey::Position], AttributeValue::String("faculty".to_string()));
+        assert_eq!(
+            user.attributes[&UserAttributeKey::CrsTaken],
+            AttributeValue::Set(vec!["cs101".to_string()])
+        );
+
+        let mut plain = Parser::new();
+        plain.parse_line(line).unwrap();
+        assert_eq!(plain.users[0].attributes[&UserAttributeKey::Position], AttributeValue::String("Faculty".to_string()));
     }
-}
\ No newline at end of file
+}
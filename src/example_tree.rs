@@ -1,5 +1,48 @@
-use crate::attr_val::*;
 use std::collections::HashMap;
+use std::io::Read;
+
+use crate::cal_shannon_entropy;
+use crate::util::csv::read_csv_table;
+use crate::util::rng::SplitMix64;
+
+/// 未知の属性値で最初の子ノードに進んだことを表すマーカー
+pub const UNSEEN_VALUE_MARKER: &str = "<unseen>";
+/// 例が属性を持たず、経路を辿れなかったことを表すマーカー
+pub const MISSING_VALUE_MARKER: &str = "<missing>";
+
+/// すべての例が同じクラスならそのクラスを返す（空なら None）
+pub fn is_pure(examples: &[Example]) -> Option<String> {
+    let first_class = &examples.first()?.class;
+    if examples.iter().all(|ex| &ex.class == first_class) {
+        Some(first_class.clone())
+    } else {
+        None
+    }
+}
+
+/// 多数決でクラスを決定する（空なら None）。
+/// 同数の場合は辞書順で最小のクラスを選ぶ
+pub fn majority_class(examples: &[Example]) -> Option<String> {
+    let mut class_counts: HashMap<&str, usize> = HashMap::new();
+
+    for ex in examples {
+        *class_counts.entry(ex.class.as_str()).or_insert(0) += 1;
+    }
+
+    class_counts
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+        .map(|(class, _)| class.to_string())
+}
+
+/// 各クラスの例の数を数える
+pub fn class_distribution(examples: &[Example]) -> HashMap<String, usize> {
+    let mut distribution = HashMap::new();
+    for ex in examples {
+        *distribution.entry(ex.class.clone()).or_insert(0) += 1;
+    }
+    distribution
+}
 
 /// 決定木のノード
 #[derive(Debug, Clone)]
@@ -8,45 +51,96 @@ pub enum Node {
     Internal {
         attribute: String,
         children: HashMap<String, Box<Node>>,
+        /// このノードに到達した学習例の数
+        n_samples: usize,
+        /// このノードに到達した学習例のクラス分布
+        distribution: HashMap<String, usize>,
     },
     /// リーフノード（決定結果を持つ）
     Leaf {
         decision: String,
+        n_samples: usize,
+        distribution: HashMap<String, usize>,
     },
 }
 
+impl Node {
+    /// このノードに到達した学習例の数
+    pub fn n_samples(&self) -> usize {
+        match self {
+            Node::Internal { n_samples, .. } | Node::Leaf { n_samples, .. } => *n_samples,
+        }
+    }
+
+    /// このノードに到達した学習例のクラス分布
+    pub fn distribution(&self) -> &HashMap<String, usize> {
+        match self {
+            Node::Internal { distribution, .. } | Node::Leaf { distribution, .. } => distribution,
+        }
+    }
+}
+
+// "no:2 yes:3" の形式（クラス名の辞書順）
+fn format_distribution(distribution: &HashMap<String, usize>) -> String {
+    let mut entries: Vec<(&String, &usize)> = distribution.iter().collect();
+    entries.sort();
+    entries
+        .iter()
+        .map(|(class, count)| format!("{}:{}", class, count))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 /// 決定木
 #[derive(Debug, Clone)]
 pub struct DecisionTree {
     root: Option<Box<Node>>,
+    /// Some(k) なら各分割で属性をランダムに k 個だけ候補にする (ランダム部分空間法)。
+    /// None なら全属性を調べる
+    max_features: Option<usize>,
+    seed: u64,
 }
 
 impl DecisionTree {
     /// 新しい空の決定木を作成
     pub fn new() -> Self {
-        DecisionTree { root: None }
+        DecisionTree { root: None, max_features: None, seed: 0 }
+    }
+
+    /// 各分割で seed から選んだ max_features 個の属性だけを候補にする決定木を作成
+    pub fn with_max_features(max_features: usize, seed: u64) -> Self {
+        DecisionTree { root: None, max_features: Some(max_features), seed }
     }
 
     /// 決定木を構築（ID3アルゴリズムの簡易版）
     pub fn build(&mut self, examples: &[Example], attributes: &[String]) {
-        self.root = Some(self.build_tree(examples, attributes));
+        let mut rng = SplitMix64::new(self.seed);
+        self.root = Some(self.build_tree(examples, attributes, &mut rng));
     }
 
     /// 再帰的に決定木を構築
-    fn build_tree(&self, examples: &[Example], attributes: &[String]) -> Box<Node> {
+    fn build_tree(&self, examples: &[Example], attributes: &[String], rng: &mut SplitMix64) -> Box<Node> {
         // すべての例が同じクラスなら、リーフノードを作成
-        if let Some(decision) = self.all_same_class(examples) {
-            return Box::new(Node::Leaf { decision });
+        if let Some(decision) = is_pure(examples) {
+            return Box::new(Node::Leaf {
+                decision,
+                n_samples: examples.len(),
+                distribution: class_distribution(examples),
+            });
         }
 
         // 属性がなければ、多数決でリーフノードを作成
         if attributes.is_empty() {
-            let decision = self.majority_class(examples);
-            return Box::new(Node::Leaf { decision });
+            let decision = majority_class(examples).unwrap_or_else(|| "unknown".to_string());
+            return Box::new(Node::Leaf {
+                decision,
+                n_samples: examples.len(),
+                distribution: class_distribution(examples),
+            });
         }
 
         // 最良の属性を選択
-        let best_attr = self.select_best_attribute(examples, attributes);
+        let best_attr = self.select_best_attribute(examples, attributes, rng);
         
         // 選択された属性で分割
         let mut children = HashMap::new();
@@ -68,57 +162,35 @@ impl DecisionTree {
 
             if subset.is_empty() {
                 // サブセットが空なら、多数決でリーフノードを作成
-                let decision = self.majority_class(examples);
-                children.insert(value, Box::new(Node::Leaf { decision }));
+                let decision = majority_class(examples).unwrap_or_else(|| "unknown".to_string());
+                children.insert(value, Box::new(Node::Leaf {
+                    decision,
+                    n_samples: 0,
+                    distribution: HashMap::new(),
+                }));
             } else {
                 // 再帰的にサブツリーを構築
-                children.insert(value, self.build_tree(&subset, &remaining_attrs));
+                children.insert(value, self.build_tree(&subset, &remaining_attrs, rng));
             }
         }
 
         Box::new(Node::Internal {
             attribute: best_attr,
             children,
+            n_samples: examples.len(),
+            distribution: class_distribution(examples),
         })
     }
 
-    /// すべての例が同じクラスかチェック
-    fn all_same_class(&self, examples: &[Example]) -> Option<String> {
-        if examples.is_empty() {
-            return None;
-        }
-
-        let first_class = &examples[0].class;
-        if examples.iter().all(|ex| &ex.class == first_class) {
-            Some(first_class.clone())
-        } else {
-            None
-        }
-    }
-
-    /// 多数決でクラスを決定
-    fn majority_class(&self, examples: &[Example]) -> String {
-        let mut class_counts: HashMap<String, usize> = HashMap::new();
-        
-        for ex in examples {
-            *class_counts.entry(ex.class.clone()).or_insert(0) += 1;
-        }
-
-        class_counts
-            .into_iter()
-            .max_by_key(|(_, count)| *count)
-            .map(|(class, _)| class)
-            .unwrap_or_else(|| "unknown".to_string())
-    }
-
     /// 最良の属性を選択（情報利得が最大の属性）
-    fn select_best_attribute(&self, examples: &[Example], attributes: &[String]) -> String {
-        let mut best_attr = attributes[0].clone();
+    fn select_best_attribute(&self, examples: &[Example], attributes: &[String], rng: &mut SplitMix64) -> String {
+        let candidates = self.candidate_attributes(attributes, rng);
+        let mut best_attr = candidates[0].clone();
         let mut best_gain = 0.0;
 
         let base_entropy = self.entropy(examples);
 
-        for attr in attributes {
+        for attr in &candidates {
             let gain = self.information_gain(examples, attr, base_entropy);
             if gain > best_gain {
                 best_gain = gain;
@@ -129,6 +201,20 @@ impl DecisionTree {
         best_attr
     }
 
+    /// max_features が指定されていれば、attributes から重複なくランダムに選ぶ
+    fn candidate_attributes(&self, attributes: &[String], rng: &mut SplitMix64) -> Vec<String> {
+        let mut candidates = attributes.to_vec();
+        let Some(k) = self.max_features else { return candidates };
+        let k = k.clamp(1, candidates.len());
+        // Fisher-Yates を先頭 k 個だけ行う
+        for i in 0..k {
+            let j = i + rng.below((candidates.len() - i) as u64) as usize;
+            candidates.swap(i, j);
+        }
+        candidates.truncate(k);
+        candidates
+    }
+
     /// エントロピーを計算
     fn entropy(&self, examples: &[Example]) -> f64 {
         if examples.is_empty() {
@@ -157,9 +243,9 @@ impl DecisionTree {
     /// 情報利得を計算
     fn information_gain(&self, examples: &[Example], attribute: &str, base_entropy: f64) -> f64 {
         let attribute_values = self.get_attribute_values(examples, attribute);
-        let total = examples.len() as f64;
 
-        let mut weighted_entropy = 0.0;
+        let mut subset_entropies = Vec::with_capacity(attribute_values.len());
+        let mut subset_sizes = Vec::with_capacity(attribute_values.len());
 
         for value in attribute_values {
             let subset: Vec<Example> = examples
@@ -168,14 +254,18 @@ impl DecisionTree {
                 .cloned()
                 .collect();
 
-            if !subset.is_empty() {
-                let subset_entropy = self.entropy(&subset);
-                let subset_size = subset.len() as f64;
-                weighted_entropy += (subset_size / total) * subset_entropy;
-            }
+            subset_entropies.push(self.entropy(&subset));
+            subset_sizes.push(subset.len());
+        }
+
+        // 属性を持たない例も全体の重みに含める
+        let missing = examples.len() - subset_sizes.iter().sum::<usize>();
+        if missing > 0 {
+            subset_entropies.push(0.0);
+            subset_sizes.push(missing);
         }
 
-        base_entropy - weighted_entropy
+        cal_shannon_entropy::information_gain(base_entropy, &subset_entropies, &subset_sizes)
     }
 
     /// 属性の値のリストを取得
@@ -195,11 +285,16 @@ impl DecisionTree {
         self.root.as_ref().map(|root| self.predict_recursive(root, example))
     }
 
+    /// 複数の例をまとめて予測する（結果は examples と同じ順）
+    pub fn predict_batch(&self, examples: &[Example]) -> Vec<Option<String>> {
+        examples.iter().map(|ex| self.predict(ex)).collect()
+    }
+
     /// 再帰的に予測を実行
     fn predict_recursive(&self, node: &Node, example: &Example) -> String {
         match node {
-            Node::Leaf { decision } => decision.clone(),
-            Node::Internal { attribute, children } => {
+            Node::Leaf { decision, .. } => decision.clone(),
+            Node::Internal { attribute, children, .. } => {
                 if let Some(value) = example.get_attribute_value(attribute) {
                     if let Some(child) = children.get(value) {
                         self.predict_recursive(child, example)
@@ -233,6 +328,39 @@ impl DecisionTree {
         }
     }
 
+    /// 予測時に辿った (属性, 値) の経路を根から順に返す
+    pub fn decision_path(&self, example: &Example) -> Vec<(String, String)> {
+        let mut path = Vec::new();
+        let mut node = match self.root.as_ref() {
+            Some(root) => root,
+            None => return path,
+        };
+
+        while let Node::Internal { attribute, children, .. } = node.as_ref() {
+            match example.get_attribute_value(attribute) {
+                Some(value) => {
+                    if let Some(child) = children.get(value) {
+                        path.push((attribute.clone(), value.clone()));
+                        node = child;
+                    } else {
+                        // predict と同様に最初の子ノードへ進む
+                        path.push((attribute.clone(), UNSEEN_VALUE_MARKER.to_string()));
+                        match children.values().next() {
+                            Some(child) => node = child,
+                            None => break,
+                        }
+                    }
+                }
+                None => {
+                    // predict は全ての子ノードの多数決になるので、ここで打ち切る
+                    path.push((attribute.clone(), MISSING_VALUE_MARKER.to_string()));
+                    break;
+                }
+            }
+        }
+        path
+    }
+
     /// 決定木を表示（デバッグ用）
     pub fn print(&self) {
         if let Some(ref root) = self.root {
@@ -243,11 +371,17 @@ impl DecisionTree {
     fn print_recursive(&self, node: &Node, depth: usize) {
         let indent = "  ".repeat(depth);
         match node {
-            Node::Leaf { decision } => {
-                println!("{}Leaf: {}", indent, decision);
+            Node::Leaf { decision, n_samples, distribution } => {
+                println!(
+                    "{}Leaf: {} (n={}, {})",
+                    indent, decision, n_samples, format_distribution(distribution)
+                );
             }
-            Node::Internal { attribute, children } => {
-                println!("{}Attribute: {}", indent, attribute);
+            Node::Internal { attribute, children, n_samples, distribution } => {
+                println!(
+                    "{}Attribute: {} (n={}, {})",
+                    indent, attribute, n_samples, format_distribution(distribution)
+                );
                 for (value, child) in children {
                     println!("{}  Value: {}", indent, value);
                     self.print_recursive(child, depth + 2);
@@ -255,6 +389,70 @@ impl DecisionTree {
             }
         }
     }
+
+    /// 根から葉までの経路を `IF outlook = sunny AND humidity = high THEN no` の形式で返す（葉 1 つにつき 1 行）
+    pub fn to_rules_text(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(ref root) = self.root {
+            Self::rules_recursive(root, &mut Vec::new(), &mut lines);
+        }
+        lines
+    }
+
+    fn rules_recursive(node: &Node, path: &mut Vec<String>, lines: &mut Vec<String>) {
+        match node {
+            Node::Leaf { decision, .. } => {
+                let premise = if path.is_empty() { "TRUE".to_string() } else { path.join(" AND ") };
+                lines.push(format!("IF {} THEN {}", premise, decision));
+            }
+            Node::Internal { attribute, children, .. } => {
+                let mut values: Vec<&String> = children.keys().collect();
+                values.sort();
+                for value in values {
+                    path.push(format!("{} = {}", attribute, value));
+                    Self::rules_recursive(&children[value], path, lines);
+                    path.pop();
+                }
+            }
+        }
+    }
+
+    /// 決定木を Graphviz の DOT 形式で出力する
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph DecisionTree {\n");
+        if let Some(ref root) = self.root {
+            let mut next_id = 0;
+            Self::dot_recursive(root, &mut next_id, &mut out);
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn dot_recursive(node: &Node, next_id: &mut usize, out: &mut String) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+        match node {
+            Node::Leaf { decision, n_samples, distribution } => {
+                out.push_str(&format!(
+                    "  n{} [shape=box, label=\"{}\\nn={}\\n{}\"];\n",
+                    id, decision, n_samples, format_distribution(distribution)
+                ));
+            }
+            Node::Internal { attribute, children, n_samples, distribution } => {
+                out.push_str(&format!(
+                    "  n{} [label=\"{}\\nn={}\\n{}\"];\n",
+                    id, attribute, n_samples, format_distribution(distribution)
+                ));
+                let mut values: Vec<&String> = children.keys().collect();
+                values.sort();
+                for value in values {
+                    let child_id = Self::dot_recursive(&children[value], next_id, out);
+                    out.push_str(&format!("  n{} -> n{} [label=\"{}\"];\n", id, child_id, value));
+                }
+            }
+        }
+        id
+    }
 }
 
 impl Default for DecisionTree {
@@ -263,6 +461,47 @@ impl Default for DecisionTree {
     }
 }
 
+/// ブートストラップ標本で学習した決定木の集まり。予測は多数決
+#[derive(Debug, Clone)]
+pub struct RandomForest {
+    trees: Vec<DecisionTree>,
+}
+
+impl RandomForest {
+    /// n_trees 本の木を、それぞれ復元抽出した examples と
+    /// max_features = ceil(sqrt(属性数)) で学習する
+    pub fn fit(examples: &[Example], attributes: &[String], n_trees: usize, seed: u64) -> Self {
+        let mut rng = SplitMix64::new(seed);
+        let max_features = (attributes.len() as f64).sqrt().ceil() as usize;
+        let mut trees = Vec::with_capacity(n_trees);
+        for _ in 0..n_trees {
+            let sample: Vec<Example> = (0..examples.len())
+                .map(|_| examples[rng.below(examples.len() as u64) as usize].clone())
+                .collect();
+            let mut tree = DecisionTree::with_max_features(max_features, rng.next_u64());
+            tree.build(&sample, attributes);
+            trees.push(tree);
+        }
+        RandomForest { trees }
+    }
+
+    /// 各木の予測の多数決（同数なら辞書順で小さいクラス）。木が無ければ None
+    pub fn predict(&self, example: &Example) -> Option<String> {
+        let mut votes: HashMap<String, usize> = HashMap::new();
+        for class in self.trees.iter().filter_map(|t| t.predict(example)) {
+            *votes.entry(class).or_insert(0) += 1;
+        }
+        votes
+            .into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(&a.0)))
+            .map(|(class, _)| class)
+    }
+
+    pub fn n_trees(&self) -> usize {
+        self.trees.len()
+    }
+}
+
 /// 学習用の例（サンプルデータ）
 #[derive(Debug, Clone)]
 pub struct Example {
@@ -279,6 +518,11 @@ impl Example {
         }
     }
 
+    /// 属性の表からまとめて作成
+    pub fn from_map(attributes: HashMap<String, String>, class: String) -> Self {
+        Example { attributes, class }
+    }
+
     /// 属性を追加
     pub fn add_attribute(&mut self, key: String, value: String) {
         self.attributes.insert(key, value);
@@ -290,12 +534,89 @@ impl Example {
     }
 }
 
+/// 予測が正解クラスと一致した割合（空なら 0.0）
+pub fn accuracy(tree: &DecisionTree, examples: &[Example]) -> f64 {
+    if examples.is_empty() {
+        return 0.0;
+    }
+    let correct = tree
+        .predict_batch(examples)
+        .iter()
+        .zip(examples)
+        .filter(|(predicted, ex)| predicted.as_deref() == Some(ex.class.as_str()))
+        .count();
+    correct as f64 / examples.len() as f64
+}
+
+/// seed でシャッフルした k 分割交差検証の平均正解率。
+/// k が例の数より大きければ例の数 (leave-one-out) に、2 未満なら 2 に丸める。
+/// 例が 2 つ未満で分割できないときは 0.0。
+pub fn cross_validate(examples: &[Example], attributes: &[String], k: usize, seed: u64) -> f64 {
+    let n = examples.len();
+    if n < 2 {
+        return 0.0;
+    }
+    let k = k.clamp(2, n);
+
+    // Fisher-Yates
+    let mut order: Vec<usize> = (0..n).collect();
+    let mut rng = SplitMix64::new(seed);
+    for i in (1..n).rev() {
+        let j = rng.below(i as u64 + 1) as usize;
+        order.swap(i, j);
+    }
+
+    let mut total = 0.0;
+    for fold in 0..k {
+        let mut train = Vec::new();
+        let mut test = Vec::new();
+        for (pos, &idx) in order.iter().enumerate() {
+            if pos % k == fold {
+                test.push(examples[idx].clone());
+            } else {
+                train.push(examples[idx].clone());
+            }
+        }
+        let mut tree = DecisionTree::new();
+        tree.build(&train, attributes);
+        total += accuracy(&tree, &test);
+    }
+    total / k as f64
+}
+
+/// ヘッダ付き CSV から例を読み込む。class_column の列がクラス、それ以外は属性になる。
+/// 空のセルは属性なし (欠損) として扱う。
+pub fn load_examples_csv<R: Read>(reader: R, class_column: &str) -> Result<Vec<Example>, String> {
+    let table = read_csv_table(reader)?;
+    let header = table.header;
+    if header.is_empty() {
+        return Ok(Vec::new());
+    }
+    let class_col = header
+        .iter()
+        .position(|h| h == class_column)
+        .ok_or_else(|| format!("CSV header must contain a '{}' column", class_column))?;
+
+    let mut examples = Vec::new();
+    for (_, mut cells) in table.rows {
+        let class = std::mem::take(&mut cells[class_col]);
+        let attributes = header
+            .iter()
+            .zip(cells)
+            .enumerate()
+            .filter(|(i, (_, cell))| *i != class_col && !cell.is_empty())
+            .map(|(_, (h, cell))| (h.clone(), cell))
+            .collect();
+        examples.push(Example::from_map(attributes, class));
+    }
+    Ok(examples)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_decision_tree() {
+    fn tennis_examples() -> Vec<Example> {
         // サンプルデータを作成
         let mut examples = Vec::new();
         
@@ -331,6 +652,13 @@ mod tests {
         ex4.add_attribute("wind".to_string(), "weak".to_string());
         examples.push(ex4);
 
+        examples
+    }
+
+    #[test]
+    fn test_decision_tree() {
+        let examples = tennis_examples();
+
         // 決定木を構築
         let mut tree = DecisionTree::new();
         let attributes = vec![
@@ -354,5 +682,218 @@ mod tests {
         // 決定木を表示
         tree.print();
     }
-}
 
+    fn tennis_tree() -> DecisionTree {
+        let mut tree = DecisionTree::new();
+        let attributes = vec![
+            "outlook".to_string(),
+            "temperature".to_string(),
+            "humidity".to_string(),
+            "wind".to_string(),
+        ];
+        tree.build(&tennis_examples(), &attributes);
+        tree
+    }
+
+    #[test]
+    fn test_decision_path() {
+        let tree = tennis_tree();
+
+        let mut ex = Example::new("unknown".to_string());
+        ex.add_attribute("outlook".to_string(), "sunny".to_string());
+        ex.add_attribute("wind".to_string(), "weak".to_string());
+        assert_eq!(
+            tree.decision_path(&ex),
+            vec![("outlook".to_string(), "sunny".to_string())]
+        );
+
+        let mut unseen = Example::new("unknown".to_string());
+        unseen.add_attribute("outlook".to_string(), "snow".to_string());
+        assert_eq!(
+            tree.decision_path(&unseen),
+            vec![("outlook".to_string(), UNSEEN_VALUE_MARKER.to_string())]
+        );
+
+        let missing = Example::new("unknown".to_string());
+        assert_eq!(
+            tree.decision_path(&missing),
+            vec![("outlook".to_string(), MISSING_VALUE_MARKER.to_string())]
+        );
+    }
+
+    #[test]
+    fn test_root_attribute_uses_shared_information_gain() {
+        let tree = tennis_tree();
+        match tree.root.as_deref() {
+            Some(Node::Internal { attribute, .. }) => assert_eq!(attribute, "outlook"),
+            other => panic!("unexpected root: {:?}", other),
+        }
+
+        let examples = tennis_examples();
+        let base = tree.entropy(&examples);
+        assert!((tree.information_gain(&examples, "outlook", base) - 1.0).abs() < 1e-12);
+        assert!(tree.information_gain(&examples, "humidity", base).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_majority_class_and_is_pure() {
+        assert_eq!(majority_class(&[]), None);
+        assert_eq!(is_pure(&[]), None);
+
+        let pure = vec![Example::new("yes".to_string()), Example::new("yes".to_string())];
+        assert_eq!(is_pure(&pure), Some("yes".to_string()));
+        assert_eq!(majority_class(&pure), Some("yes".to_string()));
+
+        let examples = tennis_examples();
+        assert_eq!(is_pure(&examples), None);
+        // 2対2の同数は辞書順で小さい方
+        assert_eq!(majority_class(&examples), Some("no".to_string()));
+    }
+
+    fn leaf_sample_total(node: &Node) -> usize {
+        match node {
+            Node::Leaf { n_samples, .. } => *n_samples,
+            Node::Internal { children, .. } => children.values().map(|c| leaf_sample_total(c)).sum(),
+        }
+    }
+
+    #[test]
+    fn test_node_sample_counts() {
+        let tree = tennis_tree();
+        let root = tree.root.as_deref().unwrap();
+        let examples = tennis_examples();
+
+        assert_eq!(root.n_samples(), examples.len());
+        assert_eq!(root.distribution(), &class_distribution(&examples));
+        assert_eq!(leaf_sample_total(root), examples.len());
+
+        let dot = tree.to_dot();
+        assert!(dot.starts_with("digraph DecisionTree {"));
+        assert!(dot.contains(&format!("outlook\\nn={}", examples.len())));
+    }
+
+    #[test]
+    fn test_predict_batch_matches_predict() {
+        let tree = tennis_tree();
+        let examples = tennis_examples();
+
+        let batch = tree.predict_batch(&examples);
+        let single: Vec<Option<String>> = examples.iter().map(|ex| tree.predict(ex)).collect();
+        assert_eq!(batch, single);
+        assert!(DecisionTree::new().predict_batch(&examples).iter().all(|p| p.is_none()));
+    }
+
+    fn leaf_count(node: &Node) -> usize {
+        match node {
+            Node::Leaf { .. } => 1,
+            Node::Internal { children, .. } => children.values().map(|c| leaf_count(c)).sum(),
+        }
+    }
+
+    #[test]
+    fn test_to_rules_text_has_one_line_per_leaf() {
+        let tree = tennis_tree();
+        let rules = tree.to_rules_text();
+
+        assert_eq!(rules.len(), leaf_count(tree.root.as_deref().unwrap()));
+        assert!(rules.iter().all(|r| r.starts_with("IF outlook = ")));
+        assert!(rules.contains(&"IF outlook = sunny THEN no".to_string()));
+        assert!(DecisionTree::new().to_rules_text().is_empty());
+    }
+
+    #[test]
+    fn test_load_examples_csv_builds_tree() {
+        let csv = "outlook,wind,play\n\
+                   sunny,weak,no\n\
+                   sunny,strong,no\n\
+                   overcast,weak,yes\n\
+                   rain,,yes\n";
+        let examples = load_examples_csv(csv.as_bytes(), "play").unwrap();
+        assert_eq!(examples.len(), 4);
+        assert_eq!(examples[0].class, "no");
+        assert_eq!(examples[0].get_attribute_value("outlook"), Some(&"sunny".to_string()));
+        assert_eq!(examples[3].get_attribute_value("wind"), None);
+
+        let mut tree = DecisionTree::new();
+        tree.build(&examples, &["outlook".to_string(), "wind".to_string()]);
+        let ex = Example::from_map(
+            HashMap::from([("outlook".to_string(), "overcast".to_string())]),
+            "unknown".to_string(),
+        );
+        assert_eq!(tree.predict(&ex), Some("yes".to_string()));
+
+        assert!(load_examples_csv(csv.as_bytes(), "label").is_err());
+    }
+
+    #[test]
+    fn test_cross_validate_on_tennis() {
+        let examples = tennis_examples();
+        let attributes = vec![
+            "outlook".to_string(),
+            "temperature".to_string(),
+            "humidity".to_string(),
+            "wind".to_string(),
+        ];
+        let acc = cross_validate(&examples, &attributes, 2, 7);
+        assert!((0.0..=1.0).contains(&acc));
+        assert_eq!(acc, cross_validate(&examples, &attributes, 2, 7));
+
+        // k が例の数を超えたら leave-one-out
+        let loo = cross_validate(&examples, &attributes, 100, 7);
+        assert!((0.0..=1.0).contains(&loo));
+        assert_eq!(cross_validate(&examples[..1], &attributes, 3, 7), 0.0);
+
+        assert_eq!(accuracy(&tennis_tree(), &examples), 1.0);
+    }
+
+    #[test]
+    fn test_max_features_is_reproducible() {
+        let attributes = vec![
+            "outlook".to_string(),
+            "temperature".to_string(),
+            "humidity".to_string(),
+            "wind".to_string(),
+        ];
+        let rules_for = |seed: u64| {
+            let mut tree = DecisionTree::with_max_features(1, seed);
+            tree.build(&tennis_examples(), &attributes);
+            let mut rules = tree.to_rules_text();
+            rules.sort();
+            rules
+        };
+        assert_eq!(rules_for(3), rules_for(3));
+
+        let mut full = tennis_tree().to_rules_text();
+        full.sort();
+        assert!((0..16).any(|seed| rules_for(seed) != full));
+    }
+
+    #[test]
+    fn test_random_forest_votes_deterministically() {
+        let examples = tennis_examples();
+        let attributes = vec![
+            "outlook".to_string(),
+            "temperature".to_string(),
+            "humidity".to_string(),
+            "wind".to_string(),
+        ];
+        let forest = RandomForest::fit(&examples, &attributes, 15, 42);
+        assert_eq!(forest.n_trees(), 15);
+
+        let correct = examples
+            .iter()
+            .filter(|ex| forest.predict(ex).as_deref() == Some(ex.class.as_str()))
+            .count();
+        let forest_accuracy = correct as f64 / examples.len() as f64;
+        assert!(forest_accuracy >= accuracy(&tennis_tree(), &examples));
+
+        let again = RandomForest::fit(&examples, &attributes, 15, 42);
+        let mut probe = Example::new("unknown".to_string());
+        probe.add_attribute("outlook".to_string(), "sunny".to_string());
+        probe.add_attribute("wind".to_string(), "weak".to_string());
+        assert_eq!(forest.predict(&probe), again.predict(&probe));
+        for ex in &examples {
+            assert_eq!(forest.predict(ex), again.predict(ex));
+        }
+    }
+}
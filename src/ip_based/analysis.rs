@@ -0,0 +1,345 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::ip_based::entity::{AttributeValue, DestinationEntity, SourceEntity};
+use crate::ip_based::evaluator::{Decision, evaluate_policy, evaluate_policy_explained, matching_rules};
+use crate::ip_based::rule::{CombiningAlgorithm, Effect, Policy};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CoverageReport {
+    pub explicit_allow: usize,
+    pub explicit_deny: usize,
+    pub default_fallthrough: usize,
+}
+
+impl CoverageReport {
+    pub fn total(&self) -> usize {
+        self.explicit_allow + self.explicit_deny + self.default_fallthrough
+    }
+}
+
+pub fn coverage(
+    policy: &Policy,
+    sources: &[SourceEntity],
+    destinations: &[DestinationEntity],
+    env: &HashMap<String, AttributeValue>,
+) -> Result<CoverageReport, String> {
+    let mut report = CoverageReport::default();
+    for src in sources {
+        for dst in destinations {
+            let decision = evaluate_policy_explained(policy, src, dst, env)?;
+            match (decision.is_default(), &decision.effect) {
+                (true, _) => report.default_fallthrough += 1,
+                (false, Effect::Allow) => report.explicit_allow += 1,
+                (false, Effect::Deny) => report.explicit_deny += 1,
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// 全ての (送信元IP, 宛先IP) の組について effect を評価する
+pub fn evaluate_matrix(
+    policy: &Policy,
+    sources: &[SourceEntity],
+    destinations: &[DestinationEntity],
+    env: &HashMap<String, AttributeValue>,
+) -> Result<Vec<(String, String, Effect)>, String> {
+    let mut rows = Vec::with_capacity(sources.len() * destinations.len());
+    for src in sources {
+        for dst in destinations {
+            let effect = evaluate_policy(policy, src, dst, env)?;
+            rows.push((src.ip.clone(), dst.ip.clone(), effect));
+        }
+    }
+    Ok(rows)
+}
+
+/// evaluate_matrix の結果の集計
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EffectStats {
+    pub allow: usize,
+    pub deny: usize,
+    /// source IP ごとの allow の割合
+    pub source_allow_ratio: BTreeMap<String, f64>,
+    /// destination IP ごとの allow の割合
+    pub destination_allow_ratio: BTreeMap<String, f64>,
+}
+
+pub fn summarize_matrix(rows: &[(String, String, Effect)]) -> EffectStats {
+    // (allow 数, 全体数)
+    let mut per_source: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    let mut per_destination: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    let mut stats = EffectStats::default();
+
+    for (src, dst, effect) in rows {
+        let allowed = *effect == Effect::Allow;
+        if allowed {
+            stats.allow += 1;
+        } else {
+            stats.deny += 1;
+        }
+        for counts in [
+            per_source.entry(src.as_str()).or_default(),
+            per_destination.entry(dst.as_str()).or_default(),
+        ] {
+            counts.0 += allowed as usize;
+            counts.1 += 1;
+        }
+    }
+
+    let ratio = |(allowed, total): (usize, usize)| allowed as f64 / total as f64;
+    stats.source_allow_ratio = per_source.into_iter().map(|(ip, c)| (ip.to_string(), ratio(c))).collect();
+    stats.destination_allow_ratio = per_destination.into_iter().map(|(ip, c)| (ip.to_string(), ratio(c))).collect();
+    stats
+}
+
+/// 複数の環境 (時間帯など) それぞれで行列を評価し、環境のインデックスを付けて返す
+pub fn evaluate_matrix_over_envs(
+    policy: &Policy,
+    sources: &[SourceEntity],
+    destinations: &[DestinationEntity],
+    envs: &[HashMap<String, AttributeValue>],
+) -> Result<Vec<(usize, String, String, Effect)>, String> {
+    let mut rows = Vec::new();
+    for (i, env) in envs.iter().enumerate() {
+        for (src_ip, dst_ip, effect) in evaluate_matrix(policy, sources, destinations, env)? {
+            rows.push((i, src_ip, dst_ip, effect));
+        }
+    }
+    Ok(rows)
+}
+
+/// Allow と Deny のルールが同時にマッチする (送信元IP, 宛先IP, ルールID) を列挙する
+pub fn find_conflicts(
+    policy: &Policy,
+    sources: &[SourceEntity],
+    destinations: &[DestinationEntity],
+    env: &HashMap<String, AttributeValue>,
+) -> Result<Vec<(String, String, Vec<String>)>, String> {
+    let mut conflicts = Vec::new();
+    for src in sources {
+        for dst in destinations {
+            let matched = matching_rules(policy, src, dst, env)?;
+            let has_allow = matched.iter().any(|r| r.effect == Effect::Allow);
+            let has_deny = matched.iter().any(|r| r.effect == Effect::Deny);
+            if has_allow && has_deny {
+                let ids = matched.iter().map(|r| r.id.clone()).collect();
+                conflicts.push((src.ip.clone(), dst.ip.clone(), ids));
+            }
+        }
+    }
+    Ok(conflicts)
+}
+
+/// 記録されたリクエスト (送信元, 宛先, 環境) を順に policy で評価し直す。
+/// 新しいポリシー案が過去のアクセスにどう判定したかを確かめる監査用
+pub fn replay(
+    policy: &Policy,
+    requests: &[(SourceEntity, DestinationEntity, HashMap<String, AttributeValue>)],
+) -> Result<Vec<Decision>, String> {
+    requests
+        .iter()
+        .map(|(src, dst, env)| evaluate_policy_explained(policy, src, dst, env))
+        .collect()
+}
+
+/// 各ルールの matches を全ペアで評価した合計時間を、遅い順に返す。
+/// 評価中のエラーはそのまま返す
+pub fn profile_policy(
+    policy: &Policy,
+    sources: &[SourceEntity],
+    destinations: &[DestinationEntity],
+    env: &HashMap<String, AttributeValue>,
+) -> Result<Vec<(String, Duration)>, String> {
+    let mut totals: Vec<(String, Duration)> = Vec::with_capacity(policy.rules.len());
+    for rule in &policy.rules {
+        let mut total = Duration::ZERO;
+        for src in sources {
+            for dst in destinations {
+                let start = Instant::now();
+                rule.matches(src, dst, env)?;
+                total += start.elapsed();
+            }
+        }
+        totals.push((rule.id.clone(), total));
+    }
+    totals.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(totals)
+}
+
+/// 全ルールの effect と default_effect を反転したポリシーを返す（条件はそのまま）。
+/// deny-overrides と permit-overrides も入れ替えるので、どの要求に対しても判定がちょうど反転する。
+/// 元のポリシーの補集合を調べたり、否定側のテストケースを作ったりするのに使う
+pub fn invert_effects(policy: &Policy) -> Policy {
+    let mut inverted = policy.clone();
+    inverted.default_effect = policy.default_effect.inverted();
+    inverted.combining_algorithm = match policy.combining_algorithm {
+        CombiningAlgorithm::FirstApplicable => CombiningAlgorithm::FirstApplicable,
+        CombiningAlgorithm::DenyOverrides => CombiningAlgorithm::PermitOverrides,
+        CombiningAlgorithm::PermitOverrides => CombiningAlgorithm::DenyOverrides,
+    };
+    for rule in &mut inverted.rules {
+        rule.effect = rule.effect.inverted();
+    }
+    inverted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn source(ip: &str, role: &str) -> SourceEntity {
+        SourceEntity::from_json_value(&json!({ "ip": ip, "attributes": { "Src.Role": role } })).unwrap()
+    }
+
+    fn destination(ip: &str, ty: &str) -> DestinationEntity {
+        DestinationEntity::from_json_value(&json!({ "ip": ip, "attributes": { "Dst.Type": ty } })).unwrap()
+    }
+
+    fn sample_policy() -> Policy {
+        Policy::from_json_value(&json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": "deny",
+            "rules": [
+                { "id": "deny_guest", "effect": "deny", "condition": { "operator": "EQ", "lhs": "Src.Role", "rhs": "Guest" } },
+                { "id": "allow_web", "effect": "allow", "condition": { "operator": "EQ", "lhs": "Dst.Type", "rhs": "WebServer" } }
+            ]
+        })).unwrap()
+    }
+
+    #[test]
+    fn coverage_counts_sum_to_pair_count() {
+        let sources = vec![source("s1", "Guest"), source("s2", "Student"), source("s3", "Admin")];
+        let destinations = vec![destination("d1", "WebServer"), destination("d2", "Database")];
+
+        let report = coverage(&sample_policy(), &sources, &destinations, &HashMap::new()).unwrap();
+        assert_eq!(report, CoverageReport { explicit_allow: 2, explicit_deny: 2, default_fallthrough: 2 });
+        assert_eq!(report.total(), sources.len() * destinations.len());
+    }
+
+    #[test]
+    fn find_conflicts_reports_pairs_matched_by_both_effects() {
+        let sources = vec![source("s1", "Guest"), source("s2", "Student")];
+        let destinations = vec![destination("d1", "WebServer"), destination("d2", "Database")];
+
+        let conflicts = find_conflicts(&sample_policy(), &sources, &destinations, &HashMap::new()).unwrap();
+        assert_eq!(conflicts, vec![(
+            "s1".to_string(),
+            "d1".to_string(),
+            vec!["deny_guest".to_string(), "allow_web".to_string()],
+        )]);
+    }
+
+    #[test]
+    fn matrix_over_envs_tracks_time_window_changes() {
+        let policy = Policy::from_json_value(&json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": "deny",
+            "rules": [
+                {
+                    "id": "office_hours",
+                    "effect": "allow",
+                    "condition": {
+                        "operator": "AND",
+                        "operands": [
+                            { "operator": "GTE", "lhs": "Env.Hour", "rhs": 9 },
+                            { "operator": "LT", "lhs": "Env.Hour", "rhs": 18 }
+                        ]
+                    }
+                }
+            ]
+        })).unwrap();
+        let env_at = |hour: i64| {
+            let mut env = HashMap::new();
+            env.insert("Env.Hour".to_string(), AttributeValue::Number(hour));
+            env
+        };
+
+        let rows = evaluate_matrix_over_envs(
+            &policy,
+            &[source("s1", "Staff")],
+            &[destination("d1", "Database")],
+            &[env_at(10), env_at(22)],
+        ).unwrap();
+        assert_eq!(rows, vec![
+            (0, "s1".to_string(), "d1".to_string(), Effect::Allow),
+            (1, "s1".to_string(), "d1".to_string(), Effect::Deny),
+        ]);
+    }
+
+    #[test]
+    fn summarize_matrix_reports_allow_ratios() {
+        let row = |s: &str, d: &str, e: Effect| (s.to_string(), d.to_string(), e);
+        let rows = vec![
+            row("s1", "d1", Effect::Allow),
+            row("s1", "d2", Effect::Deny),
+            row("s2", "d1", Effect::Allow),
+            row("s2", "d2", Effect::Deny),
+            row("s3", "d1", Effect::Allow),
+            row("s3", "d2", Effect::Allow),
+        ];
+
+        let stats = summarize_matrix(&rows);
+        assert_eq!((stats.allow, stats.deny), (4, 2));
+        assert_eq!(stats.source_allow_ratio["s1"], 0.5);
+        assert_eq!(stats.source_allow_ratio["s3"], 1.0);
+        assert_eq!(stats.destination_allow_ratio["d1"], 1.0);
+        assert!((stats.destination_allow_ratio["d2"] - 1.0 / 3.0).abs() < 1e-12);
+        assert_eq!(summarize_matrix(&[]), EffectStats::default());
+    }
+
+    #[test]
+    fn inverted_policy_flips_every_explicit_decision() {
+        let policy = sample_policy();
+        let inverted = invert_effects(&policy);
+        assert_eq!(inverted.default_effect, Effect::Allow);
+        assert_eq!(inverted.rules.len(), policy.rules.len());
+
+        let env = HashMap::new();
+        for src in [source("s1", "Guest"), source("s2", "Student")] {
+            for dst in [destination("d1", "WebServer"), destination("d2", "Database")] {
+                let original = evaluate_policy_explained(&policy, &src, &dst, &env).unwrap();
+                let flipped = evaluate_policy_explained(&inverted, &src, &dst, &env).unwrap();
+                assert_eq!(flipped.matched_rule, original.matched_rule);
+                if !original.is_default() {
+                    assert_eq!(flipped.effect, original.effect.inverted());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn profile_policy_reports_every_rule() {
+        let sources = vec![source("s1", "Guest"), source("s2", "Student")];
+        let destinations = vec![destination("d1", "WebServer")];
+
+        let profile = profile_policy(&sample_policy(), &sources, &destinations, &HashMap::new()).unwrap();
+        let mut ids: Vec<&str> = profile.iter().map(|(id, _)| id.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["allow_web", "deny_guest"]);
+        assert!(profile.windows(2).all(|w| w[0].1 >= w[1].1));
+    }
+
+    #[test]
+    fn replay_reevaluates_each_logged_request() {
+        let log = vec![
+            (source("s1", "Guest"), destination("d1", "WebServer"), HashMap::new()),
+            (source("s2", "Student"), destination("d1", "WebServer"), HashMap::new()),
+            (source("s2", "Student"), destination("d2", "Database"), HashMap::new()),
+        ];
+        let decisions = replay(&sample_policy(), &log).unwrap();
+        let summary: Vec<(Effect, Option<&str>)> = decisions
+            .iter()
+            .map(|d| (d.effect.clone(), d.matched_rule.as_deref()))
+            .collect();
+        assert_eq!(summary, vec![
+            (Effect::Deny, Some("deny_guest")),
+            (Effect::Allow, Some("allow_web")),
+            (Effect::Deny, None),
+        ]);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::ip_based::entity::AttributeValue;
+use crate::util::rng::SplitMix64;
+use crate::ip_based::rule::{Condition, Effect, Expression};
+
+/// 型の整合した（評価時に型エラーにならない）ランダムな値を生成する。fuzz 用の単純な乱択で、
+/// proptest のような shrinking は行わない
+pub trait Arbitrary: Sized {
+    fn arbitrary(rng: &mut SplitMix64) -> Self;
+}
+
+/// And / Or のネストの上限
+const MAX_DEPTH: usize = 3;
+
+const STRING_ATTRS: &[&str] = &["Src.Role", "Src.Dept", "Dst.Type", "Dst.OwnerDept"];
+const NUMBER_ATTRS: &[&str] = &["Src.TrustScore", "Dst.Sensitivity"];
+const SET_ATTRS: &[&str] = &["Src.Groups", "Dst.AllowedVLANs"];
+const WORDS: &[&str] = &["Student", "Staff", "CS", "Bio", "Server", "Lab", "Guest_WiFi", "Faculty"];
+
+fn pick<'a>(rng: &mut SplitMix64, items: &[&'a str]) -> &'a str {
+    items[rng.below(items.len() as u64) as usize]
+}
+
+fn arbitrary_words(rng: &mut SplitMix64) -> Vec<String> {
+    let len = rng.below(4) as usize;
+    (0..len).map(|_| pick(rng, WORDS).to_string()).collect()
+}
+
+fn string_expression(rng: &mut SplitMix64) -> Expression {
+    match rng.below(2) {
+        0 => Expression::LiteralString(pick(rng, WORDS).to_string()),
+        _ => Expression::AttributeRef(pick(rng, STRING_ATTRS).to_string()),
+    }
+}
+
+fn set_expression(rng: &mut SplitMix64) -> Expression {
+    match rng.below(2) {
+        0 => {
+            let mut words = arbitrary_words(rng);
+            words.sort();
+            words.dedup();
+            Expression::LiteralSet(words)
+        }
+        _ => Expression::AttributeRef(pick(rng, SET_ATTRS).to_string()),
+    }
+}
+
+fn number_expression(rng: &mut SplitMix64, depth: usize) -> Expression {
+    let choice = if depth >= MAX_DEPTH { rng.below(3) } else { rng.below(5) };
+    match choice {
+        0 => Expression::LiteralNumber(rng.below(100) as i64),
+        1 => Expression::AttributeRef(pick(rng, NUMBER_ATTRS).to_string()),
+        2 => Expression::Count { operand: Box::new(set_expression(rng)) },
+        3 => Expression::Add {
+            operands: (0..2).map(|_| number_expression(rng, depth + 1)).collect(),
+        },
+        _ => Expression::Multiply {
+            operands: (0..2).map(|_| number_expression(rng, depth + 1)).collect(),
+        },
+    }
+}
+
+fn arbitrary_condition(rng: &mut SplitMix64, depth: usize) -> Condition {
+    let choice = if depth >= MAX_DEPTH { rng.below(7) } else { rng.below(9) };
+    match choice {
+        0 => Condition::Always,
+        1 => Condition::Never,
+        2 => Condition::Eq { lhs: string_expression(rng), rhs: string_expression(rng) },
+        3 => Condition::Gte { lhs: number_expression(rng, depth), rhs: number_expression(rng, depth) },
+        4 => Condition::Gt { lhs: number_expression(rng, depth), rhs: number_expression(rng, depth) },
+        5 => Condition::Lt { lhs: number_expression(rng, depth), rhs: number_expression(rng, depth) },
+        6 => Condition::In { target: string_expression(rng), check_against: set_expression(rng) },
+        7 => Condition::And {
+            operands: (0..1 + rng.below(3)).map(|_| arbitrary_condition(rng, depth + 1)).collect(),
+        },
+        _ => Condition::Or {
+            operands: (0..1 + rng.below(3)).map(|_| arbitrary_condition(rng, depth + 1)).collect(),
+        },
+    }
+}
+
+impl Arbitrary for Effect {
+    fn arbitrary(rng: &mut SplitMix64) -> Self {
+        if rng.below(2) == 0 { Effect::Allow } else { Effect::Deny }
+    }
+}
+
+impl Arbitrary for AttributeValue {
+    fn arbitrary(rng: &mut SplitMix64) -> Self {
+        match rng.below(4) {
+            0 => AttributeValue::String(pick(rng, WORDS).to_string()),
+            1 => AttributeValue::Number(rng.below(100) as i64),
+            2 => AttributeValue::Set(arbitrary_words(rng)),
+            _ => AttributeValue::Boolean(rng.below(2) == 0),
+        }
+    }
+}
+
+impl Arbitrary for Expression {
+    fn arbitrary(rng: &mut SplitMix64) -> Self {
+        match rng.below(3) {
+            0 => string_expression(rng),
+            1 => set_expression(rng),
+            _ => number_expression(rng, 0),
+        }
+    }
+}
+
+impl Arbitrary for Condition {
+    fn arbitrary(rng: &mut SplitMix64) -> Self {
+        arbitrary_condition(rng, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonicalize_is_idempotent() {
+        let mut rng = SplitMix64::new(2024);
+        for _ in 0..500 {
+            let condition = Condition::arbitrary(&mut rng);
+            let once = condition.canonicalize();
+            let twice = once.canonicalize();
+            assert_eq!(once, twice, "input: {:?}", condition);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::ip_based::rule::{Condition, Expression};
+
+// 並べ替えと重複除去に使う安定したキー
+fn sort_key<T: std::fmt::Debug>(item: &T) -> String {
+    format!("{:?}", item)
+}
+
+fn sort_and_dedup<T: std::fmt::Debug>(items: Vec<T>) -> Vec<T> {
+    let mut keyed: Vec<(String, T)> = items.into_iter().map(|i| (sort_key(&i), i)).collect();
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+    keyed.dedup_by(|a, b| a.0 == b.0);
+    keyed.into_iter().map(|(_, i)| i).collect()
+}
+
+impl Condition {
+    /// 比較用の正規形を返す。
+    ///
+    /// - 同じ演算子の入れ子になった AND / OR を平坦化する
+    /// - AND / OR の被演算子と EQ の両辺を安定したキーで並べ替え、重複を除く
+    /// - 被演算子が一つだけの AND / OR はその被演算子に置き換える
+    ///
+    /// 真偽値としての評価結果は変わらないが、被演算子の順序が変わるため
+    /// 評価エラーになる被演算子がある場合の短絡の仕方は変わり得る。
+    pub fn canonicalize(&self) -> Condition {
+        match self {
+            Condition::And { operands } => {
+                let mut flat = Vec::new();
+                for c in operands {
+                    match c.canonicalize() {
+                        Condition::And { operands } => flat.extend(operands),
+                        other => flat.push(other),
+                    }
+                }
+                let mut flat = sort_and_dedup(flat);
+                if flat.len() == 1 {
+                    return flat.remove(0);
+                }
+                Condition::And { operands: flat }
+            }
+            Condition::Or { operands } => {
+                let mut flat = Vec::new();
+                for c in operands {
+                    match c.canonicalize() {
+                        Condition::Or { operands } => flat.extend(operands),
+                        other => flat.push(other),
+                    }
+                }
+                let mut flat = sort_and_dedup(flat);
+                if flat.len() == 1 {
+                    return flat.remove(0);
+                }
+                Condition::Or { operands: flat }
+            }
+            Condition::Eq { lhs, rhs } => {
+                let (lhs, rhs) = (lhs.canonicalize(), rhs.canonicalize());
+                if sort_key(&rhs) < sort_key(&lhs) {
+                    Condition::Eq { lhs: rhs, rhs: lhs }
+                } else {
+                    Condition::Eq { lhs, rhs }
+                }
+            }
+            Condition::Gte { lhs, rhs } => Condition::Gte { lhs: lhs.canonicalize(), rhs: rhs.canonicalize() },
+            Condition::Gt { lhs, rhs } => Condition::Gt { lhs: lhs.canonicalize(), rhs: rhs.canonicalize() },
+            Condition::Lt { lhs, rhs } => Condition::Lt { lhs: lhs.canonicalize(), rhs: rhs.canonicalize() },
+            Condition::In { target, check_against } => Condition::In {
+                target: target.canonicalize(),
+                check_against: check_against.canonicalize(),
+            },
+            Condition::NotIn { target, check_against } => Condition::NotIn {
+                target: target.canonicalize(),
+                check_against: check_against.canonicalize(),
+            },
+            Condition::InSet { value, set } => Condition::InSet {
+                value: value.canonicalize(),
+                set: set.canonicalize(),
+            },
+            Condition::Always | Condition::Never => self.clone(),
+        }
+    }
+}
+
+impl Expression {
+    /// ADD / MULTIPLY の被演算子を並べ替えた正規形を返す（重複は意味が変わるので除かない）
+    pub fn canonicalize(&self) -> Expression {
+        match self {
+            Expression::Add { operands } => {
+                let mut ops: Vec<Expression> = operands.iter().map(|e| e.canonicalize()).collect();
+                ops.sort_by_key(sort_key);
+                Expression::Add { operands: ops }
+            }
+            Expression::Multiply { operands } => {
+                let mut ops: Vec<Expression> = operands.iter().map(|e| e.canonicalize()).collect();
+                ops.sort_by_key(sort_key);
+                Expression::Multiply { operands: ops }
+            }
+            Expression::Count { operand } => Expression::Count {
+                operand: Box::new(operand.canonicalize()),
+            },
+            Expression::If { cond, then, els } => Expression::If {
+                cond: Box::new(cond.canonicalize()),
+                then: Box::new(then.canonicalize()),
+                els: Box::new(els.canonicalize()),
+            },
+            _ => self.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(value: serde_json::Value) -> Condition {
+        Condition::from_json_value(&value).unwrap()
+    }
+
+    #[test]
+    fn equivalent_conditions_share_a_canonical_form() {
+        let a = parse(json!({
+            "operator": "AND",
+            "operands": [
+                { "operator": "EQ", "lhs": "Src.Dept", "rhs": "Dst.OwnerDept" },
+                {
+                    "operator": "AND",
+                    "operands": [
+                        { "operator": "GTE", "lhs": { "operator": "ADD", "operands": [5, "Src.TrustScore"] }, "rhs": 80 },
+                        { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" }
+                    ]
+                }
+            ]
+        }));
+        let b = parse(json!({
+            "operator": "AND",
+            "operands": [
+                { "operator": "EQ", "lhs": "Admin", "rhs": "Src.Role" },
+                { "operator": "GTE", "lhs": { "operator": "ADD", "operands": ["Src.TrustScore", 5] }, "rhs": 80 },
+                { "operator": "EQ", "lhs": "Dst.OwnerDept", "rhs": "Src.Dept" },
+                { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" }
+            ]
+        }));
+
+        assert_eq!(a.canonicalize(), b.canonicalize());
+        match a.canonicalize() {
+            Condition::And { operands } => assert_eq!(operands.len(), 3),
+            other => panic!("unexpected: {:?}", other),
+        }
+
+        // 集合リテラルの要素順・重複も正規形に影響しない
+        let in_ab = parse(json!({ "operator": "IN", "target": "Src.Role", "check_against": ["a", "b"] }));
+        let in_ba = parse(json!({ "operator": "IN", "target": "Src.Role", "check_against": ["b", "a", "b"] }));
+        assert_eq!(in_ab.canonicalize(), in_ba.canonicalize());
+    }
+
+    #[test]
+    fn single_operand_groups_collapse() {
+        let nested = parse(json!({
+            "operator": "OR",
+            "operands": [{ "operator": "OR", "operands": [{ "operator": "TRUE" }, { "operator": "TRUE" }] }]
+        }));
+        assert_eq!(nested.canonicalize(), Condition::Always);
+    }
+}
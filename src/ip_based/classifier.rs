@@ -3,7 +3,7 @@ use crate::ip_based::entity::{
     SourceEntityAttributeKey, DestinationEntityAttributeKey,
 };
 use crate::ip_based::rule::{
-    Policy, Rule, Effect, Condition, Expression,
+    Policy, Effect, Condition, Expression,
 };
 use crate::ip_based::encoder::{
     AttrIdMap, 
@@ -12,37 +12,250 @@ use crate::ip_based::rule_requirements::{
     collect_src_requirements,
 };
 
-fn is_rule_applicable_for_dest_entity(
-    rule: &Rule,
-    dest_entity: &DestinationEntity,
-) -> bool {
-    println!("rule.condition.references_dst(): {}", rule.condition.references_dst());
-    if !rule.condition.references_dst() {
-        return true;
+/// destination ごとに、各ポリシーの各ルールが適用され得るか
+/// (Rule::is_applicable_to_dest の結果) を一度だけ計算して持っておく。
+/// 同じ policies / destinations に対して何度も dest-only 評価をする処理で使い回す。
+/// 同じ ip の destination が複数あっても区別できるよう、build に渡した並びの添字で引く
+pub struct DestCache {
+    // [destination の添字][ポリシーの添字][ルールの添字]
+    applicable: Vec<Vec<Vec<bool>>>,
+}
+
+impl DestCache {
+    pub fn build(policies: &[Policy], dest_entities: &[DestinationEntity]) -> Self {
+        let applicable = dest_entities
+            .iter()
+            .map(|dest| {
+                policies
+                    .iter()
+                    .map(|policy| {
+                        policy.rules.iter().map(|rule| rule.is_applicable_to_dest(dest)).collect()
+                    })
+                    .collect()
+            })
+            .collect();
+        DestCache { applicable }
+    }
+
+    /// build に渡していない destination / ポリシー / ルールなら None
+    pub fn is_applicable(&self, dest_index: usize, policy_index: usize, rule_index: usize) -> Option<bool> {
+        self.applicable.get(dest_index)?.get(policy_index)?.get(rule_index).copied()
     }
-    rule.condition.evaluate_dest_only(dest_entity) == Ok(true)
 }
 
-pub fn list_applicable_rules_per_dest_entity(
+/// 適用可能なルールと、それが属するポリシー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApplicableRule {
+    pub policy_name: String,
+    pub rule_id: String,
+}
+
+/// destination ごとに適用可能なルールを、どのポリシーのものかと合わせて列挙する。
+/// ポリシー間でルール id が重複していても区別できる。
+/// 同じ policies / destinations で繰り返し呼ぶなら DestCache を作って
+/// list_applicable_rules_with_origin_cached を使う
+pub fn list_applicable_rules_with_origin_per_dest_entity(
     policies: &[Policy],
     dest_entities: &[DestinationEntity],
-) -> Vec<(String, Vec<String>)> {
+) -> Vec<(String, Vec<ApplicableRule>)> {
+    list_applicable_rules_with(policies, dest_entities, |_, _, _| None)
+}
+
+/// list_applicable_rules_with_origin_per_dest_entity と同じだが、判定は cache から引く。
+/// cache は同じ policies / dest_entities (同じ並び) で build したものを渡す
+pub fn list_applicable_rules_with_origin_cached(
+    policies: &[Policy],
+    dest_entities: &[DestinationEntity],
+    cache: &DestCache,
+) -> Vec<(String, Vec<ApplicableRule>)> {
+    list_applicable_rules_with(policies, dest_entities, |d, p, r| cache.is_applicable(d, p, r))
+}
+
+// cached が None を返したものはその場で評価する
+fn list_applicable_rules_with(
+    policies: &[Policy],
+    dest_entities: &[DestinationEntity],
+    cached: impl Fn(usize, usize, usize) -> Option<bool>,
+) -> Vec<(String, Vec<ApplicableRule>)> {
+    let cached = &cached;
     dest_entities
         .iter()
-        .map(|dest| {
-            let applicable: Vec<String> = policies
+        .enumerate()
+        .map(|(d, dest)| {
+            let applicable: Vec<ApplicableRule> = policies
                 .iter()
-                .flat_map(|policy| {
-                    policy.rules.iter().filter_map(|rule| {
-                        if is_rule_applicable_for_dest_entity(rule, dest) {
-                            Some(rule.id.clone())
-                        } else {
-                            None
-                        }
-                    })
+                .enumerate()
+                .flat_map(|(p, policy)| {
+                    policy
+                        .rules
+                        .iter()
+                        .enumerate()
+                        .filter(move |&(r, rule)| {
+                            cached(d, p, r).unwrap_or_else(|| rule.is_applicable_to_dest(dest))
+                        })
+                        .map(|(_, rule)| ApplicableRule {
+                            policy_name: policy.policy_name.clone(),
+                            rule_id: rule.id.clone(),
+                        })
                 })
                 .collect();
             (dest.ip.clone(), applicable)
-        })  
+        })
         .collect()
-}
\ No newline at end of file
+}
+
+/// ルール id だけを返す互換版
+pub fn list_applicable_rules_per_dest_entity(
+    policies: &[Policy],
+    dest_entities: &[DestinationEntity],
+) -> Vec<(String, Vec<String>)> {
+    list_applicable_rules_with_origin_per_dest_entity(policies, dest_entities)
+        .into_iter()
+        .map(|(ip, rules)| (ip, rules.into_iter().map(|r| r.rule_id).collect()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn policy(name: &str, rule_type: &str) -> Policy {
+        Policy::from_json_value(&json!({
+            "policy_name": name,
+            "description": "",
+            "default_effect": "deny",
+            "rules": [{
+                "id": "r1",
+                "effect": "allow",
+                "condition": { "operator": "EQ", "lhs": "Dst.Type", "rhs": rule_type }
+            }]
+        })).unwrap()
+    }
+
+    #[test]
+    fn applicable_rules_are_attributed_to_their_policy() {
+        let policies = [policy("web", "WebServer"), policy("db", "Database")];
+        let dest = DestinationEntity::from_json_value(&json!({
+            "ip": "10.0.0.3", "attributes": { "Dst.Type": "Database" }
+        })).unwrap();
+
+        let listed = list_applicable_rules_with_origin_per_dest_entity(&policies, std::slice::from_ref(&dest));
+        assert_eq!(listed, vec![(
+            "10.0.0.3".to_string(),
+            vec![ApplicableRule { policy_name: "db".to_string(), rule_id: "r1".to_string() }],
+        )]);
+
+        let both = [policy("db", "Database"), policy("db2", "Database")];
+        let listed = list_applicable_rules_with_origin_per_dest_entity(&both, std::slice::from_ref(&dest));
+        let names: Vec<&str> = listed[0].1.iter().map(|r| r.policy_name.as_str()).collect();
+        assert_eq!(names, vec!["db", "db2"]);
+        assert_eq!(
+            list_applicable_rules_per_dest_entity(&both, &[dest]),
+            vec![("10.0.0.3".to_string(), vec!["r1".to_string(), "r1".to_string()])]
+        );
+    }
+
+    #[test]
+    fn rules_applicable_to_matches_listed_ids() {
+        let policy = Policy::from_json_value(&json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": "deny",
+            "rules": [
+                { "id": "web", "effect": "allow", "condition": { "operator": "EQ", "lhs": "Dst.Type", "rhs": "WebServer" } },
+                { "id": "any", "effect": "deny", "condition": { "operator": "EQ", "lhs": "Src.Role", "rhs": "Guest" } },
+                { "id": "db", "effect": "allow", "condition": { "operator": "EQ", "lhs": "Dst.Type", "rhs": "Database" } }
+            ]
+        })).unwrap();
+        let dest = DestinationEntity::from_json_value(&json!({
+            "ip": "10.0.0.3", "attributes": { "Dst.Type": "Database" }
+        })).unwrap();
+
+        let rules = policy.rules_applicable_to(&dest);
+        let ids: Vec<String> = rules.iter().map(|r| r.id.clone()).collect();
+        assert_eq!(ids, vec!["any".to_string(), "db".to_string()]);
+        assert_eq!(
+            list_applicable_rules_per_dest_entity(std::slice::from_ref(&policy), &[dest]),
+            vec![("10.0.0.3".to_string(), ids)]
+        );
+    }
+
+    #[test]
+    fn dest_cache_agrees_with_uncached_evaluation() {
+        let policies = [policy("web", "WebServer"), policy("db", "Database")];
+        let dests: Vec<DestinationEntity> = ["WebServer", "Database", "Printer"]
+            .iter()
+            .enumerate()
+            .map(|(i, t)| DestinationEntity::from_json_value(&json!({
+                "ip": format!("10.0.0.{}", i), "attributes": { "Dst.Type": t }
+            })).unwrap())
+            .collect();
+
+        let cache = DestCache::build(&policies, &dests);
+        for (d, dest) in dests.iter().enumerate() {
+            for (p, policy) in policies.iter().enumerate() {
+                for (r, rule) in policy.rules.iter().enumerate() {
+                    assert_eq!(
+                        cache.is_applicable(d, p, r),
+                        Some(rule.is_applicable_to_dest(dest))
+                    );
+                }
+            }
+        }
+        assert_eq!(cache.is_applicable(dests.len(), 0, 0), None);
+        assert_eq!(
+            list_applicable_rules_with_origin_cached(&policies, &dests, &cache),
+            list_applicable_rules_with_origin_per_dest_entity(&policies, &dests)
+        );
+    }
+
+    #[test]
+    fn dest_cache_keeps_destinations_with_the_same_ip_apart() {
+        let policies = [policy("web", "WebServer")];
+        // NAT の内側などで同じ ip の destination が並ぶことがある
+        let dests: Vec<DestinationEntity> = ["WebServer", "Database"]
+            .iter()
+            .map(|t| DestinationEntity::from_json_value(&json!({
+                "ip": "10.0.0.1", "attributes": { "Dst.Type": t }
+            })).unwrap())
+            .collect();
+
+        let cache = DestCache::build(&policies, &dests);
+        assert_eq!(cache.is_applicable(0, 0, 0), Some(true));
+        assert_eq!(cache.is_applicable(1, 0, 0), Some(false));
+
+        let listed = list_applicable_rules_with_origin_cached(&policies, &dests, &cache);
+        let counts: Vec<usize> = listed.iter().map(|(_, rules)| rules.len()).collect();
+        assert_eq!(counts, vec![1, 0]);
+        assert_eq!(listed, list_applicable_rules_with_origin_per_dest_entity(&policies, &dests));
+    }
+
+    #[test]
+    fn never_conditions_are_not_applicable() {
+        let policy = Policy::from_json_value(&json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": "deny",
+            "rules": [
+                { "id": "never", "effect": "allow", "condition": { "operator": "FALSE" } },
+                { "id": "never_and", "effect": "allow", "condition": { "operator": "AND", "operands": [
+                    { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" },
+                    { "operator": "FALSE" }
+                ]}},
+                { "id": "src_only", "effect": "allow", "condition": { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" } },
+                { "id": "always", "effect": "allow", "condition": { "operator": "TRUE" } }
+            ]
+        })).unwrap();
+        let dest = DestinationEntity::from_json_value(&json!({
+            "ip": "10.0.0.3", "attributes": { "Dst.Type": "Database" }
+        })).unwrap();
+
+        let ids: Vec<&str> = policy.rules_applicable_to(&dest).iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["src_only", "always"]);
+        assert_eq!(
+            list_applicable_rules_per_dest_entity(std::slice::from_ref(&policy), &[dest]),
+            vec![("10.0.0.3".to_string(), vec!["src_only".to_string(), "always".to_string()])]
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use crate::ip_based::entity::{
+    AttributeValue, DestinationEntity, DestinationEntityAttributeKey,
+    SourceEntity, SourceEntityAttributeKey,
+};
+use crate::util::csv::read_csv_table;
+
+/// 集合値の列で要素を区切る文字 (例: `Faculty;WiFi_Users`)
+pub const SET_DELIMITER: char = ';';
+
+enum ColumnKind {
+    String,
+    Number,
+    Set,
+}
+
+fn parse_cell(cell: &str, kind: &ColumnKind, column: &str) -> Result<AttributeValue, String> {
+    match kind {
+        ColumnKind::String => Ok(AttributeValue::String(cell.to_string())),
+        ColumnKind::Number => cell
+            .trim()
+            .parse()
+            .map(AttributeValue::Number)
+            .map_err(|_| format!("Column {} expects a number, got '{}'", column, cell)),
+        ColumnKind::Set => Ok(AttributeValue::new_set(
+            cell.split(SET_DELIMITER)
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string()),
+        )),
+    }
+}
+
+/// ヘッダ行を読み、各行を (ip, desc, (列名, セル) の組) にして f に渡す
+fn read_rows<R, T, F>(reader: R, known_column: impl Fn(&str) -> bool, mut f: F) -> Result<Vec<T>, String>
+where
+    R: Read,
+    F: FnMut(String, Option<String>, Vec<(&str, String)>) -> Result<T, String>,
+{
+    let table = read_csv_table(reader)?;
+    let header = table.header;
+    if header.is_empty() {
+        return Ok(Vec::new());
+    }
+    let ip_col = header
+        .iter()
+        .position(|h| h == "ip")
+        .ok_or("CSV header must contain an 'ip' column")?;
+    let desc_col = header.iter().position(|h| h == "desc");
+    if let Some(unknown) = header
+        .iter()
+        .enumerate()
+        .find(|(i, h)| *i != ip_col && Some(*i) != desc_col && !known_column(h))
+    {
+        return Err(format!("Unknown CSV column: {}", unknown.1));
+    }
+
+    let mut out = Vec::new();
+    for (line_no, mut cells) in table.rows {
+        let ip = std::mem::take(&mut cells[ip_col]);
+        let desc = desc_col.map(|i| std::mem::take(&mut cells[i])).filter(|d| !d.is_empty());
+        let attributes = header
+            .iter()
+            .zip(cells)
+            .enumerate()
+            .filter(|(i, (_, cell))| *i != ip_col && Some(*i) != desc_col && !cell.is_empty())
+            .map(|(_, (h, cell))| (h.as_str(), cell))
+            .collect();
+        out.push(f(ip, desc, attributes).map_err(|e| format!("Line {}: {}", line_no, e))?);
+    }
+    Ok(out)
+}
+
+fn source_column_kind(key: &SourceEntityAttributeKey) -> ColumnKind {
+    match key {
+        SourceEntityAttributeKey::TrustScore | SourceEntityAttributeKey::SessionCount => ColumnKind::Number,
+        SourceEntityAttributeKey::Groups => ColumnKind::Set,
+        _ => ColumnKind::String,
+    }
+}
+
+fn destination_column_kind(key: &DestinationEntityAttributeKey) -> ColumnKind {
+    match key {
+        DestinationEntityAttributeKey::Sensitivity => ColumnKind::Number,
+        DestinationEntityAttributeKey::AllowedVLANs => ColumnKind::Set,
+        _ => ColumnKind::String,
+    }
+}
+
+/// 1 行目がヘッダの CSV から source エンティティを読み込む。
+///
+/// `ip` 列は必須、`desc` 列は任意。それ以外の列は `header_to_key` で属性に対応付ける。
+/// 空のセルは属性なしとして扱い、集合値の列は `SET_DELIMITER` で区切る。
+pub fn load_sources_csv<R: Read>(
+    reader: R,
+    header_to_key: &HashMap<String, SourceEntityAttributeKey>,
+) -> Result<Vec<SourceEntity>, String> {
+    read_rows(reader, |h| header_to_key.contains_key(h), |ip, desc, cells| {
+        let mut attributes = HashMap::new();
+        for (column, cell) in cells {
+            let key = &header_to_key[column];
+            attributes.insert(key.clone(), parse_cell(&cell, &source_column_kind(key), column)?);
+        }
+        Ok(SourceEntity { ip, attributes, desc })
+    })
+}
+
+/// load_sources_csv の destination 版
+pub fn load_destinations_csv<R: Read>(
+    reader: R,
+    header_to_key: &HashMap<String, DestinationEntityAttributeKey>,
+) -> Result<Vec<DestinationEntity>, String> {
+    read_rows(reader, |h| header_to_key.contains_key(h), |ip, desc, cells| {
+        let mut attributes = HashMap::new();
+        for (column, cell) in cells {
+            let key = &header_to_key[column];
+            attributes.insert(key.clone(), parse_cell(&cell, &destination_column_kind(key), column)?);
+        }
+        Ok(DestinationEntity { ip, attributes, desc })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn source_headers() -> HashMap<String, SourceEntityAttributeKey> {
+        HashMap::from([
+            ("role".to_string(), SourceEntityAttributeKey::Role),
+            ("trust".to_string(), SourceEntityAttributeKey::TrustScore),
+            ("groups".to_string(), SourceEntityAttributeKey::Groups),
+        ])
+    }
+
+    #[test]
+    fn imports_sources_with_set_column() {
+        let csv = "ip,role,trust,groups,desc\n\
+                   10.0.0.1,Student,40,Undergrad;WiFi_Users,\"lab, 2F\"\n\
+                   10.0.0.2,Admin,90,,\n";
+        let sources = load_sources_csv(Cursor::new(csv), &source_headers()).unwrap();
+
+        assert_eq!(sources.len(), 2);
+        let first = &sources[0];
+        assert_eq!(first.ip, "10.0.0.1");
+        assert_eq!(first.desc.as_deref(), Some("lab, 2F"));
+        assert_eq!(
+            first.attributes[&SourceEntityAttributeKey::Groups],
+            AttributeValue::Set(vec!["Undergrad".to_string(), "WiFi_Users".to_string()])
+        );
+        assert_eq!(first.attributes[&SourceEntityAttributeKey::TrustScore], AttributeValue::Number(40));
+        assert!(!sources[1].attributes.contains_key(&SourceEntityAttributeKey::Groups));
+    }
+
+    #[test]
+    fn rejects_unknown_columns_and_bad_numbers() {
+        let unknown = load_sources_csv(Cursor::new("ip,color\n10.0.0.1,red\n"), &source_headers());
+        assert_eq!(unknown.unwrap_err(), "Unknown CSV column: color");
+
+        let bad = load_sources_csv(Cursor::new("ip,trust\n10.0.0.1,high\n"), &source_headers());
+        assert!(bad.unwrap_err().starts_with("Line 2:"));
+    }
+}
@@ -0,0 +1,166 @@
+use crate::ip_based::rule::{Condition, Effect, Expression, Policy};
+
+fn escape_label(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// リテラルの " と \ はラベルの中でも区切りと区別できるようエスケープしておく。
+// ラベル全体は出力時にもう一度 escape_label される
+fn expression_label(expr: &Expression) -> String {
+    match expr {
+        Expression::LiteralString(s) => format!("\"{}\"", escape_label(s)),
+        Expression::LiteralNumber(n) => n.to_string(),
+        Expression::LiteralSet(items) => {
+            let parts: Vec<String> = items.iter().map(|s| format!("\"{}\"", escape_label(s))).collect();
+            format!("[{}]", parts.join(", "))
+        }
+        Expression::AttributeRef(name) | Expression::EnvRef(name) => name.clone(),
+        Expression::Add { operands } => {
+            let parts: Vec<String> = operands.iter().map(expression_label).collect();
+            format!("({})", parts.join(" + "))
+        }
+        Expression::Multiply { operands } => {
+            let parts: Vec<String> = operands.iter().map(expression_label).collect();
+            format!("({})", parts.join(" * "))
+        }
+        Expression::Count { operand } => format!("COUNT({})", expression_label(operand)),
+        Expression::If { cond, then, els } => format!(
+            "IF({}, {}, {})",
+            condition_label(cond),
+            expression_label(then),
+            expression_label(els)
+        ),
+    }
+}
+
+fn condition_label(cond: &Condition) -> String {
+    match cond {
+        Condition::Always => "TRUE".to_string(),
+        Condition::Never => "FALSE".to_string(),
+        Condition::And { .. } => "AND".to_string(),
+        Condition::Or { .. } => "OR".to_string(),
+        Condition::Eq { lhs, rhs } => format!("{} == {}", expression_label(lhs), expression_label(rhs)),
+        Condition::Gte { lhs, rhs } => format!("{} >= {}", expression_label(lhs), expression_label(rhs)),
+        Condition::Gt { lhs, rhs } => format!("{} > {}", expression_label(lhs), expression_label(rhs)),
+        Condition::Lt { lhs, rhs } => format!("{} < {}", expression_label(lhs), expression_label(rhs)),
+        Condition::In { target, check_against } => {
+            format!("{} IN {}", expression_label(target), expression_label(check_against))
+        }
+        Condition::NotIn { target, check_against } => {
+            format!("{} NOT IN {}", expression_label(target), expression_label(check_against))
+        }
+        Condition::InSet { value, set } => {
+            format!("{} IN {}", expression_label(value), expression_label(set))
+        }
+    }
+}
+
+// ノードを出力し、そのノードIDを返す
+fn write_condition(
+    out: &mut String,
+    cond: &Condition,
+    prefix: &str,
+    counter: &mut usize,
+) -> String {
+    let id = format!("{}_n{}", prefix, *counter);
+    *counter += 1;
+
+    let shape = match cond {
+        Condition::And { .. } | Condition::Or { .. } => "ellipse",
+        _ => "box",
+    };
+    out.push_str(&format!(
+        "    \"{}\" [label=\"{}\", shape={}];\n",
+        id,
+        escape_label(&condition_label(cond)),
+        shape
+    ));
+
+    if let Condition::And { operands } | Condition::Or { operands } = cond {
+        for child in operands {
+            let child_id = write_condition(out, child, prefix, counter);
+            out.push_str(&format!("    \"{}\" -> \"{}\";\n", id, child_id));
+        }
+    }
+    id
+}
+
+pub fn policy_to_dot(policy: &Policy) -> String {
+    let mut out = String::new();
+    out.push_str(&format!("digraph \"{}\" {{\n", escape_label(&policy.policy_name)));
+
+    for (i, rule) in policy.rules.iter().enumerate() {
+        let color = match rule.effect {
+            Effect::Allow => "green",
+            Effect::Deny => "red",
+        };
+        out.push_str(&format!("  subgraph cluster_{} {{\n", i));
+        out.push_str(&format!(
+            "    label=\"{} ({:?})\";\n    color={};\n",
+            escape_label(&rule.id),
+            rule.effect,
+            color
+        ));
+
+        let prefix = format!("r{}", i);
+        let mut counter = 0;
+        write_condition(&mut out, &rule.condition, &prefix, &mut counter);
+        out.push_str("  }\n");
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn dot_has_subgraph_per_rule_and_node_per_operator() {
+        let policy = Policy::from_json_value(&json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": "deny",
+            "rules": [
+                {
+                    "id": "r1",
+                    "effect": "allow",
+                    "condition": {
+                        "operator": "AND",
+                        "operands": [
+                            { "operator": "EQ", "lhs": "Src.Role", "rhs": "say \"hi\"" },
+                            {
+                                "operator": "OR",
+                                "operands": [
+                                    { "operator": "GTE", "lhs": "Src.TrustScore", "rhs": 80 },
+                                    { "operator": "EQ", "lhs": "Dst.Type", "rhs": "Printer" }
+                                ]
+                            }
+                        ]
+                    }
+                },
+                {
+                    "id": "r2",
+                    "effect": "deny",
+                    "condition": { "operator": "EQ", "lhs": "Src.Role", "rhs": "Guest" }
+                }
+            ]
+        })).unwrap();
+
+        let dot = policy_to_dot(&policy);
+        assert_eq!(dot.matches("subgraph cluster_").count(), 2);
+        assert_eq!(dot.matches("[label=").count(), 6);
+        assert!(dot.contains("color=green"));
+        assert!(dot.contains("color=red"));
+        assert!(dot.contains(r#"label="Src.Role == \"say \\\"hi\\\"\"""#));
+        assert_eq!(
+            condition_label(&Condition::In {
+                target: Expression::AttributeRef("Src.Dept".to_string()),
+                check_against: Expression::LiteralSet(vec!["a\\b".to_string()]),
+            }),
+            r#"Src.Dept IN ["a\\b"]"#
+        );
+    }
+}
@@ -0,0 +1,94 @@
+// ファイルシステムを使わない JSON 文字列入出力の評価 API。
+// ブラウザ / エッジ環境向けに wasm-bindgen などでそのまま包めるようにする
+
+use std::collections::HashMap;
+use serde_json::{Value, json};
+
+use crate::ip_based::entity::{AttributeValue, DestinationEntity, SourceEntity, parse_attribute_value};
+use crate::ip_based::evaluator::{Decision, evaluate_policy_explained};
+use crate::ip_based::rule::{Effect, Policy};
+use crate::util::normalization::NormalizationConfig;
+
+fn parse_json(label: &str, text: &str) -> Result<Value, String> {
+    serde_json::from_str(text).map_err(|e| format!("{}: {}", label, e))
+}
+
+// env は {"Env.Time": 10, ...} の形。null は空の env として扱う
+fn parse_env(value: &Value) -> Result<HashMap<String, AttributeValue>, String> {
+    match value {
+        Value::Null => Ok(HashMap::new()),
+        Value::Object(obj) => obj
+            .iter()
+            .map(|(key, v)| {
+                parse_attribute_value(v, &NormalizationConfig::default())
+                    .map(|parsed| (key.clone(), parsed))
+                    .map_err(|e| format!("env: {}: {}", key, e))
+            })
+            .collect(),
+        _ => Err("env: must be an object".to_string()),
+    }
+}
+
+pub fn decision_to_json(decision: &Decision) -> Value {
+    json!({
+        "effect": match decision.effect {
+            Effect::Allow => "allow",
+            Effect::Deny => "deny",
+        },
+        "matched_rule": decision.matched_rule,
+        "obligations": decision.obligations,
+    })
+}
+
+/// ポリシー・source・destination・env をそれぞれ JSON 文字列で受け取り、
+/// 判定結果 `{"effect", "matched_rule", "obligations"}` を JSON 文字列で返す
+pub fn evaluate_json(
+    policy_json: &str,
+    source_json: &str,
+    dest_json: &str,
+    env_json: &str,
+) -> Result<String, String> {
+    let policy = Policy::from_json_value(&parse_json("policy", policy_json)?)?;
+    let source = SourceEntity::from_json_value(&parse_json("source", source_json)?)
+        .map_err(|e| format!("source: {}", e))?;
+    let dest = DestinationEntity::from_json_value(&parse_json("destination", dest_json)?)
+        .map_err(|e| format!("destination: {}", e))?;
+    let env = parse_env(&parse_json("env", env_json)?)?;
+
+    let decision = evaluate_policy_explained(&policy, &source, &dest, &env)?;
+    Ok(decision_to_json(&decision).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluates_stringified_inputs() {
+        let policy = json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": "deny",
+            "rules": [{
+                "id": "office_hours",
+                "effect": "allow",
+                "obligations": ["log"],
+                "condition": { "operator": "AND", "operands": [
+                    { "operator": "EQ", "lhs": "Src.Dept", "rhs": "Dst.OwnerDept" },
+                    { "operator": "LT", "lhs": "Env.Hour", "rhs": 18 }
+                ]}
+            }]
+        }).to_string();
+        let source = json!({ "ip": "10.0.0.1", "attributes": { "Src.Dept": "CS" } }).to_string();
+        let dest = json!({ "ip": "172.20.0.1", "attributes": { "Dst.OwnerDept": "CS" } }).to_string();
+
+        let out = evaluate_json(&policy, &source, &dest, r#"{"Env.Hour": 9}"#).unwrap();
+        let decision: Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(decision, json!({ "effect": "allow", "matched_rule": "office_hours", "obligations": ["log"] }));
+
+        let out = evaluate_json(&policy, &source, &dest, r#"{"Env.Hour": 20}"#).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&out).unwrap()["effect"], "deny");
+
+        assert!(evaluate_json(&policy, "{", &dest, "null").unwrap_err().starts_with("source:"));
+    }
+}
@@ -1,64 +1,285 @@
 use std::collections::HashMap;
-use std::fs;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read};
 use serde_json::Value;
 
 use crate::ip_based::entity::{
     AttributeValue, SourceEntity, DestinationEntity,
-    SourceEntityAttributeKey, DestinationEntityAttributeKey,
+    SourceEntityAttributeKey, DestinationEntityAttributeKey, json_number_to_i64, parse_attribute_value,
 };
 
-use crate::ip_based::rule_requirements::SrcRequirement;
+use crate::ip_based::rule_requirements::{
+    SrcRequirement, flatten_src_requirements, merge_numeric_requirements,
+};
+use crate::util::normalization::NormalizationConfig;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AttrValueType {
     Single,
     Multiple,
     Numeric,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct AttrIdEntry {
     pub value_type: AttrValueType,
     pub value_to_id: Option<HashMap<String, u32>>,
     pub numeric_min: Option<i64>,
     pub numeric_max: Option<i64>,
+    /// 未知の値を割り当てる "other" の ID (description.unknown_id)。
+    /// 未指定なら未知の値はエラーになる
+    pub unknown_id: Option<u32>,
+}
+
+/// エンコーダが 1 属性に使えるビット数 (u32)
+pub const MAX_ATTR_WIDTH: usize = 32;
+
+// value を 2 進で表すのに必要なビット数 (0 でも 1 ビット)
+fn bits_needed(value: u64) -> usize {
+    (u64::BITS - value.leading_zeros()).max(1) as usize
+}
+
+impl AttrIdEntry {
+    /// 数値を u32 にするときに引く値。
+    /// 範囲の下限が負の場合は offset-binary (値 - min) で符号化し、
+    /// 下限が 0 以上か未指定の場合は値をそのまま使う (0)
+    pub fn numeric_offset(&self) -> i64 {
+        match self.numeric_min {
+            Some(min) if min < 0 => min,
+            _ => 0,
+        }
+    }
+}
+
+/// encoded_value_to_u32 で符号化した数値を元に戻す
+pub fn u32_to_numeric(entry: &AttrIdEntry, bits: u32) -> i64 {
+    bits as i64 + entry.numeric_offset()
 }
 
 pub struct AttrIdMap {
     pub entries: HashMap<String, AttrIdEntry>,
+    /// 別名 -> 正規の属性名 (例: "Src.Department" -> "Src.Dept")
+    pub aliases: HashMap<String, String>,
+    /// エンティティに属性が無いときに使う値 (description.default)。
+    /// 指定の無い属性はこれまで通り評価時にエラーになる
+    pub defaults: HashMap<String, AttributeValue>,
 }
 
 impl AttrIdMap {
+    /// JSON を使わずにコードから組み立てる (テストや動的な語彙向け)
+    pub fn builder() -> AttrIdMapBuilder {
+        AttrIdMapBuilder::default()
+    }
+
     pub fn load(path: &str) -> Result<Self, String> {
-        let s = fs::read_to_string(path).map_err(
-            |e| e.to_string()
+        let file = File::open(path).map_err(
+            |e| format!("{}: {}", path, e)
         )?;
-        let json: Value = serde_json::from_str(&s).map_err(
+        Self::from_reader(BufReader::new(file))
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, String> {
+        let json: Value = serde_json::from_reader(reader).map_err(
             |e| e.to_string()
         )?;
         let obj = json.as_object().ok_or("attr_id json must be an object")?;
 
         let mut entries = HashMap::new();
+        let mut aliases = HashMap::new();
+        let mut defaults = HashMap::new();
         for (attr_name, attr_val) in obj {
-            let entry = Self::parse_attr_entry(attr_val)?;
+            if attr_name == "aliases" {
+                aliases = Self::parse_aliases(attr_val)?;
+                continue;
+            }
+            let entry = Self::parse_attr_entry(attr_val).map_err(|e| format!("{}: {}", attr_name, e))?;
+            if let Some(default) = attr_val.get("description").and_then(|d| d.get("default")) {
+                let value = parse_attribute_value(default, &NormalizationConfig::default())
+                    .map_err(|e| format!("{}: description.default: {}", attr_name, e))?;
+                defaults.insert(attr_name.clone(), value);
+            }
             entries.insert(attr_name.clone(), entry);
         }
-        Ok(AttrIdMap { entries })
+        for (alias, target) in &aliases {
+            if !entries.contains_key(target) {
+                return Err(format!("Alias {} refers to unknown attribute {}", alias, target));
+            }
+        }
+        let map = AttrIdMap { entries, aliases, defaults };
+        // 既定値もエンティティの値と同じく符号化できなければならない
+        for (attr_name, value) in &map.defaults {
+            encode_value(&map, attr_name, value)
+                .map_err(|e| format!("{}: description.default: {}", attr_name, e))?;
+        }
+        Ok(map)
+    }
+
+    fn parse_aliases(v: &Value) -> Result<HashMap<String, String>, String> {
+        let obj = v.as_object().ok_or("aliases must be an object")?;
+        obj.iter()
+            .map(|(alias, target)| {
+                target
+                    .as_str()
+                    .map(|t| (alias.clone(), t.to_string()))
+                    .ok_or_else(|| format!("Alias target must be a string: {}", alias))
+            })
+            .collect()
+    }
+
+    /// 別名なら正規の属性名を、そうでなければそのまま返す
+    pub fn canonical_name<'a>(&'a self, attr_name: &'a str) -> &'a str {
+        self.aliases.get(attr_name).map(|s| s.as_str()).unwrap_or(attr_name)
+    }
+
+    /// 別名も考慮して属性の定義を引く
+    pub fn entry(&self, attr_name: &str) -> Option<&AttrIdEntry> {
+        self.entries.get(self.canonical_name(attr_name))
+    }
+
+    /// 属性が取り得る値の数（single / multiple は既知の値の数、numeric は範囲の幅）
+    pub fn vocabulary_size(&self, attr_name: &str) -> Option<usize> {
+        let entry = self.entry(attr_name)?;
+        match (&entry.value_to_id, entry.numeric_min, entry.numeric_max) {
+            (Some(map), _, _) => Some(map.len()),
+            (None, Some(min), Some(max)) if max >= min => {
+                usize::try_from(max.checked_sub(min)?.checked_add(1)?).ok()
+            }
+            _ => None,
+        }
     }
 
     pub fn value_to_id(&self, attr_name: &str, value: &str) -> Result<u32, String> {
-        let entry = self.entries.get(attr_name)
+        self.lookup_id(attr_name, value, false)
+    }
+
+    /// value_to_id と同じだが、見つからない値は unknown_id があればそちらに割り当てる。
+    /// エンティティの符号化用で、ポリシーのリテラルや要求には使わない
+    pub fn entity_value_to_id(&self, attr_name: &str, value: &str) -> Result<u32, String> {
+        self.lookup_id(attr_name, value, true)
+    }
+
+    fn lookup_id(&self, attr_name: &str, value: &str, use_unknown_id: bool) -> Result<u32, String> {
+        let entry = self.entry(attr_name)
             .ok_or_else(|| format!("Unknown attribute: {}", attr_name))?;
         let map = entry.value_to_id.as_ref()
             .ok_or_else(|| format!("Attribute {} has no value->id map", attr_name))?;
         map.get(value).copied()
+            .or(entry.unknown_id.filter(|_| use_unknown_id))
             .ok_or_else(|| format!("Value '{}' not found in attribute {}", value, attr_name))
     }
 
+    /// 属性を符号化するのに必要なビット数 (未知の属性は 0)。
+    ///
+    /// - single: 最大 ID を表すのに必要なビット数 (ID が 0..n なら ceil(log2 n))
+    /// - multiple: 最大 ID + 1 (ID ごとに 1 ビット)
+    /// - numeric: offset-binary にした numeric_max (max - offset) を表すのに必要なビット数。
+    ///   温度計型 (numeric_to_threshold_bits) の場合は閾値の数がビット数になる
+    pub fn attr_width(&self, attr_name: &str) -> usize {
+        let Some(entry) = self.entry(attr_name) else { return 0 };
+        let max_id = entry
+            .value_to_id
+            .as_ref()
+            .and_then(|m| m.values().max().copied())
+            .max(entry.unknown_id);
+        match entry.value_type {
+            AttrValueType::Single => max_id.map_or(0, |id| bits_needed(id as u64)),
+            AttrValueType::Multiple => max_id.map_or(0, |id| id as usize + 1),
+            AttrValueType::Numeric => entry.numeric_max.map_or(0, |max| {
+                // 差が i64 に収まらないなら u64 の全ビットが必要
+                max.checked_sub(entry.numeric_offset())
+                    .map_or(u64::BITS as usize, |span| bits_needed(span.max(0) as u64))
+            }),
+        }
+    }
+
+    /// ビット列の 1 フィールドの幅。numeric_thresholds に閾値がある数値属性は
+    /// 温度計型になるので閾値の数、それ以外は attr_width
+    pub fn field_width(&self, attr_name: &str, numeric_thresholds: &HashMap<String, Vec<i64>>) -> usize {
+        match (self.entry(attr_name), numeric_thresholds.get(attr_name)) {
+            (Some(entry), Some(thresholds)) if entry.value_type == AttrValueType::Numeric => thresholds.len(),
+            _ => self.attr_width(attr_name),
+        }
+    }
+
+    /// いずれかの属性が MAX_ATTR_WIDTH ビットに収まらなければエラーにする
+    pub fn validate_widths(&self) -> Result<(), String> {
+        let mut names: Vec<&String> = self.entries.keys().collect();
+        names.sort();
+        for name in names {
+            let width = self.attr_width(name);
+            if width > MAX_ATTR_WIDTH {
+                return Err(format!(
+                    "Attribute {} needs {} bits, but the encoder supports at most {}",
+                    name, width, MAX_ATTR_WIDTH
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// value_to_id の逆引き
+    pub fn id_to_value(&self, attr_name: &str, id: u32) -> Option<&str> {
+        self.entry(attr_name)?
+            .value_to_id
+            .as_ref()?
+            .iter()
+            .find(|(_, v)| **v == id)
+            .map(|(value, _)| value.as_str())
+    }
+
+    /// 複数の値をまとめて ID に変換する。見つからなかった値は 2 つ目の Vec に入れて返す
+    pub fn values_to_ids(&self, attr_name: &str, values: &[&str]) -> (Vec<u32>, Vec<String>) {
+        self.values_to_ids_with(attr_name, values, false)
+    }
+
+    // use_unknown_id なら見つからなかった値を unknown_id に割り当てる
+    fn values_to_ids_with(&self, attr_name: &str, values: &[&str], use_unknown_id: bool) -> (Vec<u32>, Vec<String>) {
+        let entry = self.entry(attr_name);
+        let map = entry.and_then(|e| e.value_to_id.as_ref());
+        let unknown_id = entry.and_then(|e| e.unknown_id).filter(|_| use_unknown_id);
+        let mut ids = Vec::with_capacity(values.len());
+        let mut unknown = Vec::new();
+        for value in values {
+            match map.and_then(|m| m.get(*value)).copied().or(unknown_id) {
+                Some(id) => ids.push(id),
+                None => unknown.push(value.to_string()),
+            }
+        }
+        (ids, unknown)
+    }
+
+    /// values_to_ids の結果を、未知の値を 1 つのエラーにまとめて返す
+    fn resolve_ids<S: AsRef<str>>(
+        &self,
+        attr_name: &str,
+        values: &[S],
+        use_unknown_id: bool,
+    ) -> Result<Vec<u32>, String> {
+        if self.entry(attr_name).is_none() {
+            return Err(format!("Unknown attribute: {}", attr_name));
+        }
+        let values: Vec<&str> = values.iter().map(|v| v.as_ref()).collect();
+        let (ids, unknown) = self.values_to_ids_with(attr_name, &values, use_unknown_id);
+        if unknown.is_empty() {
+            Ok(ids)
+        } else {
+            Err(format!("Values {:?} not found in attribute {}", unknown, attr_name))
+        }
+    }
+
     fn parse_attr_entry(v: &Value) -> Result<AttrIdEntry, String> {
         let desc = v.get("description").and_then(|d| d.get("type"))
             .and_then(|t| t.as_str())
             .ok_or("Missing description.type")?;
+        let unknown_id = match v.get("description").and_then(|d| d.get("unknown_id")) {
+            None => None,
+            Some(id) => Some(
+                id.as_u64()
+                    .and_then(|n| u32::try_from(n).ok())
+                    .ok_or("description.unknown_id must be a non-negative integer")?,
+            ),
+        };
         let value_obj = v.get("value").and_then(|v| v.as_object())
             .ok_or("Missing value object")?;
 
@@ -70,17 +291,34 @@ impl AttrIdMap {
         };
 
         let (value_to_id, numeric_min, numeric_max) = if value_obj.contains_key("min") && value_obj.contains_key("max") {
-            let min = value_obj.get("min").and_then(|n| n.as_i64()).ok_or("numeric min")?;
-            let max = value_obj.get("max").and_then(|n| n.as_i64()).ok_or("numeric max")?;
+            let bound = |key: &str| match value_obj.get(key) {
+                Some(Value::Number(n)) => json_number_to_i64(n).map_err(|e| format!("numeric {}: {}", key, e)),
+                _ => Err(format!("numeric {} must be a number", key)),
+            };
+            let min = bound("min")?;
+            let max = bound("max")?;
 
             (None, Some(min), Some(max))
         } else {
             let mut value_to_id = HashMap::new();
             for (id_str, val) in value_obj {
                 let id = id_str.parse::<u32>().map_err(|_| format!("Invalid id: {}", id_str))?;
-                let s = val.as_str().ok_or("value must be string for single/multiple")?.to_string();
+                // 数値コードの値 (VLAN ID など) は 10 進の文字列として登録する
+                let s = match val {
+                    Value::String(s) => s.clone(),
+                    Value::Number(n) => json_number_to_i64(n)
+                        .map_err(|e| format!("value for id {}: {}", id_str, e))?
+                        .to_string(),
+                    _ => return Err("value must be string or integer for single/multiple".to_string()),
+                };
                 value_to_id.insert(s, id);
             }
+            // 既存の値と同じ ID だと、未知の値とその値を区別できなくなる
+            if let Some(unknown) = unknown_id
+                && let Some((value, _)) = value_to_id.iter().find(|(_, id)| **id == unknown)
+            {
+                return Err(format!("description.unknown_id {} collides with value '{}'", unknown, value));
+            }
             (Some(value_to_id), None, None)
         };
 
@@ -89,10 +327,62 @@ impl AttrIdMap {
             value_to_id,
             numeric_min,
             numeric_max,
+            unknown_id,
         })
     }
 }
 
+/// AttrIdMap::builder() で使う。single / multiple の値には渡した順に 0 から ID を振る
+#[derive(Debug, Default)]
+pub struct AttrIdMapBuilder {
+    entries: HashMap<String, AttrIdEntry>,
+}
+
+impl AttrIdMapBuilder {
+    pub fn single(self, name: &str, values: &[&str]) -> Self {
+        self.categorical(name, AttrValueType::Single, values)
+    }
+
+    pub fn multiple(self, name: &str, values: &[&str]) -> Self {
+        self.categorical(name, AttrValueType::Multiple, values)
+    }
+
+    pub fn numeric(mut self, name: &str, min: i64, max: i64) -> Self {
+        self.entries.insert(name.to_string(), AttrIdEntry {
+            value_type: AttrValueType::Numeric,
+            value_to_id: None,
+            numeric_min: Some(min),
+            numeric_max: Some(max),
+            unknown_id: None,
+        });
+        self
+    }
+
+    fn categorical(mut self, name: &str, value_type: AttrValueType, values: &[&str]) -> Self {
+        let value_to_id = values
+            .iter()
+            .enumerate()
+            .map(|(id, v)| (v.to_string(), id as u32))
+            .collect();
+        self.entries.insert(name.to_string(), AttrIdEntry {
+            value_type,
+            value_to_id: Some(value_to_id),
+            numeric_min: None,
+            numeric_max: None,
+            unknown_id: None,
+        });
+        self
+    }
+
+    pub fn build(self) -> AttrIdMap {
+        AttrIdMap {
+            entries: self.entries,
+            aliases: HashMap::new(),
+            defaults: HashMap::new(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum EncodedAttributeValue {
     SingleId(u32),
@@ -100,45 +390,97 @@ pub enum EncodedAttributeValue {
     Numeric(i64),
 }
 
+impl fmt::Display for EncodedAttributeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodedAttributeValue::SingleId(id) => write!(f, "SingleId({})", id),
+            EncodedAttributeValue::MultipleIds(ids) => write!(f, "MultipleIds({:?})", ids),
+            EncodedAttributeValue::Numeric(n) => write!(f, "Numeric({})", n),
+        }
+    }
+}
+
+/// エンコード済みの値を AttrIdMap で元の値に戻して表示する（デバッグ用）。
+/// 例: `SingleId(2) => "Admin"`, `MultipleIds([0, 8]) => {"Undergrad", "Faculty"}`
+pub fn describe(map: &AttrIdMap, attr_name: &str, value: &EncodedAttributeValue) -> String {
+    let decode = |id: u32| match map.id_to_value(attr_name, id) {
+        Some(v) => format!("{:?}", v),
+        None => format!("<unknown id {}>", id),
+    };
+    match value {
+        EncodedAttributeValue::SingleId(id) => format!("{} => {}", value, decode(*id)),
+        EncodedAttributeValue::MultipleIds(ids) => {
+            let values: Vec<String> = ids.iter().map(|id| decode(*id)).collect();
+            format!("{} => {{{}}}", value, values.join(", "))
+        }
+        EncodedAttributeValue::Numeric(_) => value.to_string(),
+    }
+}
+
+/// value が [min, max] (両端を含む。None の側は制限なし) に入っているか。
+/// 閾値やルールの数値を encode_value に渡す前の検査にも使える
+pub fn in_range(value: i64, min: Option<i64>, max: Option<i64>) -> Result<(), String> {
+    let below = min.is_some_and(|min| value < min);
+    let above = max.is_some_and(|max| value > max);
+    if below || above {
+        let bound = |b: Option<i64>| b.map_or("unbounded".to_string(), |b| b.to_string());
+        return Err(format!("Numeric value {} out of range [{}, {}]", value, bound(min), bound(max)));
+    }
+    Ok(())
+}
+
+/// ポリシーのリテラルや要求の値を符号化する。value_to_id に無い値はエラー
 pub fn encode_value(
     map: &AttrIdMap,
     attr_name: &str,
     v: &AttributeValue,
 ) -> Result<EncodedAttributeValue, String> {
-    let entry = map.entries.get(attr_name)
+    encode_value_with(map, attr_name, v, false)
+}
+
+/// エンティティの属性値を符号化する。value_to_id に無い値は unknown_id があればそちらに割り当てる
+pub fn encode_entity_value(
+    map: &AttrIdMap,
+    attr_name: &str,
+    v: &AttributeValue,
+) -> Result<EncodedAttributeValue, String> {
+    encode_value_with(map, attr_name, v, true)
+}
+
+fn encode_value_with(
+    map: &AttrIdMap,
+    attr_name: &str,
+    v: &AttributeValue,
+    use_unknown_id: bool,
+) -> Result<EncodedAttributeValue, String> {
+    let entry = map.entry(attr_name)
         .ok_or_else(|| format!("Unknown attribute: {}", attr_name))?;
     
     match (&entry.value_type, v) {
         (AttrValueType::Single, AttributeValue::String(s)) => {
-            let id = map.value_to_id(attr_name, s)?;
+            let id = map.lookup_id(attr_name, s, use_unknown_id)?;
+            Ok(EncodedAttributeValue::SingleId(id))
+        }
+        // 数値コードの single 属性: 値は 10 進の文字列として value_to_id を引くので、
+        // エンティティ側は Number でも String ("10") でも同じ ID になる
+        (AttrValueType::Single, AttributeValue::Number(n)) => {
+            let id = map.lookup_id(attr_name, &n.to_string(), use_unknown_id)?;
             Ok(EncodedAttributeValue::SingleId(id))
         }
         (AttrValueType::Numeric, AttributeValue::Number(n)) => {
-            match (entry.numeric_min, entry.numeric_max) {
-                (Some(min), Some(max)) => {
-                    if *n < min || *n > max {
-                        return Err(format!("Numeric value {} out of range [{}, {}]", n, min, max));
-                    }
-                }
-                (Some(min), None) => {
-                    if *n < min {
-                        return Err(format!("Numeric value {} is below minimum {}", n, min));
-                    }
-                }
-                (None, Some(max)) => {
-                    if *n > max {
-                        return Err(format!("Numeric value {} above maximu {}", n, max));
-                    }
-                }
-                (None, None) => {}
-            }
+            in_range(*n, entry.numeric_min, entry.numeric_max)
+                .map_err(|e| format!("{}: {}", attr_name, e))?;
             Ok(EncodedAttributeValue::Numeric(*n))
         }
         (AttrValueType::Multiple, AttributeValue::Set(vec)) => {
-            let ids: Result<Vec<u32>, _> = vec.iter()
-                .map(|s| map.value_to_id(attr_name, s))
-                .collect();
-            Ok(EncodedAttributeValue::MultipleIds(ids?))
+            let ids = map.resolve_ids(attr_name, vec, use_unknown_id)?;
+            Ok(EncodedAttributeValue::MultipleIds(ids))
+        }
+        // multiple 属性に単一の String が来た場合は要素 1 つの集合として扱う
+        // (実データでは要素が 1 つだけのとき配列にしていないことが多い)
+        (AttrValueType::Multiple, AttributeValue::String(s)) => {
+            let ids = map.resolve_ids(attr_name, std::slice::from_ref(s), use_unknown_id)?;
+            Ok(EncodedAttributeValue::MultipleIds(ids))
         }
         _ => Err(format!(
             "Type mismatch: attribute {} expects {:?}, got {:?}",
@@ -155,7 +497,7 @@ pub fn encode_source_entity(
     for (key, val) in &entity.attributes {
         let name = SourceEntity::deparse_attribute_key(key)?;
         if map.entries.contains_key(&name) {
-            let encoded = encode_value(map, &name, val)?;
+            let encoded = encode_entity_value(map, &name, val)?;
             out.insert(key.clone(), encoded);
         }
     }
@@ -170,7 +512,7 @@ pub fn encode_destination_entity(
     for (key, val) in &entity.attributes {
         let name = DestinationEntity::deparse_attribute_key(key)?;
         if map.entries.contains_key(&name) {
-            let encoded = encode_value(map, &name, val)?;
+            let encoded = encode_entity_value(map, &name, val)?;
             out.insert(key.clone(), encoded);
         }
     }
@@ -186,15 +528,16 @@ pub fn encoded_value_to_u32(
             Ok(*id)
         }
         (AttrIdEntry { value_type: AttrValueType::Numeric, .. }, EncodedAttributeValue::Numeric(n)) => {
-            if *n < 0 || *n > u32::MAX as i64 {
+            let shifted = n.checked_sub(entry.numeric_offset()).unwrap_or(-1);
+            if shifted < 0 || shifted > u32::MAX as i64 {
                 return Err(format!("Numeric value {} out of u32 range", n));
             }
-            Ok(*n as u32)
+            Ok(shifted as u32)
         }
         (AttrIdEntry { value_type: AttrValueType::Multiple, .. }, EncodedAttributeValue::MultipleIds(ids)) => {
             let mut bits = 0u32;
             for &id in ids {
-                if id >= 32 {
+                if id as usize >= MAX_ATTR_WIDTH {
                     return Err(format!("Multiple id {} does not fit in 32 bits", id));
                 }
                 bits |= 1u32 << id;
@@ -205,26 +548,247 @@ pub fn encoded_value_to_u32(
     }
 }
 
+/// encoded_value_to_u32 の逆
+pub fn u32_to_encoded_value(entry: &AttrIdEntry, bits: u32) -> EncodedAttributeValue {
+    match entry.value_type {
+        AttrValueType::Single => EncodedAttributeValue::SingleId(bits),
+        AttrValueType::Numeric => EncodedAttributeValue::Numeric(u32_to_numeric(entry, bits)),
+        AttrValueType::Multiple => EncodedAttributeValue::MultipleIds(
+            (0..MAX_ATTR_WIDTH as u32).filter(|id| (bits >> id) & 1 == 1).collect(),
+        ),
+    }
+}
+
+/// 観測された数値の分位点から numeric_to_threshold_bits 用の閾値を求める。
+///
+/// `n_bins` 個の区間にほぼ同数ずつ入るよう `n_bins - 1` 個の閾値を返す。
+/// 値の種類が少なく同じ閾値が重なる場合は重複を除くので、返る閾値はそれより少なくなる。
+pub fn auto_thresholds(
+    sources: &[SourceEntity],
+    key: &SourceEntityAttributeKey,
+    n_bins: usize,
+) -> Vec<i64> {
+    let mut values: Vec<i64> = sources
+        .iter()
+        .filter_map(|s| s.attributes.get(key))
+        .filter_map(|v| v.as_number().ok())
+        .collect();
+    if values.is_empty() || n_bins < 2 {
+        return Vec::new();
+    }
+    values.sort_unstable();
+
+    let mut thresholds: Vec<i64> = (1..n_bins)
+        .map(|i| values[(i * values.len()).div_ceil(n_bins) - 1])
+        .collect();
+    thresholds.dedup();
+    // 最大値を閾値にしても区間は増えない
+    if thresholds.last() == values.last() {
+        thresholds.pop();
+    }
+    thresholds
+}
+
+/// 数値を閾値列に対する温度計型のビット列に変換する。
+///
+/// `thresholds` は昇順で、要素数は 32 以下であること。
+/// ビット i (`1 << i`) は `value <= thresholds[i]` のときに限り立つ。
+/// 例えば `thresholds = [0, 50, 80]` のとき、
+/// `-5 -> 0b111`, `30 -> 0b110`, `60 -> 0b100`, `90 -> 0b000` となる。
+pub fn numeric_to_threshold_bits(value: i64, thresholds: &[i64]) -> Result<u32, String> {
+    if thresholds.len() > 32 {
+        return Err(format!("Too many thresholds: {} (max 32)", thresholds.len()));
+    }
+    let mut bits = 0u32;
+    for (i, &t) in thresholds.iter().enumerate() {
+        if value <= t {
+            bits |= 1u32 << i;
+        }
+    }
+    Ok(bits)
+}
+
+/// `value >= required_ge` を満たすために 0 でなければならないビットのマスクを返す。
+///
+/// `numeric_to_threshold_bits` と同じビット配置で、`thresholds[i] < required_ge`
+/// となるビット i が立つ。ソース側のビット列 `b` は `b & mask == 0` のとき要件を満たす。
+/// `required_ge - 1` が閾値に含まれる場合に厳密で、そうでなければ
+/// 閾値の粒度まで緩く（過大に）近似される。
+pub fn requirement_ge_to_threshold_bits(required_ge: i64, thresholds: &[i64]) -> Result<u32, String> {
+    if thresholds.len() > 32 {
+        return Err(format!("Too many thresholds: {} (max 32)", thresholds.len()));
+    }
+    let mut mask = 0u32;
+    for (i, &t) in thresholds.iter().enumerate() {
+        if t < required_ge {
+            mask |= 1u32 << i;
+        }
+    }
+    Ok(mask)
+}
+
+/// `numeric_to_threshold_bits` の逆変換。ビット列から値が取り得る範囲を
+/// 両端を含む `(下限, 上限)` として返す。`None` はその方向に制限が無いことを表す。
+///
+/// 最下位の立っているビットを j とすると範囲は `thresholds[j-1] + 1 ..= thresholds[j]`。
+/// ビットが一つも立っていなければ値は最大の閾値より大きい。
+/// 下限が i64 に収まらない (最大の閾値が i64::MAX で、どの値からも作られない) ビット列はエラー
+pub fn threshold_bits_to_range(bits: u32, thresholds: &[i64]) -> Result<(Option<i64>, Option<i64>), String> {
+    let above = |t: i64| t.checked_add(1).ok_or_else(|| format!("No value is greater than the threshold {}", t));
+    let lowest_set = (0..thresholds.len().min(32)).find(|&i| (bits >> i) & 1 == 1);
+    Ok(match lowest_set {
+        Some(0) => (None, Some(thresholds[0])),
+        Some(j) => (Some(above(thresholds[j - 1])?), Some(thresholds[j])),
+        None => (thresholds.last().map(|&t| above(t)).transpose()?, None),
+    })
+}
+
 pub fn u32_to_bit_string(b: u32) -> String {
-    (0..32).rev().map(|i| if (b >> i) & 1 == 1 { '1' } else { '0' }).collect()
+    u32_to_bit_string_with_width(b, MAX_ATTR_WIDTH)
+}
+
+/// 下位 width ビットだけを文字列にする (attr_width と組み合わせて使う)
+pub fn u32_to_bit_string_with_width(b: u32, width: usize) -> String {
+    (0..width.min(MAX_ATTR_WIDTH)).rev().map(|i| if (b >> i) & 1 == 1 { '1' } else { '0' }).collect()
+}
+
+/// u32_to_bit_string(_with_width) の逆。1〜MAX_ATTR_WIDTH 文字の '0' / '1' だけを受け付ける
+pub fn bit_string_to_u32(s: &str) -> Result<u32, String> {
+    if s.is_empty() || s.len() > MAX_ATTR_WIDTH {
+        return Err(format!("Bit string must be 1 to {} bits long, got {}", MAX_ATTR_WIDTH, s.len()));
+    }
+    s.chars().try_fold(0u32, |acc, c| match c {
+        '0' => Ok(acc << 1),
+        '1' => Ok((acc << 1) | 1),
+        _ => Err(format!("Bit string contains non-binary character {:?}: {}", c, s)),
+    })
+}
+
+/// packed_source_key で作ったキーを属性ごとの EncodedAttributeValue に戻す。
+/// has_threshold なら末尾の閾値ブロックも返す。
+/// packed_source_key は持っていない属性を 0 で埋めるので、それらは ID 0 (multiple なら空集合) として戻る
+pub fn bit_arrays_to_encoded(
+    map: &AttrIdMap,
+    attr_order: &[&str],
+    key: &str,
+    has_threshold: bool,
+) -> Result<(HashMap<SourceEntityAttributeKey, EncodedAttributeValue>, Option<u32>), String> {
+    // 属性ごとに切り出す前に検査しておく (マルチバイト文字で split_at しないように)
+    if let Some(c) = key.chars().find(|c| !matches!(c, '0' | '1')) {
+        return Err(format!("Key contains non-binary character {:?}", c));
+    }
+    let expected = total_key_width(map, attr_order, has_threshold);
+    if key.len() != expected {
+        return Err(format!("Key must be {} bits long, got {}", expected, key.len()));
+    }
+    let mut out = HashMap::new();
+    let mut rest = key;
+    for &name in attr_order {
+        let entry = map.entry(name).ok_or_else(|| format!("Unknown attr: {}", name))?;
+        let (bits, tail) = rest.split_at(map.attr_width(name));
+        rest = tail;
+        if bits.is_empty() {
+            continue;
+        }
+        let u = bit_string_to_u32(bits).map_err(|e| format!("{}: {}", name, e))?;
+        out.insert(SourceEntity::parse_attribute_key(name)?, u32_to_encoded_value(entry, u));
+    }
+    let threshold = if has_threshold { Some(bit_string_to_u32(rest)?) } else { None };
+    Ok((out, threshold))
+}
+
+/// attr_order にある属性をエンティティが持っていないときの扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingAttribute {
+    /// 出力から除く（出力が attr_order より短くなる）
+    Skip,
+    /// 全ビット 0 のビット列で埋める
+    ZeroFill,
+    /// エラーにする
+    Error,
 }
 
 pub fn encoded_source_to_bit_arrays(
     map: &AttrIdMap,
     encoded: &HashMap<SourceEntityAttributeKey, EncodedAttributeValue>,
     attr_order: &[&str],
+) -> Result<Vec<String>, String> {
+    encoded_source_to_bit_arrays_with(map, encoded, attr_order, MissingAttribute::Skip, &HashMap::new())
+}
+
+/// 各属性を field_width ビットの文字列にする。numeric_thresholds に閾値がある数値属性は
+/// numeric_to_threshold_bits で温度計型にする。
+/// ZeroFill / Error を指定すると、出力は常に attr_order と同じ並び・長さになり
+/// 同じ numeric_thresholds を渡した requirements_to_bit_arrays の結果と位置・幅が揃う
+pub fn encoded_source_to_bit_arrays_with(
+    map: &AttrIdMap,
+    encoded: &HashMap<SourceEntityAttributeKey, EncodedAttributeValue>,
+    attr_order: &[&str],
+    missing: MissingAttribute,
+    numeric_thresholds: &HashMap<String, Vec<i64>>,
 ) -> Result<Vec<String>, String> {
     let mut out = Vec::with_capacity(attr_order.len());
     for &name in attr_order {
+        let width = map.field_width(name, numeric_thresholds);
         let key = SourceEntity::parse_attribute_key(name)?;
-        let Some(val) = encoded.get(&key) else { continue };
-        let entry = map.entries.get(name).ok_or_else(|| format!("Unknown attr: {}", name))?;
-        let u = encoded_value_to_u32(entry, val)?;
-        out.push(u32_to_bit_string(u));
+        let Some(val) = encoded.get(&key) else {
+            match missing {
+                MissingAttribute::Skip => continue,
+                MissingAttribute::ZeroFill => {
+                    out.push("0".repeat(width));
+                    continue;
+                }
+                MissingAttribute::Error => {
+                    return Err(format!("Source is missing attribute: {}", name));
+                }
+            }
+        };
+        let entry = map.entry(name).ok_or_else(|| format!("Unknown attr: {}", name))?;
+        let u = match (val, numeric_thresholds.get(name)) {
+            (EncodedAttributeValue::Numeric(n), Some(thresholds)) => numeric_to_threshold_bits(*n, thresholds)?,
+            _ => encoded_value_to_u32(entry, val)?,
+        };
+        out.push(u32_to_bit_string_with_width(u, width));
     }
     Ok(out)
 }
 
+/// packed_source_key が作るキーの長さ。
+/// attr_order の各属性の attr_width の合計に、has_threshold なら
+/// 温度計型の閾値ブロック (MAX_ATTR_WIDTH ビット) を足したもの
+pub fn total_key_width(map: &AttrIdMap, source_attr_order: &[&str], has_threshold: bool) -> usize {
+    let attrs: usize = source_attr_order.iter().map(|name| map.attr_width(name)).sum();
+    if has_threshold { attrs + MAX_ATTR_WIDTH } else { attrs }
+}
+
+/// 各属性を attr_width ビットに詰めて連結した 1 本のキーを作る。
+/// 持っていない属性は 0 で埋め、threshold_bits があれば末尾に閾値ブロックを付ける。
+/// 長さは常に total_key_width と一致する
+pub fn packed_source_key(
+    map: &AttrIdMap,
+    encoded: &HashMap<SourceEntityAttributeKey, EncodedAttributeValue>,
+    attr_order: &[&str],
+    threshold_bits: Option<u32>,
+) -> Result<String, String> {
+    let mut key = String::with_capacity(total_key_width(map, attr_order, threshold_bits.is_some()));
+    for &name in attr_order {
+        let entry = map.entry(name).ok_or_else(|| format!("Unknown attr: {}", name))?;
+        let width = map.attr_width(name);
+        let u = match encoded.get(&SourceEntity::parse_attribute_key(name)?) {
+            Some(val) => encoded_value_to_u32(entry, val)?,
+            None => 0,
+        };
+        if width < MAX_ATTR_WIDTH && u >> width != 0 {
+            return Err(format!("Value {} of {} does not fit in {} bits", u, name, width));
+        }
+        key.push_str(&u32_to_bit_string_with_width(u, width));
+    }
+    if let Some(bits) = threshold_bits {
+        key.push_str(&u32_to_bit_string(bits));
+    }
+    Ok(key)
+}
+
 fn requirement_to_bits(
     map: &AttrIdMap,
     attr_name: &str,
@@ -239,7 +803,7 @@ fn requirement_to_bits(
     let mut containment_bits: Option<u32> = None;
     let mut numeric_bits: Option<u32> = None;
 
-    for r in reqs {
+    for r in flatten_src_requirements(reqs) {
         match r {
             SrcRequirement::Exact { attr, value } if attr.as_str() == attr_name => {
                 let encoded = encode_value(map, attr, value)?;
@@ -255,23 +819,42 @@ fn requirement_to_bits(
             }
             SrcRequirement::Containment { attr, allowed_set } if attr.as_str() == attr_name => {
                 let mut bits = 0u32;
-                for s in allowed_set {
-                    let id = map.value_to_id(attr, s)?;
-                    if id >= 32 {
+                for id in map.resolve_ids(attr, allowed_set, false)? {
+                    if id as usize >= MAX_ATTR_WIDTH {
                         return Err(format!("Attribute id {} does not fit in 32 bits", id));
                     }
                     bits |= 1u32 << id;
                 }
                 containment_bits = Some(containment_bits.map_or(bits, |x| x | bits));
             }
-            SrcRequirement::Numeric { attr, required_ge, required_lt } if attr.as_str() == attr_name => {
-                let ge_val = required_ge.iter().max().copied().unwrap_or(0);
-                let lt_val = required_lt.iter().max().copied().unwrap_or(i64::MAX);
-
-                let ge_u = ge_val.clamp(0, 0xFFFFi64) as u32;
-                let lt_u = lt_val.clamp(0, 0xFFFFi64) as u32;
-                let bits = (lt_u << 16) | ge_u;
-                numeric_bits = Some(numeric_bits.map_or(bits, |a| a & bits));
+            SrcRequirement::Numeric { attr, .. } if attr.as_str() == attr_name && numeric_bits.is_none() => {
+                // AND は共通部分、OR は和集合を取る。1 つの区間しか符号化できないので、
+                // 離れた区間の OR を外側の区間に広げることはせずエラーにする
+                let bands = merge_numeric_requirements(reqs, attr_name);
+                if bands.len() > 1 {
+                    return Err(format!(
+                        "Numeric requirement on {} has {} disjoint bands; only a single band can be encoded",
+                        attr_name,
+                        bands.len()
+                    ));
+                }
+                // 下限が無い区間は属性の最小値から始まるものとする。
+                // 満たせない (空の) 区間はできるだけ大きな下限で近似する
+                let entry = map.entry(attr_name);
+                let ge = match bands.first() {
+                    Some(band) => band.ge.unwrap_or(entry.and_then(|e| e.numeric_min).unwrap_or(0)),
+                    None => i64::MAX,
+                };
+                numeric_bits = Some(match numeric_thresholds.get(attr_name) {
+                    Some(thresholds) => requirement_ge_to_threshold_bits(ge, thresholds)?,
+                    None => {
+                        // 値と同じく offset-binary にそろえ、フィールド幅に収める
+                        let offset = entry.map_or(0, |e| e.numeric_offset());
+                        let width = map.attr_width(attr_name).min(MAX_ATTR_WIDTH);
+                        let field_max = u32::MAX.checked_shr((MAX_ATTR_WIDTH - width) as u32).unwrap_or(0) as i64;
+                        ge.saturating_sub(offset).clamp(0, field_max) as u32
+                    }
+                });
             }
             _ => {}
         }
@@ -289,6 +872,9 @@ fn requirement_to_bits(
     Ok(None)
 }
 
+/// 要求を attr_order の属性ごとに field_width ビットの文字列にする。
+/// 数値の要求は区間の下限だけを表す (閾値があれば requirement_ge_to_threshold_bits のマスク、
+/// 無ければ offset-binary の下限値)。上限は表せないので無視され、過大評価になる
 pub fn requirements_to_bit_arrays(
     map: &AttrIdMap,
     requirements: &[SrcRequirement],
@@ -298,10 +884,477 @@ pub fn requirements_to_bit_arrays(
     let mut out = Vec::with_capacity(attr_order.len());
     for &name in attr_order {
         let bits = requirement_to_bits(map, name, requirements, numeric_thresholds)?;
-        let s = bits
-            .map(u32_to_bit_string)
-            .unwrap_or_else(|| "0".repeat(32));
-        out.push(s);
+        out.push(u32_to_bit_string_with_width(bits.unwrap_or(0), map.field_width(name, numeric_thresholds)));
     }
     Ok(out)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn from_reader_matches_path_loader() {
+        let path = "data/ip_based_abac_attr_id.json";
+        let text = std::fs::read_to_string(path).unwrap();
+        let from_cursor = AttrIdMap::from_reader(Cursor::new(text)).unwrap();
+        let from_path = AttrIdMap::load(path).unwrap();
+        assert_eq!(from_cursor.entries, from_path.entries);
+    }
+
+    #[test]
+    fn vocabulary_size_from_map() {
+        let map = AttrIdMap::load("data/ip_based_abac_attr_id.json").unwrap();
+        assert_eq!(map.vocabulary_size("Src.Role"), Some(6));
+        assert_eq!(map.vocabulary_size("Src.TrustScore"), Some(100));
+        assert_eq!(map.vocabulary_size("Src.Unknown"), None);
+    }
+
+    #[test]
+    fn values_to_ids_reports_unknown_values() {
+        let map = AttrIdMap::load("data/ip_based_abac_attr_id.json").unwrap();
+
+        let (ids, unknown) = map.values_to_ids("Src.Role", &["Student", "Pirate", "Admin", "Ghost"]);
+        assert_eq!(ids, vec![0, 2]);
+        assert_eq!(unknown, vec!["Pirate".to_string(), "Ghost".to_string()]);
+
+        let err = encode_value(
+            &map,
+            "Src.Groups",
+            &AttributeValue::Set(vec!["Faculty".into(), "Pirates".into(), "Ghosts".into()]),
+        )
+        .unwrap_err();
+        assert!(err.contains("Pirates") && err.contains("Ghosts"), "{}", err);
+    }
+
+    #[test]
+    fn unknown_values_map_to_configured_bucket() {
+        let json = r#"{
+            "Src.Role": {
+                "description": { "type": "single", "unknown_id": 7 },
+                "value": { "0": "Student", "1": "Admin" }
+            },
+            "Src.Groups": {
+                "description": { "type": "multiple", "unknown_id": 2 },
+                "value": { "0": "Faculty", "1": "Staff" }
+            }
+        }"#;
+        let map = AttrIdMap::from_reader(json.as_bytes()).unwrap();
+        assert_eq!(map.entity_value_to_id("Src.Role", "Admin"), Ok(1));
+        assert_eq!(map.entity_value_to_id("Src.Role", "Pirate"), Ok(7));
+        assert_eq!(map.attr_width("Src.Role"), 3);
+
+        let groups = AttributeValue::Set(vec!["Faculty".into(), "Pirates".into()]);
+        let encoded = encode_entity_value(&map, "Src.Groups", &groups).unwrap();
+        assert_eq!(encoded, EncodedAttributeValue::MultipleIds(vec![0, 2]));
+
+        // ポリシーのリテラルや要求は未知の値を受け付けない
+        assert!(map.value_to_id("Src.Role", "Pirate").is_err());
+        assert!(encode_value(&map, "Src.Groups", &groups).is_err());
+        let requirement = [SrcRequirement::Containment {
+            attr: "Src.Groups".to_string(),
+            allowed_set: vec!["Pirates".to_string()],
+        }];
+        assert!(requirements_to_bit_arrays(&map, &requirement, &["Src.Groups"], &HashMap::new()).is_err());
+
+        // 既存の ID と重なる unknown_id は読み込み時にエラー
+        let err = AttrIdMap::from_reader(
+            r#"{ "Src.Role": { "description": { "type": "single", "unknown_id": 1 }, "value": { "0": "Student", "1": "Admin" } } }"#
+                .as_bytes(),
+        )
+        .err()
+        .unwrap();
+        assert!(err.contains("collides with value 'Admin'"), "{}", err);
+
+        // 符号化できない既定値も読み込み時にエラー
+        let err = AttrIdMap::from_reader(
+            r#"{ "Src.Role": { "description": { "type": "single", "default": "Pirate" }, "value": { "0": "Student" } } }"#
+                .as_bytes(),
+        )
+        .err()
+        .unwrap();
+        assert!(err.starts_with("Src.Role: description.default:"), "{}", err);
+
+        // 未設定ならこれまで通りエラー
+        let strict = AttrIdMap::load("data/ip_based_abac_attr_id.json").unwrap();
+        assert!(strict.value_to_id("Src.Role", "Pirate").is_err());
+    }
+
+    #[test]
+    fn total_key_width_matches_packed_key() {
+        let map = AttrIdMap::load("data/ip_based_abac_attr_id.json").unwrap();
+        let order = ["Src.Role", "Src.Dept", "Src.TrustScore", "Src.Groups"];
+        let src = SourceEntity::from_json_value(&serde_json::json!({
+            "ip": "10.0.0.1",
+            "attributes": { "Src.Role": "Admin", "Src.TrustScore": 90, "Src.Groups": ["Faculty"] }
+        }))
+        .unwrap();
+        let encoded = encode_source_entity(&map, &src).unwrap();
+
+        let width = total_key_width(&map, &order, false);
+        assert_eq!(width, 3 + 4 + 7 + map.attr_width("Src.Groups"));
+        assert_eq!(packed_source_key(&map, &encoded, &order, None).unwrap().len(), width);
+
+        let thresholds = numeric_to_threshold_bits(90, &[0, 50, 80]).unwrap();
+        let key = packed_source_key(&map, &encoded, &order, Some(thresholds)).unwrap();
+        assert_eq!(key.len(), total_key_width(&map, &order, true));
+    }
+
+    #[test]
+    fn describe_decodes_ids() {
+        let map = AttrIdMap::load("data/ip_based_abac_attr_id.json").unwrap();
+
+        let role = EncodedAttributeValue::SingleId(2);
+        assert_eq!(role.to_string(), "SingleId(2)");
+        assert_eq!(describe(&map, "Src.Role", &role), r#"SingleId(2) => "Admin""#);
+
+        let groups = EncodedAttributeValue::MultipleIds(vec![0, 8, 99]);
+        assert_eq!(
+            describe(&map, "Src.Groups", &groups),
+            r#"MultipleIds([0, 8, 99]) => {"Undergrad", "Faculty", <unknown id 99>}"#
+        );
+        assert_eq!(describe(&map, "Src.TrustScore", &EncodedAttributeValue::Numeric(7)), "Numeric(7)");
+    }
+
+    #[test]
+    fn auto_thresholds_balance_bins() {
+        let sources: Vec<SourceEntity> = (0..100)
+            .map(|i| {
+                SourceEntity::from_json_value(&serde_json::json!({
+                    "ip": format!("10.0.0.{}", i),
+                    "attributes": { "Src.TrustScore": i }
+                }))
+                .unwrap()
+            })
+            .collect();
+        let key = SourceEntityAttributeKey::TrustScore;
+
+        let thresholds = auto_thresholds(&sources, &key, 4);
+        assert_eq!(thresholds, vec![24, 49, 74]);
+
+        let mut per_band: HashMap<u32, usize> = HashMap::new();
+        for i in 0..100 {
+            *per_band.entry(numeric_to_threshold_bits(i, &thresholds).unwrap()).or_insert(0) += 1;
+        }
+        assert_eq!(per_band.len(), 4);
+        assert!(per_band.values().all(|&n| n == 25));
+
+        // 値が 2 種類しかなければ閾値は 1 つ
+        let few: Vec<SourceEntity> = [1, 1, 1, 2]
+            .iter()
+            .map(|score| {
+                SourceEntity::from_json_value(&serde_json::json!({
+                    "ip": "10.0.1.1", "attributes": { "Src.TrustScore": score }
+                }))
+                .unwrap()
+            })
+            .collect();
+        assert_eq!(auto_thresholds(&few, &key, 4), vec![1]);
+        assert!(auto_thresholds(&[], &key, 4).is_empty());
+    }
+
+    #[test]
+    fn strict_bit_arrays_keep_attribute_layout() {
+        let map = AttrIdMap::load("data/ip_based_abac_attr_id.json").unwrap();
+        let source = SourceEntity::from_json_value(&serde_json::json!({
+            "ip": "10.0.0.1",
+            "attributes": { "Src.Role": "Admin", "Src.Groups": ["Faculty"] }
+        }))
+        .unwrap();
+        let encoded = encode_source_entity(&map, &source).unwrap();
+        let order = ["Src.Role", "Src.TrustScore", "Src.Groups"];
+
+        let skipped = encoded_source_to_bit_arrays(&map, &encoded, &order).unwrap();
+        assert_eq!(skipped.len(), 2);
+
+        let no_thresholds = HashMap::new();
+        let filled =
+            encoded_source_to_bit_arrays_with(&map, &encoded, &order, MissingAttribute::ZeroFill, &no_thresholds).unwrap();
+        assert_eq!(filled.len(), order.len());
+        assert_eq!(filled[1], "0".repeat(map.attr_width("Src.TrustScore")));
+        assert_eq!(filled[0], skipped[0]);
+        assert_eq!(filled[2], skipped[1]);
+        // 各フィールドが attr_width なので、連結すると packed_source_key と一致する
+        assert_eq!(filled.concat(), packed_source_key(&map, &encoded, &order, None).unwrap());
+        assert_eq!(filled.concat().len(), total_key_width(&map, &order, false));
+
+        let err = encoded_source_to_bit_arrays_with(&map, &encoded, &order, MissingAttribute::Error, &no_thresholds)
+            .unwrap_err();
+        assert!(err.contains("Src.TrustScore"));
+    }
+
+    #[test]
+    fn source_and_requirement_fields_have_the_same_widths() {
+        let map = AttrIdMap::load("data/ip_based_abac_attr_id.json").unwrap();
+        let source = SourceEntity::from_json_value(&serde_json::json!({
+            "ip": "10.0.0.1",
+            "attributes": { "Src.Role": "Admin", "Src.TrustScore": 85, "Src.Groups": ["Faculty"] }
+        }))
+        .unwrap();
+        let encoded = encode_source_entity(&map, &source).unwrap();
+        let order = ["Src.Role", "Src.TrustScore", "Src.Groups"];
+        let requirements = [SrcRequirement::Numeric {
+            attr: "Src.TrustScore".to_string(),
+            required_ge: vec![80],
+            required_lt: vec![],
+        }];
+
+        let mut thresholds = HashMap::new();
+        for with_thresholds in [false, true] {
+            if with_thresholds {
+                thresholds.insert("Src.TrustScore".to_string(), vec![0, 50, 80]);
+            }
+            let src = encoded_source_to_bit_arrays_with(&map, &encoded, &order, MissingAttribute::ZeroFill, &thresholds)
+                .unwrap();
+            let req = requirements_to_bit_arrays(&map, &requirements, &order, &thresholds).unwrap();
+            let widths: Vec<usize> = order.iter().map(|name| map.field_width(name, &thresholds)).collect();
+            assert_eq!(src.iter().map(String::len).collect::<Vec<_>>(), widths);
+            assert_eq!(req.iter().map(String::len).collect::<Vec<_>>(), widths);
+        }
+
+        // 閾値の数がビット数になり、要求はソースのビットと重ならないマスクになる
+        assert_eq!(map.field_width("Src.TrustScore", &thresholds), 3);
+        let src = encoded_source_to_bit_arrays_with(&map, &encoded, &order, MissingAttribute::ZeroFill, &thresholds)
+            .unwrap();
+        let req = requirements_to_bit_arrays(&map, &requirements, &order, &thresholds).unwrap();
+        assert_eq!((src[1].as_str(), req[1].as_str()), ("000", "011"));
+        assert_eq!(bit_string_to_u32(&src[1]).unwrap() & bit_string_to_u32(&req[1]).unwrap(), 0);
+
+        // 閾値が無ければ attr_width ビットの下限値
+        let req = requirements_to_bit_arrays(&map, &requirements, &order, &HashMap::new()).unwrap();
+        assert_eq!(req[1], "1010000");
+        assert_eq!(req.concat().len(), total_key_width(&map, &order, false));
+    }
+
+    #[test]
+    fn numeric_coded_single_accepts_number_and_string() {
+        let map = AttrIdMap::from_reader(Cursor::new(
+            r#"{ "Dst.VlanId": { "description": { "type": "single" }, "value": { "0": "10", "1": 20 } } }"#,
+        ))
+        .unwrap();
+
+        for (input, id) in [
+            (AttributeValue::Number(10), 0),
+            (AttributeValue::String("10".into()), 0),
+            (AttributeValue::Number(20), 1),
+            (AttributeValue::String("20".into()), 1),
+        ] {
+            assert_eq!(encode_value(&map, "Dst.VlanId", &input), Ok(EncodedAttributeValue::SingleId(id)));
+        }
+        assert!(encode_value(&map, "Dst.VlanId", &AttributeValue::Number(30)).is_err());
+    }
+
+    #[test]
+    fn attr_width_per_value_type() {
+        let map = AttrIdMap::load("data/ip_based_abac_attr_id.json").unwrap();
+        // ID 0..=5
+        assert_eq!(map.attr_width("Src.Role"), 3);
+        // ID 0..=11
+        assert_eq!(map.attr_width("Src.Dept"), 4);
+        // ID 0..=23 をそれぞれ 1 ビットで
+        assert_eq!(map.attr_width("Src.Groups"), 24);
+        // max 99
+        assert_eq!(map.attr_width("Src.TrustScore"), 7);
+        assert_eq!(map.attr_width("Src.Unknown"), 0);
+        assert!(map.validate_widths().is_ok());
+
+        assert_eq!(u32_to_bit_string_with_width(0b101, map.attr_width("Src.Role")), "101");
+
+        let values: serde_json::Map<String, Value> =
+            (0..40).map(|i| (i.to_string(), Value::from(format!("g{}", i)))).collect();
+        let wide = AttrIdMap::from_reader(Cursor::new(
+            serde_json::json!({ "Src.Groups": { "description": { "type": "multiple" }, "value": values } }).to_string(),
+        ))
+        .unwrap();
+        assert_eq!(wide.attr_width("Src.Groups"), 40);
+        assert!(wide.validate_widths().unwrap_err().contains("Src.Groups"));
+    }
+
+    #[test]
+    fn signed_numeric_round_trips_with_offset_binary() {
+        let map = AttrIdMap::from_reader(Cursor::new(
+            r#"{ "Src.TrustDelta": { "description": { "type": "numeric" }, "value": { "min": -100, "max": 100 } } }"#,
+        ))
+        .unwrap();
+        let entry = &map.entries["Src.TrustDelta"];
+
+        for value in [-100, -37, 0, 100] {
+            let encoded = encode_value(&map, "Src.TrustDelta", &AttributeValue::Number(value)).unwrap();
+            let bits = encoded_value_to_u32(entry, &encoded).unwrap();
+            assert_eq!(bits as i64, value + 100);
+            assert_eq!(u32_to_numeric(entry, bits), value);
+        }
+        assert!(encode_value(&map, "Src.TrustDelta", &AttributeValue::Number(-101)).is_err());
+        // -100..=100 は 0..=200 として符号化されるので 8 ビット
+        assert_eq!(map.attr_width("Src.TrustDelta"), 8);
+
+        // 下限の無い要求は offset 後の 0 (= min) から始まる
+        let numeric = |ge: Vec<i64>, lt: Vec<i64>| {
+            let reqs = [SrcRequirement::Numeric { attr: "Src.TrustDelta".to_string(), required_ge: ge, required_lt: lt }];
+            requirements_to_bit_arrays(&map, &reqs, &["Src.TrustDelta"], &HashMap::new()).unwrap()
+        };
+        assert_eq!(numeric(vec![], vec![10]), vec!["00000000"]);
+        assert_eq!(numeric(vec![-20], vec![]), vec!["01010000"]);
+
+        let extreme = AttrIdMap::from_reader(Cursor::new(
+            r#"{ "Src.Wide": { "description": { "type": "numeric" }, "value": { "min": -9223372036854775808, "max": 9223372036854775807 } } }"#,
+        ))
+        .unwrap();
+        assert_eq!(extreme.attr_width("Src.Wide"), 64);
+
+        // 下限が 0 以上なら従来どおりそのままの値
+        let trust = AttrIdMap::load("data/ip_based_abac_attr_id.json").unwrap();
+        let entry = &trust.entries["Src.TrustScore"];
+        assert_eq!(encoded_value_to_u32(entry, &EncodedAttributeValue::Numeric(42)), Ok(42));
+        assert_eq!(numeric_to_threshold_bits(-37, &[-50, 0, 50]), Ok(0b110));
+    }
+
+    #[test]
+    fn threshold_bits_round_trip_across_bands() {
+        let thresholds = [0i64, 50, 80];
+
+        assert_eq!(numeric_to_threshold_bits(-5, &thresholds), Ok(0b111));
+        assert_eq!(numeric_to_threshold_bits(30, &thresholds), Ok(0b110));
+        assert_eq!(numeric_to_threshold_bits(60, &thresholds), Ok(0b100));
+        assert_eq!(numeric_to_threshold_bits(90, &thresholds), Ok(0b000));
+
+        assert_eq!(threshold_bits_to_range(0b111, &thresholds), Ok((None, Some(0))));
+        assert_eq!(threshold_bits_to_range(0b110, &thresholds), Ok((Some(1), Some(50))));
+        assert_eq!(threshold_bits_to_range(0b100, &thresholds), Ok((Some(51), Some(80))));
+        assert_eq!(threshold_bits_to_range(0b000, &thresholds), Ok((Some(81), None)));
+        assert_eq!(threshold_bits_to_range(0b10, &[0, i64::MAX]), Ok((Some(1), Some(i64::MAX))));
+        assert!(threshold_bits_to_range(0b00, &[0, i64::MAX]).is_err());
+
+        for value in -10..=100 {
+            let bits = numeric_to_threshold_bits(value, &thresholds).unwrap();
+            let (lo, hi) = threshold_bits_to_range(bits, &thresholds).unwrap();
+            assert!(lo.is_none_or(|lo| lo <= value), "value {} below {:?}", value, lo);
+            assert!(hi.is_none_or(|hi| value <= hi), "value {} above {:?}", value, hi);
+        }
+    }
+
+    #[test]
+    fn ge_requirement_matches_threshold_encoding() {
+        let thresholds = [0i64, 49, 79];
+        let mask = requirement_ge_to_threshold_bits(80, &thresholds).unwrap();
+        for value in -10..=100 {
+            let bits = numeric_to_threshold_bits(value, &thresholds).unwrap();
+            assert_eq!(bits & mask == 0, value >= 80, "value {}", value);
+        }
+    }
+
+    #[test]
+    fn builder_matches_json_loaded_map() {
+        let built = AttrIdMap::builder()
+            .single("Src.Role", &["Student", "Staff", "Admin"])
+            .multiple("Src.Groups", &["Lab", "Club"])
+            .numeric("Src.TrustScore", 0, 100)
+            .build();
+        let loaded = AttrIdMap::from_reader(r#"{
+            "Src.Role": { "description": { "type": "single" }, "value": { "0": "Student", "1": "Staff", "2": "Admin" } },
+            "Src.Groups": { "description": { "type": "multiple" }, "value": { "0": "Lab", "1": "Club" } },
+            "Src.TrustScore": { "description": { "type": "numeric" }, "value": { "min": 0, "max": 100 } }
+        }"#.as_bytes()).unwrap();
+        assert_eq!(built.entries, loaded.entries);
+
+        let groups = AttributeValue::new_set(["Club".to_string(), "Lab".to_string()]);
+        assert_eq!(
+            encode_value(&built, "Src.Groups", &groups),
+            encode_value(&loaded, "Src.Groups", &groups)
+        );
+        assert_eq!(
+            encode_value(&built, "Src.Role", &AttributeValue::String("Admin".to_string())),
+            Ok(EncodedAttributeValue::SingleId(2))
+        );
+    }
+
+    #[test]
+    fn multiple_attribute_accepts_bare_string() {
+        let map = AttrIdMap::builder().multiple("Src.Groups", &["Lab", "Club"]).build();
+        let from_string = encode_value(&map, "Src.Groups", &AttributeValue::String("Club".to_string()));
+        let from_set = encode_value(&map, "Src.Groups", &AttributeValue::new_set(["Club".to_string()]));
+        assert_eq!(from_string, Ok(EncodedAttributeValue::MultipleIds(vec![1])));
+        assert_eq!(from_string, from_set);
+        assert!(encode_value(&map, "Src.Groups", &AttributeValue::String("Band".to_string())).is_err());
+    }
+
+    #[test]
+    fn bit_string_round_trips() {
+        assert_eq!(bit_string_to_u32("101"), Ok(5));
+        assert_eq!(bit_string_to_u32(&u32_to_bit_string(u32::MAX)), Ok(u32::MAX));
+        assert!(bit_string_to_u32("").is_err());
+        assert!(bit_string_to_u32(&"0".repeat(MAX_ATTR_WIDTH + 1)).is_err());
+        assert!(bit_string_to_u32("10x1").unwrap_err().contains("non-binary"));
+    }
+
+    #[test]
+    fn packed_key_decodes_back_to_encoded_values() {
+        let map = AttrIdMap::builder()
+            .single("Src.Role", &["Student", "Staff", "Admin"])
+            .multiple("Src.Groups", &["Lab", "Club", "Band"])
+            .numeric("Src.TrustScore", 0, 100)
+            .build();
+        let order = ["Src.Role", "Src.Groups", "Src.TrustScore"];
+        let encoded: HashMap<SourceEntityAttributeKey, EncodedAttributeValue> = [
+            ("Src.Role", EncodedAttributeValue::SingleId(2)),
+            ("Src.Groups", EncodedAttributeValue::MultipleIds(vec![0, 2])),
+            ("Src.TrustScore", EncodedAttributeValue::Numeric(73)),
+        ]
+        .into_iter()
+        .map(|(name, v)| (SourceEntity::parse_attribute_key(name).unwrap(), v))
+        .collect();
+
+        let key = packed_source_key(&map, &encoded, &order, Some(0b11)).unwrap();
+        assert_eq!(bit_arrays_to_encoded(&map, &order, &key, true), Ok((encoded, Some(0b11))));
+
+        assert!(bit_arrays_to_encoded(&map, &order, &key[1..], true).unwrap_err().contains("bits long"));
+        let garbled = key.replacen('1', "2", 1);
+        assert!(bit_arrays_to_encoded(&map, &order, &garbled, true).unwrap_err().contains("non-binary"));
+    }
+
+    #[test]
+    fn in_range_for_each_bound_combination() {
+        assert_eq!(in_range(5, Some(0), Some(10)), Ok(()));
+        assert_eq!(in_range(11, Some(0), Some(10)), Err("Numeric value 11 out of range [0, 10]".to_string()));
+        assert_eq!(in_range(-1, Some(0), None), Err("Numeric value -1 out of range [0, unbounded]".to_string()));
+        assert_eq!(in_range(i64::MAX, Some(0), None), Ok(()));
+        assert_eq!(in_range(11, None, Some(10)), Err("Numeric value 11 out of range [unbounded, 10]".to_string()));
+        assert_eq!(in_range(i64::MIN, None, Some(10)), Ok(()));
+        assert_eq!(in_range(i64::MIN, None, None), Ok(()));
+        assert_eq!(in_range(i64::MAX, None, None), Ok(()));
+
+        let map = AttrIdMap::builder().numeric("Src.TrustScore", 0, 100).build();
+        let err = encode_value(&map, "Src.TrustScore", &AttributeValue::Number(101)).unwrap_err();
+        assert_eq!(err, "Src.TrustScore: Numeric value 101 out of range [0, 100]");
+    }
+
+    #[test]
+    fn feeds_on_different_scales_encode_the_same() {
+        let map = AttrIdMap::from_reader(r#"{
+            "Src.TrustScore": { "description": { "type": "numeric" }, "value": { "min": 0, "max": 100 } }
+        }"#.as_bytes()).unwrap();
+        // scale は属性ではなく読み込むフィードごとに決める
+        let encode = |score: serde_json::Value, scale: f64| {
+            let scales = HashMap::from([("Src.TrustScore".to_string(), scale)]);
+            let src = SourceEntity::from_json_value_scaled(
+                &serde_json::json!({ "ip": "10.0.0.1", "attributes": { "Src.TrustScore": score } }),
+                &map.aliases,
+                &Default::default(),
+                &scales,
+            )?;
+            encode_source_entity(&map, &src).map(|e| e[&SourceEntityAttributeKey::TrustScore].clone())
+        };
+        // 0〜1 のフィードでは整数で書かれた 1 も 100 倍する
+        assert_eq!(encode(serde_json::json!(0.75), 100.0), Ok(EncodedAttributeValue::Numeric(75)));
+        assert_eq!(encode(serde_json::json!(1), 100.0), Ok(EncodedAttributeValue::Numeric(100)));
+        // 12.5 は 0 から遠い方に丸める
+        assert_eq!(encode(serde_json::json!(0.125), 100.0), Ok(EncodedAttributeValue::Numeric(13)));
+        // 0〜100 のフィードでは小数で書かれた 75.0 も 75 のまま
+        assert_eq!(encode(serde_json::json!(75.0), 1.0), Ok(EncodedAttributeValue::Numeric(75)));
+        assert_eq!(encode(serde_json::json!(75), 1.0), Ok(EncodedAttributeValue::Numeric(75)));
+        // フィードの取り違えは範囲外として見つかる
+        assert!(encode(serde_json::json!(75.0), 100.0).unwrap_err().contains("out of range"));
+        assert!(encode(serde_json::json!(75), 0.0).is_err());
+    }
+}
@@ -1,39 +1,227 @@
-use std::collections::HashMap;
-use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, Read};
+use serde_json::{Map, Value};
 
-fn parse_attribute_value(val: &Value) -> Result<AttributeValue, String> {
+use crate::util::normalization::NormalizationConfig;
+
+/// 数値は i64 で扱う。範囲外の整数 (i64::MAX を超える u64 のセッション ID など) や
+/// 小数はエラーにするので、そうした識別子は文字列として持たせること
+pub(crate) fn json_number_to_i64(n: &serde_json::Number) -> Result<i64, String> {
+    if let Some(i) = n.as_i64() {
+        return Ok(i);
+    }
+    if n.is_u64() {
+        Err(format!(
+            "Number {} exceeds the supported integer range (i64, max {}); store it as a string instead",
+            n,
+            i64::MAX
+        ))
+    } else {
+        Err(format!("Number {} is not an integer", n))
+    }
+}
+
+/// フィードごとに指定した scale で数値属性を整数のスケールにそろえる。
+/// 整数か小数かにかかわらず、その属性のすべての値を scale 倍して最も近い整数に丸める
+/// (0.5 は 0 から遠い方へ)。0〜1 のフィードは scale 100、すでに 0〜100 のフィードは
+/// scale を指定しない (か 1) にして、読み込むフィードごとに選ぶ
+pub(crate) fn scale_number(n: &serde_json::Number, scale: f64) -> Result<i64, String> {
+    if !scale.is_finite() || scale <= 0.0 {
+        return Err(format!("Scale must be a positive number, got {}", scale));
+    }
+    // 整数に整数倍するときは f64 を通さずに正確に計算する
+    if (n.is_i64() || n.is_u64()) && scale.fract() == 0.0 && scale <= i64::MAX as f64 {
+        return json_number_to_i64(n)?
+            .checked_mul(scale as i64)
+            .ok_or_else(|| format!("Number {} scaled by {} is out of the i64 range", n, scale));
+    }
+    let scaled = (n.as_f64().ok_or_else(|| format!("Invalid number {}", n))? * scale).round();
+    // i64::MAX as f64 は 2^63 に丸められるので、その値自体も範囲外として扱う
+    if !scaled.is_finite() || scaled < i64::MIN as f64 || scaled >= i64::MAX as f64 {
+        return Err(format!("Number {} scaled by {} is out of the i64 range", n, scale));
+    }
+    Ok(scaled as i64)
+}
+
+fn parse_scaled_attribute_value(
+    val: &Value,
+    norm: &NormalizationConfig,
+    scale: Option<f64>,
+) -> Result<AttributeValue, String> {
+    match (val, scale) {
+        (Value::Number(n), Some(scale)) => scale_number(n, scale).map(AttributeValue::Number),
+        _ => parse_attribute_value(val, norm),
+    }
+}
+
+pub(crate) fn parse_attribute_value(val: &Value, norm: &NormalizationConfig) -> Result<AttributeValue, String> {
     match val {
-        Value::String(s) => Ok(AttributeValue::String(s.clone())),
+        Value::String(s) => Ok(AttributeValue::String(norm.apply(s))),
 
-        Value::Number(n) => {
-            n.as_i64()
-                .ok_or_else(|| format!("Cannot convert number to i64: {}", n))
-                .map(AttributeValue::Number)
-        }
+        Value::Number(n) => json_number_to_i64(n).map(AttributeValue::Number),
 
         Value::Array(arr) => {
             arr.iter()
                 .map(|v| {
                     v.as_str()
                         .ok_or_else(|| format!("Array element is not a string: {:?}", v))
-                        .map(|s| s.to_string())
+                        .map(|s| norm.apply(s))
                 })
                 .collect::<Result<Vec<String>, String>>()
-                .map(AttributeValue::Set)
+                .map(AttributeValue::new_set)
         }
 
         Value::Bool(b) => Ok(AttributeValue::Boolean(*b)),
 
+        Value::Object(obj) => {
+            let mut fields = BTreeMap::new();
+            for (key, v) in obj {
+                fields.insert(key.clone(), parse_attribute_value(v, norm)?);
+            }
+            Ok(AttributeValue::Object(fields))
+        }
+
         _ => Err(format!("Unsupported attribute value type: {:?}", val)),
     }
 }
 
+/// parse_attribute_value の逆
+pub fn attribute_value_to_json(value: &AttributeValue) -> Value {
+    match value {
+        AttributeValue::String(s) => Value::from(s.as_str()),
+        AttributeValue::Number(n) => Value::from(*n),
+        AttributeValue::Set(items) => Value::from(items.clone()),
+        AttributeValue::Boolean(b) => Value::from(*b),
+        AttributeValue::Object(fields) => Value::Object(
+            fields.iter().map(|(k, v)| (k.clone(), attribute_value_to_json(v))).collect(),
+        ),
+        AttributeValue::Null => Value::Null,
+    }
+}
+
+// from_json_value が読めるオブジェクト形式 {"ip", "desc", "attributes": {...}} にする
+fn entity_to_json(ip: &str, desc: &Option<String>, attributes: Map<String, Value>) -> Value {
+    let mut out = Map::new();
+    out.insert("ip".to_string(), Value::from(ip));
+    if let Some(desc) = desc {
+        out.insert("desc".to_string(), Value::from(desc.as_str()));
+    }
+    out.insert("attributes".to_string(), Value::Object(attributes));
+    Value::Object(out)
+}
+
+/// attributes はオブジェクト形式 `{"Src.Role": "admin"}` と
+/// 配列形式 `[{"key": "Src.Role", "value": "admin"}]` のどちらでもよい
+fn attribute_pairs(entity: &Value) -> Result<Vec<(&str, &Value)>, String> {
+    match entity.get("attributes") {
+        Some(Value::Object(obj)) => Ok(obj.iter().map(|(k, v)| (k.as_str(), v)).collect()),
+        Some(Value::Array(arr)) => arr
+            .iter()
+            .map(|pair| {
+                let key = pair
+                    .get("key")
+                    .and_then(|k| k.as_str())
+                    .ok_or_else(|| format!("Attribute entry is missing 'key': {:?}", pair))?;
+                let val = pair
+                    .get("value")
+                    .ok_or_else(|| format!("Attribute entry is missing 'value': {:?}", pair))?;
+                Ok((key, val))
+            })
+            .collect(),
+        _ => Err("Missing or invalid 'attributes' field".to_string()),
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum AttributeValue {
     String(String),
     Number(i64),
+    /// 重複のない集合。パーサは new_set で昇順・重複なしにそろえるので、
+    /// 要素の順や重複の違う入力同士も == で等しくなる (多重集合としては扱わない)
     Set(Vec<String>),
     Boolean(bool),
+    // ネストした属性 (例: Src.Location.Country)。キーは SourceEntityAttributeKey::Nested
+    Object(BTreeMap<String, AttributeValue>),
+    /// 属性が無いこと。エンティティの読み込みでは作られず、
+    /// EvalOptions::missing_as_null で評価したときに無い属性の値として使われる
+    Null,
+}
+
+impl AttributeValue {
+    /// 要素を昇順に並べて重複を取り除いた Set を作る
+    pub fn new_set<I: IntoIterator<Item = String>>(items: I) -> AttributeValue {
+        let mut items: Vec<String> = items.into_iter().collect();
+        items.sort();
+        items.dedup();
+        AttributeValue::Set(items)
+    }
+
+    pub fn as_number(&self) -> Result<i64, String> {
+        match self {
+            AttributeValue::Number(n) => Ok(*n),
+            _ => Err(format!("Expected number, got {:?}", self)),
+        }
+    }
+
+    pub fn as_string(&self) -> Result<&str, String> {
+        match self {
+            AttributeValue::String(s) => Ok(s),
+            _ => Err(format!("Expected string, got {:?}", self)),
+        }
+    }
+
+    pub fn as_set(&self) -> Result<&[String], String> {
+        match self {
+            AttributeValue::Set(items) => Ok(items),
+            _ => Err(format!("Expected set, got {:?}", self)),
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool, String> {
+        match self {
+            AttributeValue::Boolean(b) => Ok(*b),
+            _ => Err(format!("Expected boolean, got {:?}", self)),
+        }
+    }
+
+    // 集合の要素順に依存しない表現 (エンティティの同一性判定用)。
+    // Object (Nested 属性の値) は Set と衝突しないよう "Object" のタグを付ける
+    fn canonical_key(&self) -> String {
+        match self {
+            AttributeValue::String(s) => format!("{:?}", s),
+            AttributeValue::Number(n) => n.to_string(),
+            AttributeValue::Boolean(b) => b.to_string(),
+            AttributeValue::Set(items) => {
+                let mut sorted = items.clone();
+                sorted.sort();
+                format!("{:?}", sorted)
+            }
+            AttributeValue::Object(fields) => {
+                let entries: Vec<String> = fields
+                    .iter()
+                    .map(|(k, v)| format!("{:?}: {}", k, v.canonical_key()))
+                    .collect();
+                format!("Object{{{}}}", entries.join(", "))
+            }
+            AttributeValue::Null => "null".to_string(),
+        }
+    }
+}
+
+fn canonical_entity_key(ip: &str, attributes: Vec<(String, &AttributeValue)>) -> String {
+    let mut attrs: Vec<String> = attributes
+        .into_iter()
+        .map(|(name, value)| format!("{}={}", name, value.canonical_key()))
+        .collect();
+    attrs.sort();
+    format!("{}|{}", ip, attrs.join(";"))
+}
+
+/// "Src.Device" のように prefix の後ろがドットを含まない 1 語の名前か
+fn is_nested_attribute_name(name: &str, prefix: &str) -> bool {
+    name.strip_prefix(prefix).is_some_and(|rest| !rest.is_empty() && !rest.contains('.'))
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -43,6 +231,9 @@ pub enum SourceEntityAttributeKey {
     TrustScore,
     Groups,
     SessionCount,
+    /// 値が JSON オブジェクトのトップレベル属性 (例: Src.Location、Src.Device)。
+    /// 名前は固定せず、完全な属性名を持つ。中身は Src.Location.Country のようなパスで参照する
+    Nested(String),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -51,6 +242,8 @@ pub enum DestinationEntityAttributeKey {
     OwnerDept,
     Sensitivity,
     AllowedVLANs,
+    /// SourceEntityAttributeKey::Nested と同じ
+    Nested(String),
 }
 
 #[derive(Debug, Clone)]
@@ -69,6 +262,34 @@ pub struct DestinationEntity {
 
 impl SourceEntity {
     pub fn from_json_value(value: &Value) -> Result<Self, String> {
+        Self::from_json_value_with_aliases(value, &HashMap::new())
+    }
+
+    /// 属性名に別名 (AttrIdMap::aliases) が使われていれば正規の名前に読み替える
+    pub fn from_json_value_with_aliases(
+        value: &Value,
+        aliases: &HashMap<String, String>,
+    ) -> Result<Self, String> {
+        Self::from_json_value_with(value, aliases, &NormalizationConfig::default())
+    }
+
+    /// 別名の読み替えに加えて、文字列の属性値を norm で正規化する
+    pub fn from_json_value_with(
+        value: &Value,
+        aliases: &HashMap<String, String>,
+        norm: &NormalizationConfig,
+    ) -> Result<Self, String> {
+        Self::from_json_value_scaled(value, aliases, norm, &HashMap::new())
+    }
+
+    /// from_json_value_with に加えて、scales (属性名 -> このフィードの倍率) にある数値属性の
+    /// すべての値を scale_number で整数のスケールにそろえる
+    pub fn from_json_value_scaled(
+        value: &Value,
+        aliases: &HashMap<String, String>,
+        norm: &NormalizationConfig,
+        scales: &HashMap<String, f64>,
+    ) -> Result<Self, String> {
         let ip = value
             .get("ip")
             .and_then(|v| v.as_str())
@@ -80,15 +301,12 @@ impl SourceEntity {
             .and_then(|v| v.as_str())
             .map(|s| s.to_string());
 
-        let attributes_obj = value
-            .get("attributes")
-            .and_then(|v| v.as_object())
-            .ok_or("Missing or invalid 'attributes' field")?;
-
         let mut attributes = HashMap::new();
-        for (key, val) in attributes_obj {
-            let attr_key = Self::parse_attribute_key(key)?;
-            let attr_value = Self::parse_attribute_value(val)?;
+        for (key, val) in attribute_pairs(value)? {
+            let key = aliases.get(key).map(|k| k.as_str()).unwrap_or(key);
+            let attr_key = Self::parse_attribute_key_for(key, val)?;
+            let attr_value = Self::parse_attribute_value(val, norm, scales.get(key).copied())
+                .map_err(|e| format!("{}: {}", key, e))?;
             attributes.insert(attr_key, attr_value);
         }
 
@@ -110,6 +328,14 @@ impl SourceEntity {
         }
     }
 
+    // parse_attribute_key に加えて、値がオブジェクトなら未知の名前も Nested として受け付ける
+    fn parse_attribute_key_for(key: &str, val: &Value) -> Result<SourceEntityAttributeKey, String> {
+        Self::parse_attribute_key(key).or_else(|e| match val {
+            Value::Object(_) if is_nested_attribute_name(key, "Src.") => Ok(SourceEntityAttributeKey::Nested(key.to_string())),
+            _ => Err(e),
+        })
+    }
+
     pub fn deparse_attribute_key(key: &SourceEntityAttributeKey) -> Result<String, String> {
         match key {
             SourceEntityAttributeKey::Role => Ok("Src.Role".to_string()),
@@ -117,16 +343,82 @@ impl SourceEntity {
             SourceEntityAttributeKey::TrustScore => Ok("Src.TrustScore".to_string()),
             SourceEntityAttributeKey::Groups => Ok("Src.Groups".to_string()),
             SourceEntityAttributeKey::SessionCount => Ok("Src.SessionCount".to_string()),
+            SourceEntityAttributeKey::Nested(name) => Ok(name.clone()),
         }
     }
 
-    fn parse_attribute_value(val: &Value) -> Result<AttributeValue, String> {
-        parse_attribute_value(val)
+    fn parse_attribute_value(
+        val: &Value,
+        norm: &NormalizationConfig,
+        scale: Option<f64>,
+    ) -> Result<AttributeValue, String> {
+        parse_scaled_attribute_value(val, norm, scale)
+    }
+
+    /// from_json_value で読み戻せる JSON にする
+    pub fn to_json_value(&self) -> Value {
+        // deparse_attribute_key はどのキーでも成功する
+        let attributes = self.attributes
+            .iter()
+            .filter_map(|(k, v)| Some((Self::deparse_attribute_key(k).ok()?, attribute_value_to_json(v))))
+            .collect();
+        entity_to_json(&self.ip, &self.desc, attributes)
+    }
+
+    /// ip と属性から作る正規化キー。集合の要素順と desc は無視する
+    pub fn canonical_key(&self) -> String {
+        let attrs = self.attributes
+            .iter()
+            .map(|(k, v)| (format!("{:?}", k), v))
+            .collect();
+        canonical_entity_key(&self.ip, attrs)
+    }
+}
+
+impl PartialEq for SourceEntity {
+    fn eq(&self, other: &Self) -> bool {
+        self.canonical_key() == other.canonical_key()
+    }
+}
+
+impl Eq for SourceEntity {}
+
+impl Hash for SourceEntity {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.canonical_key().hash(state);
     }
 }
 
 impl DestinationEntity {
-    pub fn from_json_value(value: &Value) -> Result<Self, String> {    
+    pub fn from_json_value(value: &Value) -> Result<Self, String> {
+        Self::from_json_value_with_aliases(value, &HashMap::new())
+    }
+
+    /// 属性名に別名 (AttrIdMap::aliases) が使われていれば正規の名前に読み替える
+    pub fn from_json_value_with_aliases(
+        value: &Value,
+        aliases: &HashMap<String, String>,
+    ) -> Result<Self, String> {
+        Self::from_json_value_with(value, aliases, &NormalizationConfig::default())
+    }
+
+    /// 別名の読み替えに加えて、文字列の属性値を norm で正規化する
+    pub fn from_json_value_with(
+        value: &Value,
+        aliases: &HashMap<String, String>,
+        norm: &NormalizationConfig,
+    ) -> Result<Self, String> {
+        Self::from_json_value_scaled(value, aliases, norm, &HashMap::new())
+    }
+
+    /// from_json_value_with に加えて、scales (属性名 -> このフィードの倍率) にある数値属性の
+    /// すべての値を scale_number で整数のスケールにそろえる
+    pub fn from_json_value_scaled(
+        value: &Value,
+        aliases: &HashMap<String, String>,
+        norm: &NormalizationConfig,
+        scales: &HashMap<String, f64>,
+    ) -> Result<Self, String> {
         let ip = value
             .get("ip")
             .and_then(|v| v.as_str())
@@ -138,16 +430,13 @@ impl DestinationEntity {
             .and_then(|v| v.as_str())
             .map(|s| s.to_string());
 
-        let attributes_obj = value
-            .get("attributes")
-            .and_then(|v| v.as_object())
-            .ok_or("Missing or invalid 'attributes' field")?;
-
         let mut attributes = HashMap::new();
 
-        for (key, val) in attributes_obj {
-            let attr_key = Self::parse_attribute_key(key)?;
-            let attr_value = Self::parse_attribute_value(val)?;
+        for (key, val) in attribute_pairs(value)? {
+            let key = aliases.get(key).map(|k| k.as_str()).unwrap_or(key);
+            let attr_key = Self::parse_attribute_key_for(key, val)?;
+            let attr_value = Self::parse_attribute_value(val, norm, scales.get(key).copied())
+                .map_err(|e| format!("{}: {}", key, e))?;
             attributes.insert(attr_key, attr_value);
         }
 
@@ -168,16 +457,286 @@ impl DestinationEntity {
         }
     }
 
+    // parse_attribute_key に加えて、値がオブジェクトなら未知の名前も Nested として受け付ける
+    fn parse_attribute_key_for(key: &str, val: &Value) -> Result<DestinationEntityAttributeKey, String> {
+        Self::parse_attribute_key(key).or_else(|e| match val {
+            Value::Object(_) if is_nested_attribute_name(key, "Dst.") => Ok(DestinationEntityAttributeKey::Nested(key.to_string())),
+            _ => Err(e),
+        })
+    }
+
     pub fn deparse_attribute_key(key: &DestinationEntityAttributeKey) -> Result<String, String> {
         match key {
             DestinationEntityAttributeKey::Type => Ok("Dst.Type".to_string()),
             DestinationEntityAttributeKey::OwnerDept => Ok("Dst.OwnerDept".to_string()),
             DestinationEntityAttributeKey::Sensitivity => Ok("Dst.Sensitivity".to_string()),
             DestinationEntityAttributeKey::AllowedVLANs => Ok("Dst.AllowedVLANs".to_string()),
+            DestinationEntityAttributeKey::Nested(name) => Ok(name.clone()),
         }
     }
 
-    fn parse_attribute_value(val: &Value) -> Result<AttributeValue, String> {
-        parse_attribute_value(val)
+    fn parse_attribute_value(
+        val: &Value,
+        norm: &NormalizationConfig,
+        scale: Option<f64>,
+    ) -> Result<AttributeValue, String> {
+        parse_scaled_attribute_value(val, norm, scale)
+    }
+
+    /// from_json_value で読み戻せる JSON にする
+    pub fn to_json_value(&self) -> Value {
+        // deparse_attribute_key はどのキーでも成功する
+        let attributes = self.attributes
+            .iter()
+            .filter_map(|(k, v)| Some((Self::deparse_attribute_key(k).ok()?, attribute_value_to_json(v))))
+            .collect();
+        entity_to_json(&self.ip, &self.desc, attributes)
     }
-}
\ No newline at end of file
+
+    /// ip と属性から作る正規化キー。集合の要素順と desc は無視する
+    pub fn canonical_key(&self) -> String {
+        let attrs = self.attributes
+            .iter()
+            .map(|(k, v)| (format!("{:?}", k), v))
+            .collect();
+        canonical_entity_key(&self.ip, attrs)
+    }
+}
+
+impl PartialEq for DestinationEntity {
+    fn eq(&self, other: &Self) -> bool {
+        self.canonical_key() == other.canonical_key()
+    }
+}
+
+impl Eq for DestinationEntity {}
+
+impl Hash for DestinationEntity {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.canonical_key().hash(state);
+    }
+}
+
+pub fn load_entities(path: &str) -> Result<(Vec<SourceEntity>, Vec<DestinationEntity>), String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    load_entities_from_reader(BufReader::new(file))
+}
+
+/// source_entities / destination_entities を持つ JSON を読み込む
+pub fn load_entities_from_reader<R: Read>(
+    reader: R,
+) -> Result<(Vec<SourceEntity>, Vec<DestinationEntity>), String> {
+    let json: Value = serde_json::from_reader(reader).map_err(|e| e.to_string())?;
+
+    let source_entities = match json.get("source_entities") {
+        Some(Value::Array(arr)) => arr
+            .iter()
+            .map(SourceEntity::from_json_value)
+            .collect::<Result<Vec<_>, String>>()?,
+        _ => Vec::new(),
+    };
+    let destination_entities = match json.get("destination_entities") {
+        Some(Value::Array(arr)) => arr
+            .iter()
+            .map(DestinationEntity::from_json_value)
+            .collect::<Result<Vec<_>, String>>()?,
+        _ => Vec::new(),
+    };
+
+    Ok((source_entities, destination_entities))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cal_probabilities::source_attribute_frequencies;
+
+    #[test]
+    fn accessors_return_inner_values() {
+        assert_eq!(AttributeValue::Number(3).as_number(), Ok(3));
+        assert_eq!(AttributeValue::String("a".to_string()).as_string(), Ok("a"));
+        assert_eq!(
+            AttributeValue::Set(vec!["a".to_string()]).as_set(),
+            Ok(&["a".to_string()][..])
+        );
+        assert_eq!(AttributeValue::Boolean(true).as_bool(), Ok(true));
+    }
+
+    #[test]
+    fn accessors_report_type_mismatch() {
+        let s = AttributeValue::String("a".to_string());
+        assert_eq!(s.as_number(), Err("Expected number, got String(\"a\")".to_string()));
+        assert_eq!(AttributeValue::Number(1).as_string(), Err("Expected string, got Number(1)".to_string()));
+        assert_eq!(s.as_set(), Err("Expected set, got String(\"a\")".to_string()));
+        assert_eq!(AttributeValue::Number(1).as_bool(), Err("Expected boolean, got Number(1)".to_string()));
+    }
+
+    #[test]
+    fn load_entities_from_reader_matches_path_loader() {
+        let path = "data/ip_based_abac_entity.json";
+        let text = std::fs::read_to_string(path).unwrap();
+        let (src_a, dst_a) = load_entities_from_reader(std::io::Cursor::new(text)).unwrap();
+        let (src_b, dst_b) = load_entities(path).unwrap();
+
+        let ips = |v: &[SourceEntity]| v.iter().map(|e| e.ip.clone()).collect::<Vec<_>>();
+        assert_eq!(ips(&src_a), ips(&src_b));
+        assert_eq!(dst_a.len(), dst_b.len());
+        assert!(!src_a.is_empty() && !dst_a.is_empty());
+    }
+
+    #[test]
+    fn entities_differing_in_set_order_are_deduplicated() {
+        let a = SourceEntity::from_json_value(&serde_json::json!({
+            "ip": "10.0.0.1",
+            "attributes": { "Src.Role": "Student", "Src.Groups": ["a", "b"] },
+            "desc": "first"
+        })).unwrap();
+        let b = SourceEntity::from_json_value(&serde_json::json!({
+            "ip": "10.0.0.1",
+            "attributes": { "Src.Groups": ["b", "a"], "Src.Role": "Student" }
+        })).unwrap();
+        let c = SourceEntity::from_json_value(&serde_json::json!({
+            "ip": "10.0.0.1",
+            "attributes": { "Src.Role": "Staff", "Src.Groups": ["a", "b"] }
+        })).unwrap();
+
+        assert_eq!(a.canonical_key(), b.canonical_key());
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+
+        let unique: std::collections::HashSet<SourceEntity> = vec![a, b, c].into_iter().collect();
+        assert_eq!(unique.len(), 2);
+    }
+
+    #[test]
+    fn object_keys_do_not_collide_with_set_keys() {
+        let object = AttributeValue::Object(BTreeMap::from([
+            ("Country".to_string(), AttributeValue::String("JP".to_string())),
+        ]));
+        let set = AttributeValue::Set(vec!["\"Country\": \"JP\"".to_string()]);
+        assert_ne!(object.canonical_key(), set.canonical_key());
+        assert_eq!(object.canonical_key(), "Object{\"Country\": \"JP\"}");
+    }
+
+    #[test]
+    fn array_of_pairs_attributes_match_object_form() {
+        let object_form = DestinationEntity::from_json_value(&serde_json::json!({
+            "ip": "172.20.0.1",
+            "attributes": { "Dst.Type": "Printer", "Dst.Sensitivity": 1, "Dst.AllowedVLANs": ["Staff", "Faculty"] }
+        })).unwrap();
+        let array_form = DestinationEntity::from_json_value(&serde_json::json!({
+            "ip": "172.20.0.1",
+            "attributes": [
+                { "key": "Dst.Type", "value": "Printer" },
+                { "key": "Dst.Sensitivity", "value": 1 },
+                { "key": "Dst.AllowedVLANs", "value": ["Staff", "Faculty"] }
+            ]
+        })).unwrap();
+        assert_eq!(object_form, array_form);
+        assert_eq!(array_form.attributes.len(), 3);
+
+        let missing_value = SourceEntity::from_json_value(&serde_json::json!({
+            "ip": "10.0.0.1",
+            "attributes": [{ "key": "Src.Role" }]
+        }));
+        assert!(missing_value.is_err());
+    }
+
+    #[test]
+    fn normalization_merges_whitespace_and_case_variants() {
+        let raw = [" admin ", "admin", "Admin  Staff"];
+        let parse = |norm: &NormalizationConfig| -> Vec<SourceEntity> {
+            raw.iter()
+                .enumerate()
+                .map(|(i, role)| {
+                    let v = serde_json::json!({ "ip": format!("10.0.0.{}", i), "attributes": { "Src.Role": role } });
+                    SourceEntity::from_json_value_with(&v, &HashMap::new(), norm).unwrap()
+                })
+                .collect()
+        };
+
+        // 既定では正規化しない
+        let plain = parse(&NormalizationConfig::default());
+        assert_eq!(source_attribute_frequencies(&plain, &SourceEntityAttributeKey::Role).len(), 3);
+
+        let norm = NormalizationConfig { trim: true, lowercase: true, collapse_whitespace: true };
+        let normalized = parse(&norm);
+        assert_eq!(
+            normalized[0].attributes[&SourceEntityAttributeKey::Role],
+            normalized[1].attributes[&SourceEntityAttributeKey::Role]
+        );
+        assert_eq!(
+            source_attribute_frequencies(&normalized, &SourceEntityAttributeKey::Role),
+            vec![("admin".to_string(), 2), ("admin staff".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn numbers_above_i64_max_are_rejected_with_the_field_name() {
+        let big = serde_json::json!({ "ip": "10.0.0.1", "attributes": { "Src.SessionCount": 9_223_372_036_854_775_808u64 } });
+        let err = SourceEntity::from_json_value(&big).unwrap_err();
+        assert!(err.contains("Src.SessionCount") && err.contains("as a string"), "{}", err);
+
+        let max = serde_json::json!({ "ip": "10.0.0.1", "attributes": { "Src.SessionCount": i64::MAX } });
+        let src = SourceEntity::from_json_value(&max).unwrap();
+        assert_eq!(src.attributes[&SourceEntityAttributeKey::SessionCount], AttributeValue::Number(i64::MAX));
+
+        let expr = crate::ip_based::rule::Condition::from_json_value(&serde_json::json!({
+            "operator": "GTE", "lhs": "Src.SessionCount", "rhs": u64::MAX
+        }));
+        assert!(expr.unwrap_err().contains("exceeds the supported integer range"));
+    }
+
+    #[test]
+    fn sets_are_deduplicated_and_order_insensitive() {
+        let parse = |groups: serde_json::Value| {
+            let v = serde_json::json!({ "ip": "10.0.0.1", "attributes": { "Src.Groups": groups } });
+            SourceEntity::from_json_value(&v).unwrap().attributes[&SourceEntityAttributeKey::Groups].clone()
+        };
+        let dup = parse(serde_json::json!(["a", "a", "b"]));
+        assert_eq!(dup, AttributeValue::Set(vec!["a".to_string(), "b".to_string()]));
+        assert_eq!(dup, parse(serde_json::json!(["b", "a"])));
+
+        // ポリシーの集合リテラルも同じく集合として扱う
+        use crate::ip_based::rule::Condition;
+        let src = SourceEntity::from_json_value(&serde_json::json!({
+            "ip": "10.0.0.1", "attributes": { "Src.Groups": ["a", "b"] }
+        })).unwrap();
+        let dst = DestinationEntity::from_json_value(&serde_json::json!({ "ip": "172.20.0.1", "attributes": {} })).unwrap();
+        let env = HashMap::new();
+        let eq = Condition::from_json_value(&serde_json::json!({
+            "operator": "EQ", "lhs": "Src.Groups", "rhs": ["b", "a", "b"]
+        })).unwrap();
+        assert_eq!(eq.evaluate(&src, &dst, &env), Ok(true));
+        let count = Condition::from_json_value(&serde_json::json!({
+            "operator": "GTE", "lhs": { "operator": "COUNT", "operand": ["a", "a", "a"] }, "rhs": 3
+        })).unwrap();
+        assert_eq!(count.evaluate(&src, &dst, &env), Ok(false));
+    }
+
+    #[test]
+    fn entities_round_trip_through_json() {
+        let src = SourceEntity::from_json_value(&serde_json::json!({
+            "ip": "10.0.0.1",
+            "desc": "lab pc",
+            "attributes": {
+                "Src.Role": "Student",
+                "Src.TrustScore": 42,
+                "Src.Groups": ["Lab", "Club"],
+                "Src.Location": { "Country": "JP", "Floor": 3 }
+            }
+        })).unwrap();
+        let back = SourceEntity::from_json_value(&src.to_json_value()).unwrap();
+        assert_eq!(back.ip, src.ip);
+        assert_eq!(back.desc, src.desc);
+        assert_eq!(back.attributes, src.attributes);
+
+        let dst = DestinationEntity::from_json_value(&serde_json::json!({
+            "ip": "172.20.0.1",
+            "attributes": { "Dst.Type": "FileServer", "Dst.AllowedVLANs": ["10", "20"] }
+        })).unwrap();
+        let back = DestinationEntity::from_json_value(&dst.to_json_value()).unwrap();
+        assert_eq!(back.desc, None);
+        assert_eq!(back.attributes, dst.attributes);
+    }
+}
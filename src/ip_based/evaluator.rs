@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+
+use crate::ip_based::entity::{AttributeValue, DestinationEntity, SourceEntity};
+use crate::ip_based::rule::{CombiningAlgorithm, Effect, EvalOptions, Policy, Rule};
+
+// first-applicable: 最初にマッチしたルールの effect を返す
+fn first_applicable<'a, I>(
+    rules: I,
+    source: &SourceEntity,
+    destination: &DestinationEntity,
+    env: &HashMap<String, AttributeValue>,
+    opts: &EvalOptions,
+) -> Result<Option<&'a Rule>, String>
+where
+    I: IntoIterator<Item = &'a Rule>,
+{
+    for rule in rules {
+        if rule.matches_with_options(source, destination, env, opts)? {
+            return Ok(Some(rule));
+        }
+    }
+    Ok(None)
+}
+
+// algorithm に従って採用するルールを選ぶ。overrides 系では優先される effect のルールのうち
+// 定義順で最初にマッチしたものを、無ければ最初にマッチした他方のルールを返す
+fn select_rule<'a, I>(
+    algorithm: CombiningAlgorithm,
+    rules: I,
+    source: &SourceEntity,
+    destination: &DestinationEntity,
+    env: &HashMap<String, AttributeValue>,
+    opts: &EvalOptions,
+) -> Result<Option<&'a Rule>, String>
+where
+    I: IntoIterator<Item = &'a Rule>,
+{
+    let Some(winning) = algorithm.overriding_effect() else {
+        return first_applicable(rules, source, destination, env, opts);
+    };
+    let mut fallback = None;
+    for rule in rules {
+        // 他方の effect のルールは最初の 1 つが見つかれば以降は評価しなくてよい
+        if rule.effect != winning && fallback.is_some() {
+            continue;
+        }
+        if rule.matches_with_options(source, destination, env, opts)? {
+            if rule.effect == winning {
+                return Ok(Some(rule));
+            }
+            fallback = Some(rule);
+        }
+    }
+    Ok(fallback)
+}
+
+// 短絡せずにマッチした全てのルールを返す
+pub fn matching_rules<'a>(
+    policy: &'a Policy,
+    source: &SourceEntity,
+    destination: &DestinationEntity,
+    env: &HashMap<String, AttributeValue>,
+) -> Result<Vec<&'a Rule>, String> {
+    let mut matched = Vec::new();
+    for rule in &policy.rules {
+        if rule.matches(source, destination, env)? {
+            matched.push(rule);
+        }
+    }
+    Ok(matched)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    pub effect: Effect,
+    // None の場合は default_effect が適用された
+    pub matched_rule: Option<String>,
+    // 適用されたルールの obligations（default の場合は空）
+    pub obligations: Vec<String>,
+}
+
+impl Decision {
+    pub fn is_default(&self) -> bool {
+        self.matched_rule.is_none()
+    }
+}
+
+pub fn evaluate_policy_explained(
+    policy: &Policy,
+    source: &SourceEntity,
+    destination: &DestinationEntity,
+    env: &HashMap<String, AttributeValue>,
+) -> Result<Decision, String> {
+    evaluate_policy_with_options(policy, source, destination, env, &EvalOptions::default())
+}
+
+/// evaluate_policy_explained と同じだが、各ルールの条件を opts (AttrIdMap::defaults による
+/// 欠損属性の補完や missing_as_null) で評価する
+pub fn evaluate_policy_with_options(
+    policy: &Policy,
+    source: &SourceEntity,
+    destination: &DestinationEntity,
+    env: &HashMap<String, AttributeValue>,
+    opts: &EvalOptions,
+) -> Result<Decision, String> {
+    let matched = select_rule(policy.combining_algorithm, &policy.rules, source, destination, env, opts)?;
+    let decision = match matched {
+        Some(rule) => Decision {
+            effect: rule.effect.clone(),
+            matched_rule: Some(rule.id.clone()),
+            obligations: rule.obligations.clone(),
+        },
+        None => Decision {
+            effect: policy.default_effect.clone(),
+            matched_rule: None,
+            obligations: Vec::new(),
+        },
+    };
+    #[cfg(feature = "logging")]
+    crate::ip_based::logging::emit(crate::ip_based::logging::DecisionEvent {
+        source_ip: source.ip.clone(),
+        dest_ip: destination.ip.clone(),
+        matched_rule: decision.matched_rule.clone(),
+        effect: decision.effect.clone(),
+    });
+    Ok(decision)
+}
+
+pub fn evaluate_policy(
+    policy: &Policy,
+    source: &SourceEntity,
+    destination: &DestinationEntity,
+    env: &HashMap<String, AttributeValue>,
+) -> Result<Effect, String> {
+    evaluate_policy_explained(policy, source, destination, env).map(|d| d.effect)
+}
+
+/// evaluate_policy と同じ判定 (ポリシーの combining_algorithm に従う) を Allow なら true として返す。
+/// Decision (ルール id や obligations の複製) を組み立てないので、ゲートウェイなど
+/// 判定結果だけが欲しいホットパスではこちらを使う
+pub fn is_allowed(
+    policy: &Policy,
+    source: &SourceEntity,
+    destination: &DestinationEntity,
+    env: &HashMap<String, AttributeValue>,
+) -> Result<bool, String> {
+    let matched = select_rule(
+        policy.combining_algorithm,
+        &policy.rules,
+        source,
+        destination,
+        env,
+        &EvalOptions::default(),
+    )?;
+    let effect = matched.map_or(&policy.default_effect, |r| &r.effect);
+    Ok(*effect == Effect::Allow)
+}
+
+pub fn evaluate_policy_for_action(
+    policy: &Policy,
+    source: &SourceEntity,
+    destination: &DestinationEntity,
+    env: &HashMap<String, AttributeValue>,
+    action: &str,
+) -> Result<Effect, String> {
+    let rules = policy.rules.iter().filter(|r| r.applies_to_action(action));
+    let matched = select_rule(policy.combining_algorithm, rules, source, destination, env, &EvalOptions::default())?;
+    Ok(matched.map_or(policy.default_effect.clone(), |r| r.effect.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn source() -> SourceEntity {
+        SourceEntity::from_json_value(&json!({
+            "ip": "10.0.0.1",
+            "attributes": { "Src.Role": "Student", "Src.Dept": "CS", "Src.TrustScore": 50 }
+        })).unwrap()
+    }
+
+    fn destination() -> DestinationEntity {
+        DestinationEntity::from_json_value(&json!({
+            "ip": "172.20.0.1",
+            "attributes": { "Dst.Type": "FileServer", "Dst.OwnerDept": "CS", "Dst.Sensitivity": 3 }
+        })).unwrap()
+    }
+
+    #[test]
+    fn actions_select_different_rules() {
+        let policy = Policy::from_json_value(&json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": "deny",
+            "rules": [
+                {
+                    "id": "read_same_dept",
+                    "effect": "allow",
+                    "actions": ["read"],
+                    "condition": { "operator": "EQ", "lhs": "Src.Dept", "rhs": "Dst.OwnerDept" }
+                },
+                {
+                    "id": "write_trusted",
+                    "effect": "allow",
+                    "actions": ["write"],
+                    "condition": { "operator": "GTE", "lhs": "Src.TrustScore", "rhs": 80 }
+                }
+            ]
+        })).unwrap();
+        let env = HashMap::new();
+
+        let read = evaluate_policy_for_action(&policy, &source(), &destination(), &env, "read").unwrap();
+        let write = evaluate_policy_for_action(&policy, &source(), &destination(), &env, "write").unwrap();
+        assert_eq!(read, Effect::Allow);
+        assert_eq!(write, Effect::Deny);
+    }
+
+    #[test]
+    fn rules_without_actions_apply_to_every_action() {
+        let policy = Policy::from_json_value(&json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": "allow",
+            "rules": [
+                {
+                    "id": "deny_students",
+                    "effect": "deny",
+                    "condition": { "operator": "EQ", "lhs": "Src.Role", "rhs": "Student" }
+                }
+            ]
+        })).unwrap();
+        let env = HashMap::new();
+
+        for action in ["read", "write", "delete"] {
+            let effect = evaluate_policy_for_action(&policy, &source(), &destination(), &env, action).unwrap();
+            assert_eq!(effect, Effect::Deny);
+        }
+        assert_eq!(evaluate_policy(&policy, &source(), &destination(), &env).unwrap(), Effect::Deny);
+    }
+
+    #[test]
+    fn trailing_always_rule_makes_default_unreachable() {
+        let policy = Policy::from_json_value(&json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": "deny",
+            "rules": [
+                {
+                    "id": "never",
+                    "effect": "deny",
+                    "condition": { "operator": "FALSE" }
+                },
+                {
+                    "id": "catch_all",
+                    "effect": "allow",
+                    "condition": { "operator": "TRUE" }
+                }
+            ]
+        })).unwrap();
+        let env = HashMap::new();
+
+        assert_eq!(evaluate_policy(&policy, &source(), &destination(), &env).unwrap(), Effect::Allow);
+        assert!(!policy.rules[1].condition.references_dst());
+        assert_eq!(policy.rules[0].condition.evaluate_dest_only(&destination()), Ok(false));
+        assert_eq!(policy.rules[1].condition.evaluate_dest_only(&destination()), Ok(true));
+    }
+
+    #[test]
+    fn decision_carries_matched_rule_obligations() {
+        let policy = Policy::from_json_value(&json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": "deny",
+            "rules": [
+                {
+                    "id": "admins",
+                    "effect": "allow",
+                    "obligations": ["require_mfa"],
+                    "condition": { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" }
+                },
+                {
+                    "id": "same_dept",
+                    "effect": "allow",
+                    "obligations": ["log_access"],
+                    "condition": { "operator": "EQ", "lhs": "Src.Dept", "rhs": "Dst.OwnerDept" }
+                }
+            ]
+        })).unwrap();
+        let env = HashMap::new();
+
+        let decision = evaluate_policy_explained(&policy, &source(), &destination(), &env).unwrap();
+        assert_eq!(decision.matched_rule.as_deref(), Some("same_dept"));
+        assert_eq!(decision.obligations, vec!["log_access".to_string()]);
+
+        assert!(Rule::from_json_value(&json!({
+            "id": "bad", "effect": "allow", "obligations": "log", "condition": { "operator": "TRUE" }
+        })).is_err());
+    }
+
+    #[test]
+    fn is_allowed_maps_effects_to_bool() {
+        let policy = Policy::from_json_value(&json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": "deny",
+            "rules": [
+                { "id": "deny_guest", "effect": "deny", "condition": { "operator": "EQ", "lhs": "Src.Role", "rhs": "Guest" } },
+                { "id": "allow_student", "effect": "allow", "condition": { "operator": "EQ", "lhs": "Src.Role", "rhs": "Student" } }
+            ]
+        })).unwrap();
+        let env = HashMap::new();
+        let with_role = |role: &str| {
+            SourceEntity::from_json_value(&json!({ "ip": "10.0.0.2", "attributes": { "Src.Role": role } })).unwrap()
+        };
+
+        assert_eq!(is_allowed(&policy, &source(), &destination(), &env), Ok(true));
+        assert_eq!(is_allowed(&policy, &with_role("Guest"), &destination(), &env), Ok(false));
+        // どのルールにもマッチしなければ default_effect (deny)
+        assert_eq!(is_allowed(&policy, &with_role("Admin"), &destination(), &env), Ok(false));
+    }
+
+    #[test]
+    fn overrides_pick_first_matching_rule_of_winning_effect() {
+        let policy = |algorithm: &str| Policy::from_json_value(&json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": "allow",
+            "combining_algorithm": algorithm,
+            "rules": [
+                { "id": "allow_cs", "effect": "allow", "condition": { "operator": "EQ", "lhs": "Src.Dept", "rhs": "CS" } },
+                { "id": "deny_students", "effect": "deny", "condition": { "operator": "EQ", "lhs": "Src.Role", "rhs": "Student" } },
+                { "id": "deny_low_trust", "effect": "deny", "condition": { "operator": "LT", "lhs": "Src.TrustScore", "rhs": 80 } }
+            ]
+        })).unwrap();
+        let env = HashMap::new();
+
+        let deny_overrides = policy("deny-overrides");
+        for _ in 0..3 {
+            let decision = evaluate_policy_explained(&deny_overrides, &source(), &destination(), &env).unwrap();
+            assert_eq!(decision.effect, Effect::Deny);
+            assert_eq!(decision.matched_rule.as_deref(), Some("deny_students"));
+        }
+        assert!(!is_allowed(&deny_overrides, &source(), &destination(), &env).unwrap());
+
+        let permit_overrides = policy("permit-overrides");
+        let decision = evaluate_policy_explained(&permit_overrides, &source(), &destination(), &env).unwrap();
+        assert_eq!(decision.matched_rule.as_deref(), Some("allow_cs"));
+
+        let first = policy("first-applicable");
+        assert_eq!(first.combining_algorithm, CombiningAlgorithm::FirstApplicable);
+        assert!(Policy::from_json_value(&json!({
+            "policy_name": "p", "description": "", "default_effect": "deny",
+            "combining_algorithm": "majority", "rules": []
+        })).is_err());
+    }
+
+    #[test]
+    fn policy_evaluation_uses_attribute_defaults_from_options() {
+        let map = crate::ip_based::encoder::AttrIdMap::from_reader(r#"{
+            "Src.Role": {
+                "description": { "type": "single", "default": "Guest" },
+                "value": { "0": "Student", "1": "Guest" }
+            }
+        }"#.as_bytes()).unwrap();
+        let policy = Policy::from_json_value(&json!({
+            "policy_name": "p", "description": "", "default_effect": "allow",
+            "rules": [{ "id": "deny_guests", "effect": "deny", "condition": { "operator": "EQ", "lhs": "Src.Role", "rhs": "Guest" } }]
+        })).unwrap();
+        let src = SourceEntity::from_json_value(&json!({ "ip": "10.0.0.9", "attributes": {} })).unwrap();
+        let env = HashMap::new();
+
+        assert!(evaluate_policy_explained(&policy, &src, &destination(), &env).is_err());
+        let opts = EvalOptions { defaults: Some(&map.defaults), ..EvalOptions::default() };
+        let decision = evaluate_policy_with_options(&policy, &src, &destination(), &env, &opts).unwrap();
+        assert_eq!(decision.matched_rule.as_deref(), Some("deny_guests"));
+    }
+}
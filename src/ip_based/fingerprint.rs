@@ -0,0 +1,81 @@
+use crate::ip_based::rule::{CombiningAlgorithm, Policy};
+
+// FNV-1a (64bit)。DefaultHasher と違い Rust のバージョンに依存しない
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// JSON の書式やキー順、条件の書き方に依存しない正規化表現
+fn canonical_policy_string(policy: &Policy) -> String {
+    let mut out = String::new();
+    out.push_str(&format!(
+        "policy:{:?}|{:?}|{:?}\n",
+        policy.policy_name, policy.description, policy.default_effect
+    ));
+    // 既定の first-applicable のポリシーは以前と同じ指紋になるようにする
+    if policy.combining_algorithm != CombiningAlgorithm::FirstApplicable {
+        out.push_str(&format!("combining:{:?}\n", policy.combining_algorithm));
+    }
+    for rule in &policy.rules {
+        out.push_str(&format!(
+            "rule:{:?}|{:?}|{:?}|{:?}|{:?}|{:?}\n",
+            rule.id,
+            rule.description,
+            rule.effect,
+            rule.actions,
+            rule.obligations,
+            rule.condition.canonicalize()
+        ));
+    }
+    out
+}
+
+pub fn policy_fingerprint(policy: &Policy) -> u64 {
+    fnv1a(canonical_policy_string(policy).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = r#"{
+        "policy_name": "p",
+        "description": "d",
+        "default_effect": "deny",
+        "rules": [
+            { "id": "r1", "effect": "allow", "condition": { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" } }
+        ]
+    }"#;
+
+    const REFORMATTED: &str = r#"{"rules":[{"condition":{"rhs":"Admin","lhs":"Src.Role","operator":"EQ"},"effect":"allow","id":"r1"}],
+        "default_effect":"deny","description":"d","policy_name":"p"}"#;
+
+    fn parse(s: &str) -> Policy {
+        Policy::from_json_value(&serde_json::from_str(s).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn fingerprint_ignores_json_formatting() {
+        assert_eq!(policy_fingerprint(&parse(POLICY)), policy_fingerprint(&parse(REFORMATTED)));
+    }
+
+    #[test]
+    fn fingerprint_changes_with_effect() {
+        let flipped = POLICY.replace("\"allow\"", "\"deny\"");
+        assert_ne!(policy_fingerprint(&parse(POLICY)), policy_fingerprint(&parse(&flipped)));
+    }
+
+    #[test]
+    fn policy_from_reader_matches_path_loader() {
+        let path = "data/ip_based_abac_rule.json";
+        let text = std::fs::read_to_string(path).unwrap();
+        let from_cursor = Policy::from_reader(std::io::Cursor::new(text)).unwrap();
+        let from_path = Policy::load(path).unwrap();
+        assert_eq!(policy_fingerprint(&from_cursor), policy_fingerprint(&from_path));
+    }
+}
@@ -0,0 +1,148 @@
+use serde_json::{Map, Value, json};
+
+use crate::ip_based::encoder::{AttrIdEntry, AttrIdMap, AttrValueType};
+use crate::ip_based::entity::{DestinationEntity, SourceEntity};
+use crate::util::rng::SplitMix64;
+
+// HashMap の走査順に依存しないよう、値は ID 順に並べる
+fn known_values(entry: &AttrIdEntry) -> Vec<&String> {
+    let mut values: Vec<(&String, &u32)> = entry
+        .value_to_id
+        .as_ref()
+        .map(|m| m.iter().collect())
+        .unwrap_or_default();
+    values.sort_by_key(|(_, id)| **id);
+    values.into_iter().map(|(v, _)| v).collect()
+}
+
+fn random_value(entry: &AttrIdEntry, rng: &mut SplitMix64) -> Option<Value> {
+    match entry.value_type {
+        AttrValueType::Single => {
+            let values = known_values(entry);
+            if values.is_empty() {
+                return None;
+            }
+            Some(json!(values[rng.below(values.len() as u64) as usize]))
+        }
+        AttrValueType::Multiple => {
+            let picked: Vec<&String> = known_values(entry)
+                .into_iter()
+                .filter(|_| rng.below(4) == 0)
+                .collect();
+            Some(json!(picked))
+        }
+        AttrValueType::Numeric => {
+            let (min, max) = (entry.numeric_min?, entry.numeric_max?);
+            if max < min {
+                return None;
+            }
+            // i64 全域では span が u64 に収まらないので、乱数をそのまま値にする
+            let value = match max.abs_diff(min).checked_add(1) {
+                Some(span) => min.checked_add_unsigned(rng.below(span))?,
+                None => rng.next_u64() as i64,
+            };
+            Some(json!(value))
+        }
+    }
+}
+
+fn random_attributes(map: &AttrIdMap, prefix: &str, rng: &mut SplitMix64) -> Map<String, Value> {
+    let mut names: Vec<&String> = map.entries.keys().filter(|k| k.starts_with(prefix)).collect();
+    names.sort();
+
+    let mut attributes = Map::new();
+    for name in names {
+        if let Some(value) = random_value(&map.entries[name], rng) {
+            attributes.insert(name.clone(), value);
+        }
+    }
+    attributes
+}
+
+/// AttrIdMap の既知の値・数値範囲から source エンティティを n 個生成する。同じ seed なら同じ結果になる。
+/// 生成した属性をエンティティとして読めなければ (map に source の属性として無い名前があるなど) エラー
+pub fn generate_random_sources(n: usize, map: &AttrIdMap, seed: u64) -> Result<Vec<SourceEntity>, String> {
+    let mut rng = SplitMix64::new(seed);
+    (0..n)
+        .map(|i| {
+            let attributes = random_attributes(map, "Src.", &mut rng);
+            let value = json!({ "ip": format!("10.0.{}.{}", i / 256, i % 256), "attributes": attributes });
+            SourceEntity::from_json_value(&value).map_err(|e| format!("generated source {}: {}", i, e))
+        })
+        .collect()
+}
+
+/// destination 版。IP は 172.16.0.0/16 から割り当てる
+pub fn generate_random_destinations(n: usize, map: &AttrIdMap, seed: u64) -> Result<Vec<DestinationEntity>, String> {
+    let mut rng = SplitMix64::new(seed);
+    (0..n)
+        .map(|i| {
+            let attributes = random_attributes(map, "Dst.", &mut rng);
+            let value = json!({ "ip": format!("172.16.{}.{}", i / 256, i % 256), "attributes": attributes });
+            DestinationEntity::from_json_value(&value).map_err(|e| format!("generated destination {}: {}", i, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_yields_identical_entities() {
+        let map = AttrIdMap::load("data/ip_based_abac_attr_id.json").unwrap();
+
+        let a = generate_random_sources(50, &map, 42).unwrap();
+        let b = generate_random_sources(50, &map, 42).unwrap();
+        assert_eq!(a.len(), 50);
+        assert_eq!(a, b);
+
+        let c = generate_random_sources(50, &map, 43).unwrap();
+        assert_eq!(c.len(), 50);
+        assert_ne!(a, c);
+
+        let d1 = generate_random_destinations(20, &map, 7).unwrap();
+        let d2 = generate_random_destinations(20, &map, 7).unwrap();
+        assert_eq!(d1.len(), 20);
+        assert_eq!(d1, d2);
+    }
+
+    #[test]
+    fn unreadable_attributes_are_reported_not_dropped() {
+        // Src. で始まるが SourceEntity の属性ではない名前
+        let map = AttrIdMap::builder().single("Src.Shoe", &["Left", "Right"]).build();
+        let err = generate_random_sources(3, &map, 1).unwrap_err();
+        assert!(err.starts_with("generated source 0:"), "{}", err);
+    }
+
+    #[test]
+    fn generated_numeric_values_stay_in_range() {
+        let map = AttrIdMap::load("data/ip_based_abac_attr_id.json").unwrap();
+        let entry = &map.entries["Src.TrustScore"];
+        let (min, max) = (entry.numeric_min.unwrap(), entry.numeric_max.unwrap());
+
+        for source in generate_random_sources(100, &map, 1).unwrap() {
+            let score = source.attributes[&crate::ip_based::entity::SourceEntityAttributeKey::TrustScore]
+                .as_number()
+                .unwrap();
+            assert!(score >= min && score <= max);
+        }
+    }
+
+    #[test]
+    fn wide_numeric_ranges_do_not_overflow() {
+        for (min, max) in [(-1, i64::MAX), (i64::MIN, i64::MAX)] {
+            let json = format!(
+                r#"{{ "Src.TrustScore": {{ "description": {{ "type": "numeric" }}, "value": {{ "min": {}, "max": {} }} }} }}"#,
+                min, max
+            );
+            let map = AttrIdMap::from_reader(json.as_bytes()).unwrap();
+            for source in generate_random_sources(20, &map, 3).unwrap() {
+                let score = source.attributes[&crate::ip_based::entity::SourceEntityAttributeKey::TrustScore]
+                    .as_number()
+                    .unwrap();
+                assert!(score >= min);
+            }
+        }
+    }
+}
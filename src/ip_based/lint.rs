@@ -0,0 +1,208 @@
+use std::collections::BTreeSet;
+
+use crate::ip_based::encoder::AttrIdMap;
+use crate::ip_based::entity::{AttributeValue, DestinationEntity, SourceEntity};
+use crate::ip_based::rule::{Effect, Policy};
+
+pub fn lint_default_allow(policy: &Policy) -> Option<String> {
+    if policy.default_effect != Effect::Allow {
+        return None;
+    }
+
+    let has_deny = policy.rules.iter().any(|r| r.effect == Effect::Deny);
+    if has_deny {
+        Some(format!(
+            "Policy {} uses default_effect allow; unmatched requests are allowed",
+            policy.policy_name
+        ))
+    } else {
+        Some(format!(
+            "Policy {} uses default_effect allow and has no deny rules; every request is allowed",
+            policy.policy_name
+        ))
+    }
+}
+
+// AttrIdMap に定義されているが、どのルールからも参照されない属性
+pub fn unused_attributes(policy: &Policy, map: &AttrIdMap) -> Vec<String> {
+    let referenced = policy.attributes_referenced();
+    let mut unused: Vec<String> = map
+        .entries
+        .keys()
+        .filter(|name| !referenced.contains(*name))
+        .cloned()
+        .collect();
+    unused.sort();
+    unused
+}
+
+/// これを超える深さの条件は lint_policy で警告する
+pub const MAX_CONDITION_DEPTH: usize = 8;
+/// これを超えるノード数の条件は lint_policy で警告する
+pub const MAX_CONDITION_SIZE: usize = 64;
+
+// 深すぎる・大きすぎる条件を持つルール
+pub fn lint_condition_complexity(policy: &Policy) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for rule in &policy.rules {
+        let depth = rule.condition.depth();
+        if depth > MAX_CONDITION_DEPTH {
+            warnings.push(format!(
+                "Rule {} has condition depth {} (max {})",
+                rule.id, depth, MAX_CONDITION_DEPTH
+            ));
+        }
+        let size = rule.condition.size();
+        if size > MAX_CONDITION_SIZE {
+            warnings.push(format!(
+                "Rule {} has {} condition nodes (max {})",
+                rule.id, size, MAX_CONDITION_SIZE
+            ));
+        }
+    }
+    warnings
+}
+
+// エンティティに現れるが AttrIdMap の value_to_id に無い (属性, 値)。
+// encode_entity_value が失敗する値を事前に見つけるためのもので、unknown_id に落ちる値も含む
+pub fn coverage_gaps(
+    map: &AttrIdMap,
+    sources: &[SourceEntity],
+    destinations: &[DestinationEntity],
+) -> Vec<(String, String)> {
+    let mut gaps = BTreeSet::new();
+    let mut check = |name: String, value: &AttributeValue| {
+        let Some(known) = map.entry(&name).and_then(|e| e.value_to_id.as_ref()) else { return };
+        let values = match value {
+            AttributeValue::String(s) => vec![s.clone()],
+            AttributeValue::Number(n) => vec![n.to_string()],
+            AttributeValue::Set(items) => items.clone(),
+            _ => return,
+        };
+        for v in values {
+            if !known.contains_key(&v) {
+                gaps.insert((name.clone(), v));
+            }
+        }
+    };
+    for src in sources {
+        for (key, value) in &src.attributes {
+            if let Ok(name) = SourceEntity::deparse_attribute_key(key) {
+                check(name, value);
+            }
+        }
+    }
+    for dst in destinations {
+        for (key, value) in &dst.attributes {
+            if let Ok(name) = DestinationEntity::deparse_attribute_key(key) {
+                check(name, value);
+            }
+        }
+    }
+    gaps.into_iter().collect()
+}
+
+pub fn lint_policy(policy: &Policy) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if let Some(w) = lint_default_allow(policy) {
+        warnings.push(w);
+    }
+    for id in policy.duplicate_rule_ids() {
+        warnings.push(format!(
+            "Policy {} has more than one rule with id {}; only the first is reachable by id",
+            policy.policy_name, id
+        ));
+    }
+    warnings.extend(lint_condition_complexity(policy));
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn policy(default_effect: &str, rule_effect: &str) -> Policy {
+        Policy::from_json_value(&json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": default_effect,
+            "rules": [
+                {
+                    "id": "r1",
+                    "effect": rule_effect,
+                    "condition": { "operator": "EQ", "lhs": "Src.Role", "rhs": "Guest" }
+                }
+            ]
+        })).unwrap()
+    }
+
+    #[test]
+    fn allow_default_without_deny_rules_allows_everything() {
+        let w = lint_default_allow(&policy("allow", "allow")).unwrap();
+        assert!(w.contains("no deny rules"));
+        assert_eq!(lint_policy(&policy("allow", "allow")).len(), 1);
+    }
+
+    #[test]
+    fn allow_default_with_deny_rules_still_warns() {
+        let w = lint_default_allow(&policy("allow", "deny")).unwrap();
+        assert!(!w.contains("no deny rules"));
+    }
+
+    #[test]
+    fn unused_attributes_lists_unreferenced_map_entries() {
+        let map = AttrIdMap::load("data/ip_based_abac_attr_id.json").unwrap();
+        let unused = unused_attributes(&policy("deny", "allow"), &map);
+        assert!(!unused.contains(&"Src.Role".to_string()));
+        assert!(unused.contains(&"Src.TrustScore".to_string()));
+        assert_eq!(unused.len(), map.entries.len() - 1);
+    }
+
+    #[test]
+    fn deny_default_is_clean() {
+        assert!(lint_default_allow(&policy("deny", "allow")).is_none());
+        assert!(lint_policy(&policy("deny", "allow")).is_empty());
+    }
+
+    #[test]
+    fn deeply_nested_condition_is_flagged() {
+        let mut condition = json!({ "operator": "EQ", "lhs": "Src.Role", "rhs": "Guest" });
+        for _ in 0..MAX_CONDITION_DEPTH {
+            condition = json!({ "operator": "AND", "operands": [condition] });
+        }
+        let deep = Policy::from_json_value(&json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": "deny",
+            "rules": [{ "id": "deep", "effect": "allow", "condition": condition }]
+        })).unwrap();
+
+        let warnings = lint_policy(&deep);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("deep") && warnings[0].contains("depth"), "{}", warnings[0]);
+        assert!(lint_condition_complexity(&policy("deny", "allow")).is_empty());
+    }
+
+    #[test]
+    fn coverage_gaps_lists_values_missing_from_map() {
+        let map = AttrIdMap::load("data/ip_based_abac_attr_id.json").unwrap();
+        let sources = vec![
+            SourceEntity::from_json_value(&json!({
+                "ip": "s1", "attributes": { "Src.Role": "Admin", "Src.Groups": ["Faculty", "Pirates"], "Src.TrustScore": 50 }
+            })).unwrap(),
+            SourceEntity::from_json_value(&json!({ "ip": "s2", "attributes": { "Src.Role": "Wizard" } })).unwrap(),
+        ];
+        let destinations = vec![
+            DestinationEntity::from_json_value(&json!({ "ip": "d1", "attributes": { "Dst.Type": "Database" } })).unwrap(),
+        ];
+
+        assert_eq!(
+            coverage_gaps(&map, &sources, &destinations),
+            vec![
+                ("Src.Groups".to_string(), "Pirates".to_string()),
+                ("Src.Role".to_string(), "Wizard".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::cell::RefCell;
+
+use serde_json::json;
+
+use crate::ip_based::rule::Effect;
+
+/// evaluate_policy_explained が判定ごとに出すイベント
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecisionEvent {
+    pub source_ip: String,
+    pub dest_ip: String,
+    // None の場合は default_effect が適用された
+    pub matched_rule: Option<String>,
+    pub effect: Effect,
+}
+
+impl DecisionEvent {
+    pub fn to_json_value(&self) -> serde_json::Value {
+        json!({
+            "event": "decision",
+            "source_ip": self.source_ip,
+            "dest_ip": self.dest_ip,
+            "matched_rule": self.matched_rule,
+            "effect": match self.effect {
+                Effect::Allow => "allow",
+                Effect::Deny => "deny",
+            },
+        })
+    }
+}
+
+type Subscriber = Box<dyn Fn(&DecisionEvent)>;
+
+thread_local! {
+    static SUBSCRIBER: RefCell<Option<Subscriber>> = const { RefCell::new(None) };
+}
+
+/// f を実行している間、このスレッドの判定イベントを subscriber に渡す。
+/// subscriber が無いときは 1 行の JSON として stderr に出す
+pub fn with_subscriber<S, F, R>(subscriber: S, f: F) -> R
+where
+    S: Fn(&DecisionEvent) + 'static,
+    F: FnOnce() -> R,
+{
+    let previous = SUBSCRIBER.with(|s| s.borrow_mut().replace(Box::new(subscriber)));
+    let result = f();
+    SUBSCRIBER.with(|s| *s.borrow_mut() = previous);
+    result
+}
+
+pub(crate) fn emit(event: DecisionEvent) {
+    SUBSCRIBER.with(|s| match s.borrow().as_ref() {
+        Some(subscriber) => subscriber(&event),
+        None => eprintln!("{}", event.to_json_value()),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+    use crate::ip_based::entity::{DestinationEntity, SourceEntity};
+    use crate::ip_based::evaluator::evaluate_policy_explained;
+    use crate::ip_based::rule::Policy;
+
+    #[test]
+    fn deny_decision_logs_matched_rule() {
+        let policy = Policy::from_json_value(&json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": "allow",
+            "rules": [{
+                "id": "deny_guests",
+                "effect": "deny",
+                "condition": { "operator": "EQ", "lhs": "Src.Role", "rhs": "Guest" }
+            }]
+        })).unwrap();
+        let src = SourceEntity::from_json_value(&json!({
+            "ip": "10.0.0.1", "attributes": { "Src.Role": "Guest" }
+        })).unwrap();
+        let dst = DestinationEntity::from_json_value(&json!({
+            "ip": "172.20.0.1", "attributes": {}
+        })).unwrap();
+
+        let captured = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&captured);
+        with_subscriber(
+            move |e: &DecisionEvent| sink.borrow_mut().push(e.clone()),
+            || evaluate_policy_explained(&policy, &src, &dst, &HashMap::new()).unwrap(),
+        );
+
+        assert_eq!(*captured.borrow(), vec![DecisionEvent {
+            source_ip: "10.0.0.1".to_string(),
+            dest_ip: "172.20.0.1".to_string(),
+            matched_rule: Some("deny_guests".to_string()),
+            effect: Effect::Deny,
+        }]);
+    }
+}
@@ -2,4 +2,20 @@ pub mod entity;
 pub mod rule;
 pub mod classifier;
 pub mod encoder;
-pub mod rule_requirements;
\ No newline at end of file
+pub mod rule_requirements;
+pub mod dot;
+pub mod evaluator;
+pub mod lint;
+pub mod analysis;
+pub mod output;
+pub mod visitor;
+pub mod fingerprint;
+pub mod canonical;
+pub mod generator;
+#[cfg(feature = "arbitrary")]
+pub mod arbitrary;
+pub mod simplify;
+pub mod csv_import;
+pub mod embed;
+#[cfg(feature = "logging")]
+pub mod logging;
\ No newline at end of file
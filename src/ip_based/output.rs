@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use serde_json::{Map, Value, json};
+
+use crate::ip_based::encoder::EncodedAttributeValue;
+use crate::ip_based::entity::{
+    DestinationEntity, DestinationEntityAttributeKey,
+    SourceEntity, SourceEntityAttributeKey,
+};
+
+pub fn encoded_value_to_json(v: &EncodedAttributeValue) -> Value {
+    match v {
+        EncodedAttributeValue::SingleId(id) => Value::from(*id),
+        EncodedAttributeValue::MultipleIds(ids) => {
+            let mut ids = ids.clone();
+            ids.sort();
+            Value::from(ids)
+        }
+        EncodedAttributeValue::Numeric(n) => Value::from(*n),
+    }
+}
+
+/// serde_json::Map はキー順に並ぶので (preserve_order は使っていない)、
+/// HashMap の走査順に関係なく同じデータからは常に同じ JSON になる
+pub fn encoded_source_to_json(
+    encoded: &HashMap<SourceEntityAttributeKey, EncodedAttributeValue>,
+) -> Result<Value, String> {
+    let mut out = Map::new();
+    for (key, val) in encoded {
+        out.insert(SourceEntity::deparse_attribute_key(key)?, encoded_value_to_json(val));
+    }
+    Ok(Value::Object(out))
+}
+
+pub fn encoded_destination_to_json(
+    encoded: &HashMap<DestinationEntityAttributeKey, EncodedAttributeValue>,
+) -> Result<Value, String> {
+    let mut out = Map::new();
+    for (key, val) in encoded {
+        out.insert(DestinationEntity::deparse_attribute_key(key)?, encoded_value_to_json(val));
+    }
+    Ok(Value::Object(out))
+}
+
+/// バイナリが出力する処理結果をまとめたもの
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PipelineReport {
+    /// (destination IP, 適用可能なルール ID)
+    pub applicable_rules: Vec<(String, Vec<String>)>,
+    /// (source IP, 属性ごとのビット列)
+    pub source_bits: Vec<(String, Vec<String>)>,
+    /// (destination IP, ルール ID, 属性ごとの要求ビット列)
+    pub requirement_bits: Vec<(String, String, Vec<String>)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+impl OutputFormat {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!("Unknown output format: {} (expected json, csv or text)", s)),
+        }
+    }
+}
+
+pub fn render_report(report: &PipelineReport, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Text => report_to_text(report),
+        OutputFormat::Json => report_to_json(report).to_string(),
+        OutputFormat::Csv => report_to_csv(report),
+    }
+}
+
+fn report_to_text(report: &PipelineReport) -> String {
+    let mut out = String::new();
+    for (dest_ip, rules) in &report.applicable_rules {
+        out.push_str(&format!("Destination IP: {}\n", dest_ip));
+        for rule_id in rules {
+            out.push_str(&format!("  {}\n", rule_id));
+        }
+    }
+    for (src_ip, bits) in &report.source_bits {
+        out.push_str(&format!("Source {}: {:?}\n", src_ip, bits));
+    }
+    for (dest_ip, rule_id, bits) in &report.requirement_bits {
+        out.push_str(&format!("Dest {} Rule {}: {:?}\n", dest_ip, rule_id, bits));
+    }
+    out
+}
+
+pub fn report_to_json(report: &PipelineReport) -> Value {
+    let applicable: Vec<Value> = report
+        .applicable_rules
+        .iter()
+        .map(|(dest_ip, rules)| json!({ "destination": dest_ip, "rules": rules }))
+        .collect();
+    let sources: Vec<Value> = report
+        .source_bits
+        .iter()
+        .map(|(src_ip, bits)| json!({ "source": src_ip, "bits": bits }))
+        .collect();
+    let requirements: Vec<Value> = report
+        .requirement_bits
+        .iter()
+        .map(|(dest_ip, rule_id, bits)| json!({ "destination": dest_ip, "rule": rule_id, "bits": bits }))
+        .collect();
+    json!({
+        "applicable_rules": applicable,
+        "source_bits": sources,
+        "requirement_bits": requirements,
+    })
+}
+
+// カンマ・引用符・改行を含むフィールドだけ引用符で囲む
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn csv_row(fields: &[&str]) -> String {
+    let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+    fields.join(",") + "\n"
+}
+
+/// section,entity,rule,bits の 4 列。ビット列は属性ごとに空白区切り
+fn report_to_csv(report: &PipelineReport) -> String {
+    let mut out = csv_row(&["section", "entity", "rule", "bits"]);
+    for (dest_ip, rules) in &report.applicable_rules {
+        for rule_id in rules {
+            out.push_str(&csv_row(&["applicable", dest_ip, rule_id, ""]));
+        }
+    }
+    for (src_ip, bits) in &report.source_bits {
+        out.push_str(&csv_row(&["source", src_ip, "", &bits.join(" ")]));
+    }
+    for (dest_ip, rule_id, bits) in &report.requirement_bits {
+        out.push_str(&csv_row(&["requirement", dest_ip, rule_id, &bits.join(" ")]));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_report() -> PipelineReport {
+        PipelineReport {
+            applicable_rules: vec![("10.0.0.3".into(), vec!["r1".into(), "r2".into()])],
+            source_bits: vec![("10.0.0.1".into(), vec!["0101".into(), "0011".into()])],
+            requirement_bits: vec![("10.0.0.3".into(), "r1".into(), vec!["0100".into(), "0000".into()])],
+        }
+    }
+
+    #[test]
+    fn json_report_round_trips() {
+        let report = sample_report();
+        let parsed: Value = serde_json::from_str(&render_report(&report, OutputFormat::Json)).unwrap();
+        assert_eq!(parsed, report_to_json(&report));
+        assert_eq!(parsed["applicable_rules"][0]["rules"], json!(["r1", "r2"]));
+        assert_eq!(parsed["requirement_bits"][0]["bits"], json!(["0100", "0000"]));
+    }
+
+    #[test]
+    fn csv_report_has_one_row_per_record() {
+        let csv = render_report(&sample_report(), OutputFormat::Csv);
+        let rows: Vec<Vec<&str>> = csv.lines().map(|l| l.split(',').collect()).collect();
+        assert_eq!(rows[0], vec!["section", "entity", "rule", "bits"]);
+        assert_eq!(rows.len(), 5);
+        assert!(rows.iter().all(|r| r.len() == 4));
+        assert_eq!(rows[3], vec!["source", "10.0.0.1", "", "0101 0011"]);
+
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn text_report_matches_legacy_output() {
+        let text = render_report(&sample_report(), OutputFormat::Text);
+        assert_eq!(
+            text,
+            "Destination IP: 10.0.0.3\n  r1\n  r2\n\
+             Source 10.0.0.1: [\"0101\", \"0011\"]\n\
+             Dest 10.0.0.3 Rule r1: [\"0100\", \"0000\"]\n"
+        );
+        assert!(OutputFormat::parse("yaml").is_err());
+    }
+
+    #[test]
+    fn encoded_json_is_stable_across_runs() {
+        let render = || {
+            let mut encoded = HashMap::new();
+            encoded.insert(SourceEntityAttributeKey::Role, EncodedAttributeValue::SingleId(2));
+            encoded.insert(SourceEntityAttributeKey::TrustScore, EncodedAttributeValue::Numeric(80));
+            encoded.insert(SourceEntityAttributeKey::Groups, EncodedAttributeValue::MultipleIds(vec![5, 1]));
+            encoded.insert(SourceEntityAttributeKey::Dept, EncodedAttributeValue::SingleId(0));
+            encoded_source_to_json(&encoded).unwrap().to_string()
+        };
+
+        let first = render();
+        assert_eq!(first, render());
+        assert_eq!(
+            first,
+            r#"{"Src.Dept":0,"Src.Groups":[1,5],"Src.Role":2,"Src.TrustScore":80}"#
+        );
+    }
+}
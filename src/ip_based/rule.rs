@@ -1,9 +1,12 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufReader, Read};
 use serde_json::Value;
 use crate::ip_based::entity::{
-    SourceEntity, DestinationEntity, AttributeValue,
-    SourceEntityAttributeKey, DestinationEntityAttributeKey,
+    SourceEntity, DestinationEntity, AttributeValue, SourceEntityAttributeKey,
+    DestinationEntityAttributeKey, json_number_to_i64,
 };
+use crate::ip_based::visitor::{ConditionVisitor, walk_condition, walk_expression};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Effect {
@@ -11,10 +14,48 @@ pub enum Effect {
     Deny,
 }
 
-#[derive(Debug, Clone)]
+impl Effect {
+    /// Allow と Deny を入れ替える
+    pub fn inverted(&self) -> Effect {
+        match self {
+            Effect::Allow => Effect::Deny,
+            Effect::Deny => Effect::Allow,
+        }
+    }
+}
+
+/// 複数のルールがマッチしたときにどのルールを採用するか (ポリシーの combining_algorithm)。
+/// overrides 系で勝った effect のルールが複数マッチした場合は、定義順で最初のものを採用する。
+/// 判定 (evaluator) だけが従い、Policy::rules_applicable_to や pol-tree 用の要求の抽出は
+/// これまで通り定義順 (first-applicable) を前提にしている
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CombiningAlgorithm {
+    /// 定義順で最初にマッチしたルール ("first-applicable")
+    #[default]
+    FirstApplicable,
+    /// deny のルールが 1 つでもマッチすれば deny ("deny-overrides")
+    DenyOverrides,
+    /// allow のルールが 1 つでもマッチすれば allow ("permit-overrides")
+    PermitOverrides,
+}
+
+impl CombiningAlgorithm {
+    /// overrides 系で優先される effect (first-applicable なら None)
+    pub fn overriding_effect(&self) -> Option<Effect> {
+        match self {
+            CombiningAlgorithm::FirstApplicable => None,
+            CombiningAlgorithm::DenyOverrides => Some(Effect::Deny),
+            CombiningAlgorithm::PermitOverrides => Some(Effect::Allow),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expression {
     LiteralString(String),
     LiteralNumber(i64),
+    /// パース時に昇順・重複なしにそろえる
+    LiteralSet(Vec<String>),
 
     AttributeRef(String),
 
@@ -22,10 +63,39 @@ pub enum Expression {
 
     Add { operands: Vec<Expression> },
     Multiply { operands: Vec<Expression> },
+
+    Count { operand: Box<Expression> },
+
+    /// cond が成り立てば then、そうでなければ els の値
+    If { cond: Box<Condition>, then: Box<Expression>, els: Box<Expression> },
 }
 
-#[derive(Debug, Clone)]
+/// evaluate_with_options の設定
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EvalOptions<'a> {
+    /// エンティティに無い属性の代わりに使う値 (AttrIdMap::defaults)
+    pub defaults: Option<&'a HashMap<String, AttributeValue>>,
+    /// defaults にも無い属性をエラーにせず AttributeValue::Null として評価する。
+    /// Null を含む比較は EQ (Null 同士も含む)・GT / GTE / LT・IN・NOT_IN のいずれも false になる。
+    /// 「不明」を残す 3 値論理ではなく false に倒すので、AND は Null の項があれば false、
+    /// OR は他の項で決まる。deny ルールの条件も false になる (欠損ではマッチしない) 点に注意。
+    /// ADD / MULTIPLY / COUNT の項が Null の場合はこれまで通りエラー
+    pub missing_as_null: bool,
+}
+
+/// 評価時に訪れたノード数。重いルールを見つけるための計測用
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EvalStats {
+    pub condition_visits: usize,
+    pub expression_visits: usize,
+    pub attribute_lookups: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Condition {
+    Always,
+    Never,
+
     And { operands: Vec<Condition> },
     Or { operands: Vec<Condition> },
 
@@ -38,6 +108,11 @@ pub enum Condition {
         target: Expression,
         check_against: Expression
     },
+    /// target が check_against の集合に含まれない
+    NotIn {
+        target: Expression,
+        check_against: Expression,
+    },
     InSet {
         value: Expression,
         set: Expression,
@@ -49,7 +124,10 @@ pub struct Policy {
     pub policy_name: String,
     pub description: String,
     pub default_effect: Effect,
+    pub combining_algorithm: CombiningAlgorithm,
     pub rules: Vec<Rule>,
+    // ルールID -> rules 内の位置。rules を直接書き換えた場合は rebuild_rule_index を呼ぶこと
+    rule_index: HashMap<String, usize>,
 }
 
 #[derive(Debug, Clone)]
@@ -58,9 +136,22 @@ pub struct Rule {
     pub description: String,
     pub effect: Effect,
     pub condition: Condition,
+    pub actions: Vec<String>,
+    /// 決定時に呼び出し側へそのまま渡す義務（"log", "require_mfa" など）
+    pub obligations: Vec<String>,
 }
 
 impl Policy {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::from_reader(BufReader::new(file))
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, String> {
+        let json: Value = serde_json::from_reader(reader).map_err(|e| e.to_string())?;
+        Self::from_json_value(&json)
+    }
+
     pub fn from_json_value(value: &Value) -> Result<Self, String> {
         let policy_name = value
             .get("policy_name")
@@ -84,6 +175,15 @@ impl Policy {
             _ => return Err(format!("Invalid default_effect value: {}", default_effect)),
         };
 
+        // 省略時は first-applicable
+        let combining_algorithm = match value.get("combining_algorithm").map(|v| v.as_str()) {
+            None => CombiningAlgorithm::FirstApplicable,
+            Some(Some("first-applicable")) => CombiningAlgorithm::FirstApplicable,
+            Some(Some("deny-overrides")) => CombiningAlgorithm::DenyOverrides,
+            Some(Some("permit-overrides")) => CombiningAlgorithm::PermitOverrides,
+            Some(other) => return Err(format!("Invalid combining_algorithm value: {:?}", other)),
+        };
+
         let rules_array = value
             .get("rules")
             .and_then(|v| v.as_array())
@@ -94,13 +194,145 @@ impl Policy {
             .map(|v| Rule::from_json_value(v))
             .collect();
 
-        Ok(Policy {
+        let mut policy = Policy {
             policy_name,
             description,
             default_effect,
+            combining_algorithm,
             rules: rules?,
-        })
+            rule_index: HashMap::new(),
+        };
+        policy.rebuild_rule_index();
+        Ok(policy)
+    }
+
+    /// from_json_value と同じだが、ルールIDが重複していればエラーにする
+    /// (通常の読み込みでは lint_policy が警告する)
+    pub fn from_json_value_strict(value: &Value) -> Result<Self, String> {
+        let policy = Self::from_json_value(value)?;
+        let duplicates = policy.duplicate_rule_ids();
+        if !duplicates.is_empty() {
+            return Err(format!("Duplicate rule ids in policy {}: {}", policy.policy_name, duplicates.join(", ")));
+        }
+        Ok(policy)
+    }
+
+    /// 2 回以上使われているルールID (ソート済み、各 1 回)
+    pub fn duplicate_rule_ids(&self) -> Vec<String> {
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for rule in &self.rules {
+            *counts.entry(rule.id.as_str()).or_insert(0) += 1;
+        }
+        counts
+            .into_iter()
+            .filter(|(_, n)| *n > 1)
+            .map(|(id, _)| id.to_string())
+            .collect()
+    }
+
+    pub fn attributes_referenced(&self) -> BTreeSet<String> {
+        self.rules
+            .iter()
+            .flat_map(|r| r.condition.attributes_referenced())
+            .collect()
+    }
+
+    pub fn rebuild_rule_index(&mut self) {
+        self.rule_index.clear();
+        for (i, rule) in self.rules.iter().enumerate() {
+            self.rule_index.entry(rule.id.clone()).or_insert(i);
+        }
+    }
+
+    // rule_index が指す位置に本当にそのルールがあるか確かめる。
+    // rules が直接書き換えられて食い違っていれば先頭から探し直す
+    fn position_of(&self, id: &str) -> Option<usize> {
+        match self.rule_index.get(id) {
+            Some(&i) if self.rules.get(i).is_some_and(|r| r.id == id) => Some(i),
+            _ => self.rules.iter().position(|r| r.id == id),
+        }
+    }
+
+    /// ルールIDは一意であることを前提とする。重複している場合は先頭のルールを返す
+    pub fn rule_by_id(&self, id: &str) -> Option<&Rule> {
+        self.position_of(id).map(|i| &self.rules[i])
+    }
+
+    /// 同じIDのルールがあればその位置で置き換え、無ければ末尾に追加する
+    pub fn update_rule(&mut self, rule: Rule) {
+        let position = self.position_of(&rule.id);
+        // 索引が古くなっていたら (rules を直接書き換えた後など) 作り直す
+        if position != self.rule_index.get(&rule.id).copied() {
+            self.rebuild_rule_index();
+        }
+        match position {
+            Some(i) => self.rules[i] = rule,
+            None => {
+                self.rule_index.insert(rule.id.clone(), self.rules.len());
+                self.rules.push(rule);
+            }
+        }
+    }
+
+    pub fn remove_rule(&mut self, id: &str) -> Option<Rule> {
+        let i = self.position_of(id)?;
+        let removed = self.rules.remove(i);
+        self.rebuild_rule_index();
+        Some(removed)
     }
+
+    /// 条件中の属性名の別名を正規の名前に置き換える
+    pub fn resolve_aliases(&mut self, aliases: &HashMap<String, String>) {
+        for rule in &mut self.rules {
+            rule.condition.resolve_aliases(aliases);
+        }
+    }
+
+    /// ルール数や参照している属性などの概要 (ツールやログ向け)
+    pub fn summary(&self) -> PolicySummary {
+        let allow_rules = self.rules.iter().filter(|r| r.effect == Effect::Allow).count();
+        PolicySummary {
+            rule_count: self.rules.len(),
+            allow_rules,
+            deny_rules: self.rules.len() - allow_rules,
+            default_effect: self.default_effect.clone(),
+            attributes_referenced: self.attributes_referenced(),
+            max_condition_depth: self.rules.iter().map(|r| r.condition.depth()).max().unwrap_or(0),
+        }
+    }
+
+    /// dest に適用され得るルール (list_applicable_rules_per_dest_entity と同じ判定) を定義順に返す。
+    /// combining_algorithm は見ないので、overrides 系のポリシーでは優先順とは一致しない
+    pub fn rules_applicable_to<'a>(&'a self, dest: &DestinationEntity) -> Vec<&'a Rule> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.is_applicable_to_dest(dest))
+            .collect()
+    }
+}
+
+/// Policy::summary の結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicySummary {
+    pub rule_count: usize,
+    pub allow_rules: usize,
+    pub deny_rules: usize,
+    pub default_effect: Effect,
+    pub attributes_referenced: BTreeSet<String>,
+    // 最も深い条件の depth (ルールが無ければ 0)
+    pub max_condition_depth: usize,
+}
+
+/// 複数のポリシーファイルを読み込み、paths と同じ順で返す。
+/// 読み込みや解析に失敗した場合はそのファイル名をエラーに含める
+pub fn load_policies(paths: &[&str]) -> Result<Vec<Policy>, String> {
+    paths
+        .iter()
+        .map(|path| {
+            let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+            Policy::from_reader(BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))
+        })
+        .collect()
 }
 
 impl Rule {
@@ -132,14 +364,51 @@ impl Rule {
             .ok_or("Missing condition")?;
         let condition = Condition::from_json_value(condition)?;
 
+        // actions が無い場合は全アクションに適用される
+        let actions = Self::parse_string_list(value, "actions")?;
+        let obligations = Self::parse_string_list(value, "obligations")?;
+
         Ok(Rule {
             id,
             description,
             effect,
             condition,
+            actions,
+            obligations,
         })
     }
 
+    /// 省略可能な文字列配列フィールドを読む（無ければ空）
+    fn parse_string_list(value: &Value, field: &str) -> Result<Vec<String>, String> {
+        match value.get(field) {
+            Some(Value::Array(arr)) => arr
+                .iter()
+                .map(|v| {
+                    v.as_str()
+                        .map(|s| s.to_string())
+                        .ok_or_else(|| format!("Entry of {} is not a string: {:?}", field, v))
+                })
+                .collect(),
+            Some(other) => Err(format!("Invalid {} field: {:?}", field, other)),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn applies_to_action(&self, action: &str) -> bool {
+        self.actions.is_empty() || self.actions.iter().any(|a| a == action)
+    }
+
+    /// dest だけを見て、このルールがいずれかの source に適用され得るか
+    /// (Condition::evaluate_dest_only による過大評価)。
+    /// Dst を参照しない条件は、FALSE のように dest に関係なく偽と分かるときだけ false
+    pub fn is_applicable_to_dest(&self, dest: &DestinationEntity) -> bool {
+        let result = self.condition.evaluate_dest_only(dest);
+        if !self.condition.references_dst() {
+            return result != Ok(false);
+        }
+        result == Ok(true)
+    }
+
     pub fn matches(
         &self,
         source: &SourceEntity,
@@ -148,16 +417,47 @@ impl Rule {
     ) -> Result<bool, String> {
         self.condition.evaluate(source, destination, env)
     }
+
+    /// matches と同じだが、欠損属性の扱いなどを opts で指定する
+    pub fn matches_with_options(
+        &self,
+        source: &SourceEntity,
+        destination: &DestinationEntity,
+        env: &HashMap<String, AttributeValue>,
+        opts: &EvalOptions,
+    ) -> Result<bool, String> {
+        self.condition.evaluate_with_options(source, destination, env, opts)
+    }
 }
 
+/// Condition / Expression の JSON を読み込む際の既定の最大ネスト深さ
+pub const DEFAULT_MAX_CONDITION_DEPTH: usize = 64;
+
 impl Condition {
     pub fn from_json_value(value: &Value) -> Result<Self, String> {
+        Self::from_json_value_with_max_depth(value, DEFAULT_MAX_CONDITION_DEPTH)
+    }
+
+    /// 信頼できない入力でスタックを溢れさせないよう、ネストが
+    /// `max_depth` を超える Condition / Expression はエラーにする
+    pub fn from_json_value_with_max_depth(value: &Value, max_depth: usize) -> Result<Self, String> {
+        Self::parse(value, 1, max_depth)
+    }
+
+    fn parse(value: &Value, depth: usize, max_depth: usize) -> Result<Self, String> {
+        if depth > max_depth {
+            return Err(format!("Condition is nested too deeply (max depth {})", max_depth));
+        }
+
         let operator = value
             .get("operator")
             .and_then(|v| v.as_str())
             .ok_or("Missing operator")?;
 
         match operator {
+            "TRUE" => Ok(Condition::Always),
+            "FALSE" => Ok(Condition::Never),
+
             "AND" | "OR" => {
                 let operands_array = value
                     .get("operands")
@@ -166,7 +466,7 @@ impl Condition {
 
                 let operands: Result<Vec<Condition>, String> = operands_array
                     .iter()
-                    .map(|v| Condition::from_json_value(v))
+                    .map(|v| Condition::parse(v, depth + 1, max_depth))
                     .collect();
 
                 match operator {
@@ -184,8 +484,8 @@ impl Condition {
                     .get("rhs")
                     .ok_or("Missing rhs")?;
 
-                let lhs_expr = Expression::from_json_value(lhs)?;
-                let rhs_expr = Expression::from_json_value(rhs)?;
+                let lhs_expr = Expression::parse(lhs, depth + 1, max_depth)?;
+                let rhs_expr = Expression::parse(rhs, depth + 1, max_depth)?;
 
                 match operator {
                     "EQ" => Ok(Condition::Eq { lhs: lhs_expr, rhs: rhs_expr }),
@@ -204,8 +504,8 @@ impl Condition {
                         .ok_or("Missing check_against for IN operator")?;
                     
                     Ok(Condition::In {
-                        target: Expression::from_json_value(target)?,
-                        check_against: Expression::from_json_value(check_against)?,
+                        target: Expression::parse(target, depth + 1, max_depth)?,
+                        check_against: Expression::parse(check_against, depth + 1, max_depth)?,
                     })
                 } else if let Some(value_expr) = value.get("value") {
                     let set = value
@@ -213,14 +513,25 @@ impl Condition {
                         .ok_or("Missing set for IN operator")?;
                     
                     Ok(Condition::InSet {
-                        value: Expression::from_json_value(value_expr)?,
-                        set: Expression::from_json_value(set)?,
+                        value: Expression::parse(value_expr, depth + 1, max_depth)?,
+                        set: Expression::parse(set, depth + 1, max_depth)?,
                     })
                 } else {
                     Err("IN operator requires either (target, check_against) or (value, set)".to_string())
                 }
             }
 
+            "NOT_IN" => {
+                let target = value.get("target").ok_or("Missing target for NOT_IN operator")?;
+                let check_against = value
+                    .get("check_against")
+                    .ok_or("Missing check_against for NOT_IN operator")?;
+                Ok(Condition::NotIn {
+                    target: Expression::parse(target, depth + 1, max_depth)?,
+                    check_against: Expression::parse(check_against, depth + 1, max_depth)?,
+                })
+            }
+
             _ => Err(format!("Unknown operator: {}", operator)),
         }
     }
@@ -231,10 +542,60 @@ impl Condition {
         destination: &DestinationEntity,
         env: &HashMap<String, AttributeValue>,
     ) -> Result<bool, String> {
+        self.evaluate_with_stats(source, destination, env, &mut EvalStats::default())
+    }
+
+    /// evaluate と同じだが、評価したノード数などを stats に加算する
+    pub fn evaluate_with_stats(
+        &self,
+        source: &SourceEntity,
+        destination: &DestinationEntity,
+        env: &HashMap<String, AttributeValue>,
+        stats: &mut EvalStats,
+    ) -> Result<bool, String> {
+        self.eval(source, destination, env, &EvalOptions::default(), stats)
+    }
+
+    /// エンティティに無い属性を defaults (AttrIdMap::defaults) の値で補って評価する。
+    /// defaults にも無い属性はこれまで通りエラー
+    pub fn evaluate_with_defaults(
+        &self,
+        source: &SourceEntity,
+        destination: &DestinationEntity,
+        env: &HashMap<String, AttributeValue>,
+        defaults: &HashMap<String, AttributeValue>,
+    ) -> Result<bool, String> {
+        let opts = EvalOptions { defaults: Some(defaults), ..EvalOptions::default() };
+        self.eval(source, destination, env, &opts, &mut EvalStats::default())
+    }
+
+    /// opts の設定で評価する。missing_as_null の場合の扱いは EvalOptions を参照
+    pub fn evaluate_with_options(
+        &self,
+        source: &SourceEntity,
+        destination: &DestinationEntity,
+        env: &HashMap<String, AttributeValue>,
+        opts: &EvalOptions,
+    ) -> Result<bool, String> {
+        self.eval(source, destination, env, opts, &mut EvalStats::default())
+    }
+
+    fn eval(
+        &self,
+        source: &SourceEntity,
+        destination: &DestinationEntity,
+        env: &HashMap<String, AttributeValue>,
+        opts: &EvalOptions,
+        stats: &mut EvalStats,
+    ) -> Result<bool, String> {
+        stats.condition_visits += 1;
         match self {
+            Condition::Always => Ok(true),
+            Condition::Never => Ok(false),
+
             Condition::And { operands } => {
                 for cond in operands {
-                    if !cond.evaluate(source, destination, env)? {
+                    if !cond.eval(source, destination, env, opts, stats)? {
                         return Ok(false);
                     }
                 }
@@ -243,7 +604,7 @@ impl Condition {
             
             Condition::Or { operands } => {
                 for cond in operands {
-                    if cond.evaluate(source, destination, env)? {
+                    if cond.eval(source, destination, env, opts, stats)? {
                         return Ok(true);
                     }
                 }
@@ -251,51 +612,45 @@ impl Condition {
             }
             
             Condition::Eq { lhs, rhs } => {
-                let lhs_val = lhs.evaluate(source, destination, env)?;
-                let rhs_val = rhs.evaluate(source, destination, env)?;
-                Ok(lhs_val == rhs_val)
+                let lhs_val = lhs.eval(source, destination, env, opts, stats)?;
+                let rhs_val = rhs.eval(source, destination, env, opts, stats)?;
+                Ok(Self::values_equal(&lhs_val, &rhs_val))
             }
             
             Condition::Gte { lhs, rhs } => {
-                let lhs_val = lhs.evaluate(source, destination, env)?;
-                let rhs_val = rhs.evaluate(source, destination, env)?;
+                let lhs_val = lhs.eval(source, destination, env, opts, stats)?;
+                let rhs_val = rhs.eval(source, destination, env, opts, stats)?;
                 Self::compare_values(&lhs_val, &rhs_val, |a, b| a >= b)
             }
             
             Condition::Lt { lhs, rhs } => {
-                let lhs_val = lhs.evaluate(source, destination, env)?;
-                let rhs_val = rhs.evaluate(source, destination, env)?;
+                let lhs_val = lhs.eval(source, destination, env, opts, stats)?;
+                let rhs_val = rhs.eval(source, destination, env, opts, stats)?;
                 Self::compare_values(&lhs_val, &rhs_val, |a, b| a < b)
             }
             
             Condition::Gt { lhs, rhs } => {
-                let lhs_val = lhs.evaluate(source, destination, env)?;
-                let rhs_val = rhs.evaluate(source, destination, env)?;
+                let lhs_val = lhs.eval(source, destination, env, opts, stats)?;
+                let rhs_val = rhs.eval(source, destination, env, opts, stats)?;
                 Self::compare_values(&lhs_val, &rhs_val, |a, b| a > b)
             }
             
             Condition::In { target, check_against } => {
-                let target_val = target.evaluate(source, destination, env)?;
-                let set_val = check_against.evaluate(source, destination, env)?;
-                
-                match (&target_val, &set_val) {
-                    (AttributeValue::String(s), AttributeValue::Set(set)) => {
-                        Ok(set.contains(s))
-                    }
-                    _ => Err("IN operator requires String and Set".to_string()),
-                }
+                let target_val = target.eval(source, destination, env, opts, stats)?;
+                let set_val = check_against.eval(source, destination, env, opts, stats)?;
+                Self::set_contains(&target_val, &set_val)
+            }
+
+            Condition::NotIn { target, check_against } => {
+                let target_val = target.eval(source, destination, env, opts, stats)?;
+                let set_val = check_against.eval(source, destination, env, opts, stats)?;
+                Self::set_excludes(&target_val, &set_val)
             }
             
             Condition::InSet { value, set } => {
-                let value_val = value.evaluate(source, destination, env)?;
-                let set_val = set.evaluate(source, destination, env)?;
-                
-                match (&value_val, &set_val) {
-                    (AttributeValue::String(s), AttributeValue::Set(set)) => {
-                        Ok(set.contains(s))
-                    }
-                    _ => Err("IN operator requires String and Set".to_string()),
-                }
+                let value_val = value.eval(source, destination, env, opts, stats)?;
+                let set_val = set.eval(source, destination, env, opts, stats)?;
+                Self::set_contains(&value_val, &set_val)
             }
         }
     }
@@ -305,121 +660,366 @@ impl Condition {
     where
         F: Fn(i64, i64) -> bool,
     {
-        match (lhs, rhs) {
-            (AttributeValue::Number(a), AttributeValue::Number(b)) => Ok(cmp(*a, *b)),
-            _ => Err("Comparison requires numbers".to_string()),
+        if *lhs == AttributeValue::Null || *rhs == AttributeValue::Null {
+            return Ok(false);
+        }
+        // 集合を要素数として比較することはしない。意図が要素数なら COUNT を明示してもらう
+        if matches!(lhs, AttributeValue::Set(_)) || matches!(rhs, AttributeValue::Set(_)) {
+            return Err(format!(
+                "Comparison requires numbers, got a set ({:?} vs {:?}); \
+                 to compare the number of elements use {{\"operator\": \"COUNT\", \"operand\": ...}}",
+                lhs, rhs
+            ));
         }
+        let a = lhs.as_number().map_err(|e| format!("Comparison requires numbers: {}", e))?;
+        let b = rhs.as_number().map_err(|e| format!("Comparison requires numbers: {}", e))?;
+        Ok(cmp(a, b))
+    }
+
+    /// EQ 演算子のヘルパー関数。Null を含む比較は (Null 同士でも) false
+    fn values_equal(lhs: &AttributeValue, rhs: &AttributeValue) -> bool {
+        *lhs != AttributeValue::Null && *rhs != AttributeValue::Null && lhs == rhs
+    }
+
+    /// NOT_IN 演算子のヘルパー関数。IN と同じく Null を含むなら false
+    fn set_excludes(value: &AttributeValue, set: &AttributeValue) -> Result<bool, String> {
+        if *value == AttributeValue::Null || *set == AttributeValue::Null {
+            return Ok(false);
+        }
+        Self::set_contains(value, set).map(|found| !found)
+    }
+
+    /// IN 演算子のヘルパー関数
+    fn set_contains(value: &AttributeValue, set: &AttributeValue) -> Result<bool, String> {
+        if *value == AttributeValue::Null || *set == AttributeValue::Null {
+            return Ok(false);
+        }
+        let s = value.as_string().map_err(|e| format!("IN operator requires String and Set: {}", e))?;
+        let set = set.as_set().map_err(|e| format!("IN operator requires String and Set: {}", e))?;
+        Ok(set.iter().any(|item| item == s))
+    }
+
+    pub fn attributes_referenced(&self) -> BTreeSet<String> {
+        let mut collector = AttributeRefCollector::default();
+        collector.visit_condition(self);
+        collector.names
     }
 
     pub fn references_dst(&self) -> bool {
+        self.references_prefix("Dst.")
+    }
+
+    fn references_prefix(&self, prefix: &str) -> bool {
+        let mut finder = PrefixFinder { prefix, found: false };
+        finder.visit_condition(self);
+        finder.found
+    }
+
+    /// name と一致する AttributeRef / EnvRef を含むか
+    pub fn references_attribute(&self, name: &str) -> bool {
+        let mut finder = ReferenceFinder { name, found: false };
+        finder.visit_condition(self);
+        finder.found
+    }
+
+    /// 入れ子の深さ。比較 1 つだけなら 1 で、AND / OR と ADD などの式の入れ子ごとに 1 増える
+    pub fn depth(&self) -> usize {
         match self {
+            Condition::Always | Condition::Never => 1,
             Condition::And { operands } | Condition::Or { operands } => {
-                operands.iter().any(|c| c.references_dst())
+                1 + operands.iter().map(|c| c.depth()).max().unwrap_or(0)
             }
             Condition::Eq { lhs, rhs }
             | Condition::Gte { lhs, rhs }
-            | Condition::Gt {lhs, rhs}
-            | Condition::Lt {lhs, rhs} => lhs.references_dst() || rhs.references_dst(),
-            Condition::In { target, check_against } => {
-                target.references_dst() || check_against.references_dst()
+            | Condition::Gt { lhs, rhs }
+            | Condition::Lt { lhs, rhs }
+            | Condition::In { target: lhs, check_against: rhs }
+            | Condition::NotIn { target: lhs, check_against: rhs }
+            | Condition::InSet { value: lhs, set: rhs } => 1 + lhs.depth().max(rhs.depth()),
+        }
+    }
+
+    /// 条件と式のノードの総数
+    pub fn size(&self) -> usize {
+        let mut counter = NodeCounter::default();
+        counter.visit_condition(self);
+        counter.nodes
+    }
+
+    pub fn resolve_aliases(&mut self, aliases: &HashMap<String, String>) {
+        match self {
+            Condition::Always | Condition::Never => {}
+            Condition::And { operands } | Condition::Or { operands } => {
+                for c in operands {
+                    c.resolve_aliases(aliases);
+                }
+            }
+            Condition::Eq { lhs, rhs }
+            | Condition::Gte { lhs, rhs }
+            | Condition::Gt { lhs, rhs }
+            | Condition::Lt { lhs, rhs }
+            | Condition::In { target: lhs, check_against: rhs }
+            | Condition::NotIn { target: lhs, check_against: rhs }
+            | Condition::InSet { value: lhs, set: rhs } => {
+                lhs.resolve_aliases(aliases);
+                rhs.resolve_aliases(aliases);
             }
-            Condition::InSet { value, set } => value.references_dst() || set.references_dst(),
         }
     }
 
+    /// 条件中のすべての Expression (IF の中の条件が持つものも含む) に f を適用する。
+    /// 子を先に処理するので、f が置き換えたノードの中身に再び f が呼ばれることはない。
+    /// リテラルの匿名化や属性名の移行など、ポリシーをその場で書き換えるのに使う
+    pub fn map_expressions<F: FnMut(&mut Expression)>(&mut self, mut f: F) {
+        self.map_expressions_dyn(&mut f);
+    }
+
+    fn map_expressions_dyn(&mut self, f: &mut dyn FnMut(&mut Expression)) {
+        match self {
+            Condition::Always | Condition::Never => {}
+            Condition::And { operands } | Condition::Or { operands } => {
+                for c in operands {
+                    c.map_expressions_dyn(f);
+                }
+            }
+            Condition::Eq { lhs, rhs }
+            | Condition::Gte { lhs, rhs }
+            | Condition::Gt { lhs, rhs }
+            | Condition::Lt { lhs, rhs }
+            | Condition::In { target: lhs, check_against: rhs }
+            | Condition::NotIn { target: lhs, check_against: rhs }
+            | Condition::InSet { value: lhs, set: rhs } => {
+                lhs.map_expressions_dyn(f);
+                rhs.map_expressions_dyn(f);
+            }
+        }
+    }
+
+    /// destination だけを見て、この条件が満たされ得るかを返す (過大評価)。
+    ///
+    /// Src / Env を参照する部分は満たされ得るものとして true に扱うので、
+    /// false が返ったときだけ「どの source でも満たされない」と言える。
     pub fn evaluate_dest_only(
         &self,
         dest_entity: &DestinationEntity,
     ) -> Result<bool, String> {
-        use std::collections::HashMap;
-        let empty_env = HashMap::new();
         let dummy_source = SourceEntity {
             ip: String::new(),
             attributes: HashMap::new(),
             desc: None,
         };
+        self.evaluate_one_side(&dummy_source, dest_entity, "Dst.", Expression::references_src_or_env)
+    }
+
+    /// evaluate_dest_only の source 版。Dst / Env を参照する部分は満たされ得るものとし、
+    /// false が返ったときだけ「どの destination でも満たされない」と言える。
+    pub fn evaluate_source_only(
+        &self,
+        source_entity: &SourceEntity,
+    ) -> Result<bool, String> {
+        let dummy_destination = DestinationEntity {
+            ip: String::new(),
+            attributes: HashMap::new(),
+            desc: None,
+        };
+        self.evaluate_one_side(source_entity, &dummy_destination, "Src.", Expression::references_dst_or_env)
+    }
+
+    // fixed_prefix の側だけが決まっているものとして評価する。
+    // is_free が true の (決まっていない側を参照する) 比較は満たされ得るものとする
+    fn evaluate_one_side(
+        &self,
+        source: &SourceEntity,
+        destination: &DestinationEntity,
+        fixed_prefix: &str,
+        is_free: fn(&Expression) -> bool,
+    ) -> Result<bool, String> {
+        let empty_env = HashMap::new();
+        let side = |c: &Condition| c.evaluate_one_side(source, destination, fixed_prefix, is_free);
 
         match self {
+            Condition::Always => Ok(true),
+            Condition::Never => Ok(false),
             Condition::And { operands } => {
                 for c in operands {
-                    if c.references_dst() && !c.evaluate_dest_only(dest_entity)? {
+                    // 決まっている側を参照しない項は評価できなくても満たされ得るものとし、
+                    // FALSE のように確実に偽のときだけ AND 全体を偽にする
+                    let unsatisfiable = if c.references_prefix(fixed_prefix) {
+                        !side(c)?
+                    } else {
+                        side(c) == Ok(false)
+                    };
+                    if unsatisfiable {
                         return Ok(false);
                     }
                 }
                 Ok(true)
             }
+            // 決まっていない側だけを参照する分岐は満たされ得るので、
+            // どれか 1 つの分岐が満たされ得れば OR 全体も満たされ得る
             Condition::Or { operands } => {
-                let mut has_dst = false;
                 for c in operands {
-                    if c.references_dst() {
-                        has_dst = true;
-                        if c.evaluate_dest_only(dest_entity)? {
-                            return Ok(true);
-                        }
+                    if side(c)? {
+                        return Ok(true);
                     }
                 }
-                Ok(!has_dst)
+                Ok(false)
             }
             Condition::Eq { lhs, rhs } => {
-                if lhs.references_src_or_env() || rhs.references_src_or_env() {
+                if is_free(lhs) || is_free(rhs) {
                     return Ok(true);
                 }
-                let l = lhs.evaluate(&dummy_source, dest_entity, &empty_env)?;
-                let r = rhs.evaluate(&dummy_source, dest_entity, &empty_env)?;
-                Ok(l == r)
+                let l = lhs.evaluate(source, destination, &empty_env)?;
+                let r = rhs.evaluate(source, destination, &empty_env)?;
+                Ok(Self::values_equal(&l, &r))
             }
             Condition::Gte { lhs, rhs } => {
-                if lhs.references_src_or_env() || rhs.references_src_or_env() {
+                if is_free(lhs) || is_free(rhs) {
                     return Ok(true);
                 }
-                let l = lhs.evaluate(&dummy_source, dest_entity, &empty_env)?;
-                let r = rhs.evaluate(&dummy_source, dest_entity, &empty_env)?;
+                let l = lhs.evaluate(source, destination, &empty_env)?;
+                let r = rhs.evaluate(source, destination, &empty_env)?;
                 Self::compare_values(&l, &r, |a, b| a >= b)
             }
             Condition::Gt { lhs, rhs } => {
-                if lhs.references_src_or_env() || rhs.references_src_or_env() {
+                if is_free(lhs) || is_free(rhs) {
                     return Ok(true);
                 }
-                let l = lhs.evaluate(&dummy_source, dest_entity, &empty_env)?;
-                let r = rhs.evaluate(&dummy_source, dest_entity, &empty_env)?;
+                let l = lhs.evaluate(source, destination, &empty_env)?;
+                let r = rhs.evaluate(source, destination, &empty_env)?;
                 Self::compare_values(&l, &r, |a, b| a > b)
             }
             Condition::Lt { lhs, rhs } => {
-                if lhs.references_src_or_env() || rhs.references_src_or_env() {
+                if is_free(lhs) || is_free(rhs) {
                     return Ok(true);
                 }
-                let l = lhs.evaluate(&dummy_source, dest_entity, &empty_env)?;
-                let r = rhs.evaluate(&dummy_source, dest_entity, &empty_env)?;
+                let l = lhs.evaluate(source, destination, &empty_env)?;
+                let r = rhs.evaluate(source, destination, &empty_env)?;
                 Self::compare_values(&l, &r, |a, b| a < b)
             }
             Condition::In { target, check_against } => {
-                if target.references_src_or_env() || check_against.references_src_or_env() {
+                if is_free(target) || is_free(check_against) {
                     return Ok(true);
                 }
-                let t = target.evaluate(&dummy_source, dest_entity, &empty_env)?;
-                let c = check_against.evaluate(&dummy_source, dest_entity, &empty_env)?;
-                match (&t, &c) {
-                    (AttributeValue::String(s), AttributeValue::Set(set)) => Ok(set.contains(s)),
-                    _ => Err("IN operator requires String and Set".to_string()),
+                let t = target.evaluate(source, destination, &empty_env)?;
+                let c = check_against.evaluate(source, destination, &empty_env)?;
+                Self::set_contains(&t, &c)
+            }
+            // 決まっていない側によっては「含まれない」可能性があるので true。
+            // In の結果を単に反転すると、過大評価の true が false になってしまう
+            Condition::NotIn { target, check_against } => {
+                if is_free(target) || is_free(check_against) {
+                    return Ok(true);
                 }
+                let t = target.evaluate(source, destination, &empty_env)?;
+                let c = check_against.evaluate(source, destination, &empty_env)?;
+                Self::set_excludes(&t, &c)
             }
             Condition::InSet { value, set } => {
-                if value.references_src_or_env() || set.references_src_or_env() {
+                if is_free(value) || is_free(set) {
                     return Ok(true);
                 }
-                let v = value.evaluate(&dummy_source, dest_entity, &empty_env)?;
-                let s = set.evaluate(&dummy_source, dest_entity, &empty_env)?;
-                match (&v, &s) {
-                    (AttributeValue::String(st), AttributeValue::Set(set)) => Ok(set.contains(st)),
-                    _ => Err("IN operator requires String and Set".to_string()),
-                }
+                let v = value.evaluate(source, destination, &empty_env)?;
+                let s = set.evaluate(source, destination, &empty_env)?;
+                Self::set_contains(&v, &s)
             }
         }
     }
 }
 
+// AttributeRef の属性名を集める
+#[derive(Default)]
+struct AttributeRefCollector {
+    names: BTreeSet<String>,
+}
+
+impl ConditionVisitor for AttributeRefCollector {
+    fn visit_expression(&mut self, expr: &Expression) {
+        if let Expression::AttributeRef(name) = expr {
+            self.names.insert(name.clone());
+        }
+        walk_expression(self, expr);
+    }
+}
+
+struct ReferenceFinder<'a> {
+    name: &'a str,
+    found: bool,
+}
+
+// 条件の中の比較の両辺のいずれかが pred を満たすか (pred は式の中を自分でたどる)
+fn condition_has_expression(cond: &Condition, pred: &dyn Fn(&Expression) -> bool) -> bool {
+    struct Finder<'a> {
+        pred: &'a dyn Fn(&Expression) -> bool,
+        found: bool,
+    }
+    impl ConditionVisitor for Finder<'_> {
+        fn visit_expression(&mut self, expr: &Expression) {
+            self.found = self.found || (self.pred)(expr);
+        }
+    }
+    let mut finder = Finder { pred, found: false };
+    finder.visit_condition(cond);
+    finder.found
+}
+
+#[derive(Default)]
+struct NodeCounter {
+    nodes: usize,
+}
+
+impl ConditionVisitor for NodeCounter {
+    fn visit_condition(&mut self, cond: &Condition) {
+        self.nodes += 1;
+        walk_condition(self, cond);
+    }
+
+    fn visit_expression(&mut self, expr: &Expression) {
+        self.nodes += 1;
+        walk_expression(self, expr);
+    }
+}
+
+impl ConditionVisitor for ReferenceFinder<'_> {
+    fn visit_expression(&mut self, expr: &Expression) {
+        if self.found {
+            return;
+        }
+        match expr {
+            Expression::AttributeRef(n) | Expression::EnvRef(n) if n == self.name => self.found = true,
+            _ => walk_expression(self, expr),
+        }
+    }
+}
+
+// prefix で始まる AttributeRef を含むか。見つかった時点でそれ以上たどらない
+struct PrefixFinder<'a> {
+    prefix: &'a str,
+    found: bool,
+}
+
+impl ConditionVisitor for PrefixFinder<'_> {
+    fn visit_expression(&mut self, expr: &Expression) {
+        if self.found {
+            return;
+        }
+        match expr {
+            Expression::AttributeRef(n) if n.starts_with(self.prefix) => self.found = true,
+            _ => walk_expression(self, expr),
+        }
+    }
+}
+
 impl Expression {
     pub fn from_json_value(value: &Value) -> Result<Self, String> {
+        Self::parse(value, 1, DEFAULT_MAX_CONDITION_DEPTH)
+    }
+
+    fn parse(value: &Value, depth: usize, max_depth: usize) -> Result<Self, String> {
+        if depth > max_depth {
+            return Err(format!("Expression is nested too deeply (max depth {})", max_depth));
+        }
+
         match value {
             Value::String(s) => {
                 if s.starts_with("Src.") || s.starts_with("Dst.") {
@@ -431,12 +1031,22 @@ impl Expression {
                 }
             }
             
-            Value::Number(n) => {
-                n.as_i64()
-                    .ok_or_else(|| format!("Cannot convert to i64: {}", n))
-                    .map(Expression::LiteralNumber)
-            }
+            Value::Number(n) => json_number_to_i64(n).map(Expression::LiteralNumber),
             
+            // エンティティの集合 (AttributeValue::new_set) と同じく昇順・重複なしにそろえる
+            Value::Array(arr) => {
+                let mut items = arr.iter()
+                    .map(|v| {
+                        v.as_str()
+                            .map(|s| s.to_string())
+                            .ok_or_else(|| format!("Set literal element is not a string: {:?}", v))
+                    })
+                    .collect::<Result<Vec<String>, String>>()?;
+                items.sort();
+                items.dedup();
+                Ok(Expression::LiteralSet(items))
+            }
+
             Value::Object(obj) => {
                 if let Some(op) = obj.get("operator").and_then(|v| v.as_str()) {
                     match op {
@@ -447,7 +1057,7 @@ impl Expression {
                                 .ok_or("Missing 'operands' for ADD")?;
                             let operands: Result<Vec<Expression>, String> = operands_array
                                 .iter()
-                                .map(|v| Expression::from_json_value(v))
+                                .map(|v| Expression::parse(v, depth + 1, max_depth))
                                 .collect();
                             Ok(Expression::Add { operands: operands? })
                         }
@@ -458,10 +1068,28 @@ impl Expression {
                                 .ok_or("Missing 'operands' for MULTIPLY")?;
                             let operands: Result<Vec<Expression>, String> = operands_array
                                 .iter()
-                                .map(|v| Expression::from_json_value(v))
+                                .map(|v| Expression::parse(v, depth + 1, max_depth))
                                 .collect();
                             Ok(Expression::Multiply { operands: operands? })
                         }
+                        "COUNT" => {
+                            let operand = obj
+                                .get("operand")
+                                .ok_or("Missing 'operand' for COUNT")?;
+                            Ok(Expression::Count {
+                                operand: Box::new(Expression::parse(operand, depth + 1, max_depth)?),
+                            })
+                        }
+                        "IF" => {
+                            let cond = obj.get("cond").ok_or("Missing 'cond' for IF")?;
+                            let then = obj.get("then").ok_or("Missing 'then' for IF")?;
+                            let els = obj.get("else").ok_or("Missing 'else' for IF")?;
+                            Ok(Expression::If {
+                                cond: Box::new(Condition::parse(cond, depth + 1, max_depth)?),
+                                then: Box::new(Expression::parse(then, depth + 1, max_depth)?),
+                                els: Box::new(Expression::parse(els, depth + 1, max_depth)?),
+                            })
+                        }
                         _ => Err(format!("Unknown expression operator: {}", op)),
                     }
                 } else {
@@ -479,15 +1107,53 @@ impl Expression {
         destination: &DestinationEntity,
         env: &HashMap<String, AttributeValue>,
     ) -> Result<AttributeValue, String> {
+        self.evaluate_with_stats(source, destination, env, &mut EvalStats::default())
+    }
+
+    /// evaluate と同じだが、評価したノード数などを stats に加算する
+    pub fn evaluate_with_stats(
+        &self,
+        source: &SourceEntity,
+        destination: &DestinationEntity,
+        env: &HashMap<String, AttributeValue>,
+        stats: &mut EvalStats,
+    ) -> Result<AttributeValue, String> {
+        self.eval(source, destination, env, &EvalOptions::default(), stats)
+    }
+
+    /// エンティティに無い属性を defaults (AttrIdMap::defaults) の値で補って評価する。
+    /// defaults にも無い属性はこれまで通りエラー
+    pub fn evaluate_with_defaults(
+        &self,
+        source: &SourceEntity,
+        destination: &DestinationEntity,
+        env: &HashMap<String, AttributeValue>,
+        defaults: &HashMap<String, AttributeValue>,
+    ) -> Result<AttributeValue, String> {
+        let opts = EvalOptions { defaults: Some(defaults), ..EvalOptions::default() };
+        self.eval(source, destination, env, &opts, &mut EvalStats::default())
+    }
+
+    fn eval(
+        &self,
+        source: &SourceEntity,
+        destination: &DestinationEntity,
+        env: &HashMap<String, AttributeValue>,
+        opts: &EvalOptions,
+        stats: &mut EvalStats,
+    ) -> Result<AttributeValue, String> {
+        stats.expression_visits += 1;
         match self {
             Expression::LiteralString(s) => Ok(AttributeValue::String(s.clone())),
             Expression::LiteralNumber(n) => Ok(AttributeValue::Number(*n)),
+            Expression::LiteralSet(items) => Ok(AttributeValue::Set(items.clone())),
 
             Expression::AttributeRef(attr_name) => {
+                stats.attribute_lookups += 1;
                 if attr_name.starts_with("Src.") {
-                    Self::get_source_attribute(source, attr_name)
+                    Self::get_source_attribute(source, attr_name, opts)
                 } else if attr_name.starts_with("Dst.") {
-                    Self::get_destination_attribute(destination, attr_name)
+                    Self::get_destination_attribute(destination, attr_name, opts)
                 } else {
                     Err(format!("Unknown attribute reference: {}", attr_name))
                 }
@@ -503,83 +1169,239 @@ impl Expression {
                 let values: Result<Vec<i64>, String> = operands
                     .iter()
                     .map(|expr| {
-                        let val = expr.evaluate(source, destination, env)?;
-                        match val {
-                            AttributeValue::Number(n) => Ok(n),
-                            _ => Err("ADD operands must be numbers".to_string()),
-                        }
+                        expr.eval(source, destination, env, opts, stats)?
+                            .as_number()
+                            .map_err(|e| format!("ADD operands must be numbers: {}", e))
                     })
                     .collect();
-                Ok(AttributeValue::Number(values?.iter().sum()))
+                values?
+                    .iter()
+                    .try_fold(0i64, |acc, v| acc.checked_add(*v))
+                    .map(AttributeValue::Number)
+                    .ok_or_else(|| "ADD overflowed i64".to_string())
             }
             
             Expression::Multiply { operands } => {
                 let values: Result<Vec<i64>, String> = operands
                     .iter()
                     .map(|expr| {
-                        let val = expr.evaluate(source, destination, env)?;
-                        match val {
-                            AttributeValue::Number(n) => Ok(n),
-                            _ => Err("MULTIPLY operands must be numbers".to_string()),
-                        }
+                        expr.eval(source, destination, env, opts, stats)?
+                            .as_number()
+                            .map_err(|e| format!("MULTIPLY operands must be numbers: {}", e))
                     })
                     .collect();
-                Ok(AttributeValue::Number(values?.iter().product()))
+                values?
+                    .iter()
+                    .try_fold(1i64, |acc, v| acc.checked_mul(*v))
+                    .map(AttributeValue::Number)
+                    .ok_or_else(|| "MULTIPLY overflowed i64".to_string())
+            }
+
+            Expression::Count { operand } => {
+                let val = operand.eval(source, destination, env, opts, stats)?;
+                let set = val
+                    .as_set()
+                    .map_err(|e| format!("COUNT operand must be a set: {}", e))?;
+                Ok(AttributeValue::Number(set.len() as i64))
+            }
+
+            Expression::If { cond, then, els } => {
+                if cond.eval(source, destination, env, opts, stats)? {
+                    then.eval(source, destination, env, opts, stats)
+                } else {
+                    els.eval(source, destination, env, opts, stats)
+                }
             }
         }
     }
     
+    /// "Src.Location.Country" を ("Src.Location", ["Country"]) に分割する
+    fn split_attribute_path(attr_name: &str) -> (&str, Vec<&str>) {
+        let mut parts = attr_name.splitn(3, '.');
+        let prefix_len = match (parts.next(), parts.next()) {
+            (Some(prefix), Some(field)) => prefix.len() + 1 + field.len(),
+            _ => attr_name.len(),
+        };
+        let (top, rest) = attr_name.split_at(prefix_len);
+        let path = rest
+            .strip_prefix('.')
+            .map(|r| r.split('.').collect())
+            .unwrap_or_default();
+        (top, path)
+    }
+
+    fn resolve_attribute_path(
+        value: &AttributeValue,
+        path: &[&str],
+        attr_name: &str,
+        missing_as_null: bool,
+    ) -> Result<AttributeValue, String> {
+        let mut current = value;
+        for segment in path {
+            current = match current {
+                AttributeValue::Object(fields) => match fields.get(*segment) {
+                    Some(field) => field,
+                    None if missing_as_null => return Ok(AttributeValue::Null),
+                    None => return Err(format!("Attribute not found: {}", attr_name)),
+                },
+                _ => return Err(format!("Attribute {} is not an object at '{}'", attr_name, segment)),
+            };
+        }
+        Ok(current.clone())
+    }
+
     fn get_source_attribute(
         source: &SourceEntity,
         attr_name: &str,
+        opts: &EvalOptions,
     ) -> Result<AttributeValue, String> {
-        match attr_name {
-            "Src.Role" => source.attributes.get(&SourceEntityAttributeKey::Role)
-                .cloned()
-                .ok_or_else(|| format!("Attribute not found: {}", attr_name)),
-            "Src.Dept" => source.attributes.get(&SourceEntityAttributeKey::Dept)
-                .cloned()
-                .ok_or_else(|| format!("Attribute not found: {}", attr_name)),
-            "Src.TrustScore" => source.attributes.get(&SourceEntityAttributeKey::TrustScore)
-                .cloned()
-                .ok_or_else(|| format!("Attribute not found: {}", attr_name)),
-            "Src.Groups" => source.attributes.get(&SourceEntityAttributeKey::Groups)
-                .cloned()
-                .ok_or_else(|| format!("Attribute not found: {}", attr_name)),
-            "Src.SessionCount" => source.attributes.get(&SourceEntityAttributeKey::SessionCount)
-                .cloned()
-                .ok_or_else(|| format!("Attribute not found: {}", attr_name)),
-            _ => Err(format!("Unknown source attribute: {}", attr_name)),
+        let (top, path) = Self::split_attribute_path(attr_name);
+        // 既知の名前でなくても、パスが続くなら Nested 属性として探す
+        let key = match SourceEntity::parse_attribute_key(top) {
+            Ok(key) => key,
+            Err(_) if !path.is_empty() => SourceEntityAttributeKey::Nested(top.to_string()),
+            Err(_) => return Err(format!("Unknown source attribute: {}", attr_name)),
+        };
+        let value = source.attributes.get(&key)
+            .or_else(|| opts.defaults.and_then(|d| d.get(top)));
+        match value {
+            Some(value) => Self::resolve_attribute_path(value, &path, attr_name, opts.missing_as_null),
+            None if opts.missing_as_null => Ok(AttributeValue::Null),
+            None => Err(format!("Attribute not found: {}", attr_name)),
         }
     }
     
     fn get_destination_attribute(
         destination: &DestinationEntity,
         attr_name: &str,
+        opts: &EvalOptions,
     ) -> Result<AttributeValue, String> {
-        match attr_name {
-            "Dst.Type" => destination.attributes.get(&DestinationEntityAttributeKey::Type)
-                .cloned()
-                .ok_or_else(|| format!("Attribute not found: {}", attr_name)),
-            "Dst.OwnerDept" => destination.attributes.get(&DestinationEntityAttributeKey::OwnerDept)
-                .cloned()
-                .ok_or_else(|| format!("Attribute not found: {}", attr_name)),
-            "Dst.Sensitivity" => destination.attributes.get(&DestinationEntityAttributeKey::Sensitivity)
-                .cloned()
-                .ok_or_else(|| format!("Attribute not found: {}", attr_name)),
-            "Dst.AllowedVLANs" => destination.attributes.get(&DestinationEntityAttributeKey::AllowedVLANs)
-                .cloned()
-                .ok_or_else(|| format!("Attribute not found: {}", attr_name)),
-            _ => Err(format!("Unknown destination attribute: {}", attr_name)),
+        let (top, path) = Self::split_attribute_path(attr_name);
+        let key = match DestinationEntity::parse_attribute_key(top) {
+            Ok(key) => key,
+            Err(_) if !path.is_empty() => DestinationEntityAttributeKey::Nested(top.to_string()),
+            Err(_) => return Err(format!("Unknown destination attribute: {}", attr_name)),
+        };
+        let value = destination.attributes.get(&key)
+            .or_else(|| opts.defaults.and_then(|d| d.get(top)));
+        match value {
+            Some(value) => Self::resolve_attribute_path(value, &path, attr_name, opts.missing_as_null),
+            None if opts.missing_as_null => Ok(AttributeValue::Null),
+            None => Err(format!("Attribute not found: {}", attr_name)),
         }
     }
 
+    pub fn attributes_referenced(&self) -> BTreeSet<String> {
+        let mut collector = AttributeRefCollector::default();
+        collector.visit_expression(self);
+        collector.names
+    }
+
     pub fn references_dst(&self) -> bool {
+        let mut finder = PrefixFinder { prefix: "Dst.", found: false };
+        finder.visit_expression(self);
+        finder.found
+    }
+
+    /// name と一致する AttributeRef / EnvRef を含むか
+    pub fn references_attribute(&self, name: &str) -> bool {
+        let mut finder = ReferenceFinder { name, found: false };
+        finder.visit_expression(self);
+        finder.found
+    }
+
+    /// 演算の入れ子の深さ (リテラルや参照は 0)
+    pub fn depth(&self) -> usize {
+        match self {
+            Expression::LiteralString(_)
+            | Expression::LiteralNumber(_)
+            | Expression::LiteralSet(_)
+            | Expression::AttributeRef(_)
+            | Expression::EnvRef(_) => 0,
+            Expression::Add { operands } | Expression::Multiply { operands } => {
+                1 + operands.iter().map(|e| e.depth()).max().unwrap_or(0)
+            }
+            Expression::Count { operand } => 1 + operand.depth(),
+            Expression::If { cond, then, els } => 1 + cond.depth().max(then.depth()).max(els.depth()),
+        }
+    }
+
+    /// 式のノードの総数
+    pub fn size(&self) -> usize {
+        let mut counter = NodeCounter::default();
+        counter.visit_expression(self);
+        counter.nodes
+    }
+
+    /// "Src.Department.Country" のような入れ子のパスは先頭の属性名だけを読み替える
+    pub fn resolve_aliases(&mut self, aliases: &HashMap<String, String>) {
+        match self {
+            Expression::AttributeRef(name) => {
+                let (top, path) = Self::split_attribute_path(name);
+                if let Some(canonical) = aliases.get(top) {
+                    *name = std::iter::once(canonical.as_str())
+                        .chain(path)
+                        .collect::<Vec<_>>()
+                        .join(".");
+                }
+            }
+            Expression::Add { operands } | Expression::Multiply { operands } => {
+                for e in operands {
+                    e.resolve_aliases(aliases);
+                }
+            }
+            Expression::Count { operand } => operand.resolve_aliases(aliases),
+            Expression::If { cond, then, els } => {
+                cond.resolve_aliases(aliases);
+                then.resolve_aliases(aliases);
+                els.resolve_aliases(aliases);
+            }
+            Expression::LiteralString(_)
+            | Expression::LiteralNumber(_)
+            | Expression::LiteralSet(_)
+            | Expression::EnvRef(_) => {}
+        }
+    }
+
+    /// Condition::map_expressions と同じく、自身と子孫のすべての Expression に f を適用する
+    pub fn map_expressions<F: FnMut(&mut Expression)>(&mut self, mut f: F) {
+        self.map_expressions_dyn(&mut f);
+    }
+
+    fn map_expressions_dyn(&mut self, f: &mut dyn FnMut(&mut Expression)) {
+        match self {
+            Expression::Add { operands } | Expression::Multiply { operands } => {
+                for e in operands {
+                    e.map_expressions_dyn(f);
+                }
+            }
+            Expression::Count { operand } => operand.map_expressions_dyn(f),
+            Expression::If { cond, then, els } => {
+                cond.map_expressions_dyn(f);
+                then.map_expressions_dyn(f);
+                els.map_expressions_dyn(f);
+            }
+            Expression::LiteralString(_)
+            | Expression::LiteralNumber(_)
+            | Expression::LiteralSet(_)
+            | Expression::AttributeRef(_)
+            | Expression::EnvRef(_) => {}
+        }
+        f(self);
+    }
+
+    pub fn references_dst_or_env(&self) -> bool {
         match self {
             Expression::AttributeRef(name) => name.starts_with("Dst."),
-            // 以下の実装は何？
+            Expression::EnvRef(_) => true,
             Expression::Add { operands } | Expression::Multiply { operands } => {
-                operands.iter().any(|e| e.references_dst())
+                operands.iter().any(|e| e.references_dst_or_env())
+            }
+            Expression::Count { operand } => operand.references_dst_or_env(),
+            Expression::If { cond, then, els } => {
+                condition_has_expression(cond, &|e| e.references_dst_or_env())
+                    || then.references_dst_or_env()
+                    || els.references_dst_or_env()
             }
             _ => false,
         }
@@ -588,10 +1410,722 @@ impl Expression {
     pub fn references_src_or_env(&self) -> bool {
         match self {
             Expression::AttributeRef(name) => name.starts_with("Src.") || name.starts_with("Env."),
+            Expression::EnvRef(_) => true,
             Expression::Add { operands } | Expression::Multiply { operands } => {
                 operands.iter().any(|e| e.references_src_or_env())
             }
+            Expression::Count { operand } => operand.references_src_or_env(),
+            Expression::If { cond, then, els } => {
+                condition_has_expression(cond, &|e| e.references_src_or_env())
+                    || then.references_src_or_env()
+                    || els.references_src_or_env()
+            }
             _ => false,
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn sample_policy() -> Policy {
+        Policy::from_json_value(&json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": "deny",
+            "rules": [
+                { "id": "r1", "effect": "allow", "condition": { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" } },
+                { "id": "r2", "effect": "deny", "condition": { "operator": "EQ", "lhs": "Src.Role", "rhs": "Guest" } },
+                { "id": "r3", "effect": "allow", "condition": { "operator": "TRUE" } }
+            ]
+        })).unwrap()
+    }
+
+    fn rule(id: &str, effect: &str) -> Rule {
+        Rule::from_json_value(&json!({
+            "id": id,
+            "effect": effect,
+            "condition": { "operator": "FALSE" }
+        })).unwrap()
+    }
+
+    fn ids(policy: &Policy) -> Vec<&str> {
+        policy.rules.iter().map(|r| r.id.as_str()).collect()
+    }
+
+    #[test]
+    fn rule_by_id_finds_existing_rules_only() {
+        let mut policy = sample_policy();
+        assert_eq!(policy.rule_by_id("r2").map(|r| r.effect.clone()), Some(Effect::Deny));
+        assert!(policy.rule_by_id("missing").is_none());
+
+        policy.remove_rule("r1");
+        assert_eq!(policy.rule_by_id("r3").map(|r| r.id.as_str()), Some("r3"));
+
+        // rules を直接書き換えて索引が古くなっても、別のルールや範囲外を指さない
+        policy.rules.swap(0, 1);
+        policy.rules.push(rule("r4", "deny"));
+        assert_eq!(policy.rule_by_id("r2").map(|r| r.id.as_str()), Some("r2"));
+        assert_eq!(policy.rule_by_id("r4").map(|r| r.id.as_str()), Some("r4"));
+        policy.rules.truncate(1);
+        assert!(policy.rule_by_id("r2").is_none());
+    }
+
+    #[test]
+    fn update_rule_appends_new_ids() {
+        let mut policy = sample_policy();
+        policy.update_rule(rule("r4", "deny"));
+        assert_eq!(ids(&policy), vec!["r1", "r2", "r3", "r4"]);
+    }
+
+    #[test]
+    fn update_rule_replaces_in_place() {
+        let mut policy = sample_policy();
+        policy.update_rule(rule("r2", "allow"));
+        assert_eq!(ids(&policy), vec!["r1", "r2", "r3"]);
+        assert_eq!(policy.rules[1].effect, Effect::Allow);
+    }
+
+    #[test]
+    fn remove_rule_preserves_order_and_index() {
+        let mut policy = sample_policy();
+        let removed = policy.remove_rule("r1").unwrap();
+        assert_eq!(removed.id, "r1");
+        assert_eq!(ids(&policy), vec!["r2", "r3"]);
+        assert!(policy.remove_rule("r1").is_none());
+
+        policy.update_rule(rule("r3", "deny"));
+        assert_eq!(ids(&policy), vec!["r2", "r3"]);
+        assert_eq!(policy.rules[1].effect, Effect::Deny);
+    }
+
+    #[test]
+    fn update_and_remove_survive_direct_edits_to_rules() {
+        let mut policy = sample_policy();
+        policy.rules.reverse();
+        // 索引のままなら r1 の位置 (0) にある r3 を上書きしてしまう
+        policy.update_rule(rule("r1", "deny"));
+        assert_eq!(ids(&policy), vec!["r3", "r2", "r1"]);
+        assert_eq!(policy.rules[2].effect, Effect::Deny);
+        assert_eq!(policy.rules[0].effect, Effect::Allow);
+
+        policy.rules.remove(0);
+        assert_eq!(policy.remove_rule("r1").map(|r| r.id), Some("r1".to_string()));
+        assert!(policy.remove_rule("r3").is_none());
+        assert_eq!(ids(&policy), vec!["r2"]);
+        assert_eq!(policy.rule_by_id("r2").map(|r| r.id.as_str()), Some("r2"));
+    }
+
+    #[test]
+    fn dotted_attribute_paths_resolve_nested_objects() {
+        let source = SourceEntity::from_json_value(&json!({
+            "ip": "10.0.0.1",
+            "attributes": {
+                "Src.Role": "Student",
+                "Src.Location": { "Country": "JP", "Campus": { "Building": "B3" } }
+            }
+        })).unwrap();
+        let destination = DestinationEntity::from_json_value(&json!({
+            "ip": "172.20.0.1",
+            "attributes": { "Dst.Location": { "Country": "JP" } }
+        })).unwrap();
+        let env = HashMap::new();
+
+        let country = Expression::AttributeRef("Src.Location.Country".to_string());
+        assert_eq!(
+            country.evaluate(&source, &destination, &env),
+            Ok(AttributeValue::String("JP".to_string()))
+        );
+        let building = Expression::AttributeRef("Src.Location.Campus.Building".to_string());
+        assert_eq!(
+            building.evaluate(&source, &destination, &env),
+            Ok(AttributeValue::String("B3".to_string()))
+        );
+
+        let same_country = Condition::from_json_value(&json!({
+            "operator": "EQ", "lhs": "Src.Location.Country", "rhs": "Dst.Location.Country"
+        })).unwrap();
+        assert_eq!(same_country.evaluate(&source, &destination, &env), Ok(true));
+    }
+
+    #[test]
+    fn missing_nested_attribute_is_an_error() {
+        let source = SourceEntity::from_json_value(&json!({
+            "ip": "10.0.0.1",
+            "attributes": { "Src.Role": "Student", "Src.Location": { "Country": "JP" } }
+        })).unwrap();
+        let destination = DestinationEntity::from_json_value(&json!({ "ip": "d", "attributes": {} })).unwrap();
+        let env = HashMap::new();
+
+        let city = Expression::AttributeRef("Src.Location.City".to_string());
+        assert_eq!(
+            city.evaluate(&source, &destination, &env),
+            Err("Attribute not found: Src.Location.City".to_string())
+        );
+        let role_field = Expression::AttributeRef("Src.Role.Name".to_string());
+        assert!(role_field.evaluate(&source, &destination, &env).is_err());
+    }
+
+    #[test]
+    fn any_object_valued_attribute_is_nested() {
+        let source = SourceEntity::from_json_value(&json!({
+            "ip": "10.0.0.1",
+            "attributes": { "Src.Device": { "OS": "Linux", "Owner": { "Dept": "CS" } } }
+        })).unwrap();
+        let destination = DestinationEntity::from_json_value(&json!({
+            "ip": "172.20.0.1",
+            "attributes": { "Dst.Rack": { "Row": 4 } }
+        })).unwrap();
+        let env = HashMap::new();
+
+        let cond = Condition::from_json_value(&json!({
+            "operator": "AND",
+            "operands": [
+                { "operator": "EQ", "lhs": "Src.Device.Owner.Dept", "rhs": "CS" },
+                { "operator": "LT", "lhs": "Dst.Rack.Row", "rhs": 5 }
+            ]
+        })).unwrap();
+        assert_eq!(cond.evaluate(&source, &destination, &env), Ok(true));
+        assert_eq!(
+            Expression::AttributeRef("Src.Device.Arch".to_string()).evaluate(&source, &destination, &env),
+            Err("Attribute not found: Src.Device.Arch".to_string())
+        );
+
+        // オブジェクト以外の値を持つ未知の属性はこれまで通りエラー
+        assert!(SourceEntity::from_json_value(&json!({
+            "ip": "10.0.0.1", "attributes": { "Src.Device": "Linux" }
+        })).is_err());
+    }
+
+    #[test]
+    fn count_gates_on_set_cardinality() {
+        let cond = Condition::from_json_value(&json!({
+            "operator": "GTE",
+            "lhs": { "operator": "COUNT", "operand": "Src.Groups" },
+            "rhs": 3
+        })).unwrap();
+        let destination = DestinationEntity::from_json_value(&json!({ "ip": "d", "attributes": {} })).unwrap();
+        let env = HashMap::new();
+        let source = |groups: serde_json::Value| {
+            SourceEntity::from_json_value(&json!({ "ip": "s", "attributes": { "Src.Groups": groups, "Src.Role": "Staff" } })).unwrap()
+        };
+
+        assert_eq!(cond.evaluate(&source(json!(["a", "b", "c"])), &destination, &env), Ok(true));
+        assert_eq!(cond.evaluate(&source(json!(["a", "b"])), &destination, &env), Ok(false));
+
+        let count_role = Expression::Count { operand: Box::new(Expression::AttributeRef("Src.Role".to_string())) };
+        let err = count_role.evaluate(&source(json!([])), &destination, &env).unwrap_err();
+        assert!(err.starts_with("COUNT operand must be a set"));
+    }
+
+    #[test]
+    fn attributes_referenced_collects_all_attribute_refs() {
+        let cond = Condition::from_json_value(&json!({
+            "operator": "AND",
+            "operands": [
+                { "operator": "EQ", "lhs": "Src.Dept", "rhs": "Dst.OwnerDept" },
+                { "operator": "GT", "lhs": "Env.DestPort", "rhs": 1023 },
+                { "operator": "GTE", "lhs": { "operator": "COUNT", "operand": "Src.Groups" }, "rhs": 2 }
+            ]
+        })).unwrap();
+
+        let names: Vec<String> = cond.attributes_referenced().into_iter().collect();
+        assert_eq!(names, vec!["Dst.OwnerDept", "Src.Dept", "Src.Groups"]);
+        assert!(cond.references_dst());
+
+        let src_only = Condition::from_json_value(&json!({ "operator": "EQ", "lhs": "Src.Role", "rhs": "Dst" })).unwrap();
+        assert!(!src_only.references_dst());
+        // IF の条件の中の参照も見つける
+        let nested = Condition::from_json_value(&json!({
+            "operator": "GTE",
+            "lhs": { "operator": "IF", "cond": { "operator": "EQ", "lhs": "Dst.Type", "rhs": "DB" }, "then": 1, "else": 0 },
+            "rhs": 1
+        })).unwrap();
+        assert!(nested.references_dst());
+    }
+
+    #[test]
+    fn in_accepts_inline_set_literals() {
+        let source = SourceEntity::from_json_value(&json!({ "ip": "s", "attributes": { "Src.Role": "audit" } })).unwrap();
+        let destination = DestinationEntity::from_json_value(&json!({
+            "ip": "d", "attributes": { "Dst.AllowedVLANs": ["admin", "audit"] }
+        })).unwrap();
+        let env = HashMap::new();
+
+        let literal = Condition::from_json_value(&json!({
+            "operator": "IN", "target": "Src.Role", "check_against": ["admin", "audit"]
+        })).unwrap();
+        assert_eq!(literal.evaluate(&source, &destination, &env), Ok(true));
+
+        let not_listed = Condition::from_json_value(&json!({
+            "operator": "IN", "target": "Src.Role", "check_against": ["admin"]
+        })).unwrap();
+        assert_eq!(not_listed.evaluate(&source, &destination, &env), Ok(false));
+
+        let from_attribute = Condition::from_json_value(&json!({
+            "operator": "IN", "target": "Src.Role", "check_against": "Dst.AllowedVLANs"
+        })).unwrap();
+        assert_eq!(from_attribute.evaluate(&source, &destination, &env), Ok(true));
+
+        assert!(Expression::from_json_value(&json!(["a", 1])).is_err());
+    }
+
+    #[test]
+    fn deeply_nested_conditions_are_rejected() {
+        let mut value = json!({ "operator": "TRUE" });
+        for _ in 0..200 {
+            value = json!({ "operator": "AND", "operands": [value] });
+        }
+        let err = Condition::from_json_value(&value).unwrap_err();
+        assert!(err.contains("nested too deeply"));
+
+        let mut shallow = json!({ "operator": "TRUE" });
+        for _ in 0..9 {
+            shallow = json!({ "operator": "OR", "operands": [shallow] });
+        }
+        assert!(Condition::from_json_value_with_max_depth(&shallow, 10).is_ok());
+        assert!(Condition::from_json_value_with_max_depth(&shallow, 9).is_err());
+
+        let mut expr = json!(1);
+        for _ in 0..100 {
+            expr = json!({ "operator": "ADD", "operands": [expr] });
+        }
+        assert!(Expression::from_json_value(&expr).is_err());
+    }
+
+    #[test]
+    fn eval_stats_reflect_short_circuit() {
+        let source = SourceEntity::from_json_value(&json!({ "ip": "s", "attributes": { "Src.Role": "Guest" } })).unwrap();
+        let destination = DestinationEntity::from_json_value(&json!({ "ip": "d", "attributes": {} })).unwrap();
+        let env = HashMap::new();
+        let is_guest = json!({ "operator": "EQ", "lhs": "Src.Role", "rhs": "Guest" });
+        let is_admin = json!({ "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" });
+
+        let short = Condition::from_json_value(&json!({
+            "operator": "AND", "operands": [is_admin, is_guest]
+        })).unwrap();
+        let full = Condition::from_json_value(&json!({
+            "operator": "AND", "operands": [is_guest, is_admin]
+        })).unwrap();
+
+        let mut short_stats = EvalStats::default();
+        assert_eq!(short.evaluate_with_stats(&source, &destination, &env, &mut short_stats), Ok(false));
+        let mut full_stats = EvalStats::default();
+        assert_eq!(full.evaluate_with_stats(&source, &destination, &env, &mut full_stats), Ok(false));
+
+        assert_eq!(short_stats, EvalStats { condition_visits: 2, expression_visits: 2, attribute_lookups: 1 });
+        assert_eq!(full_stats, EvalStats { condition_visits: 3, expression_visits: 4, attribute_lookups: 2 });
+    }
+
+    #[test]
+    fn references_attribute_matches_exact_names() {
+        let cond = Condition::from_json_value(&json!({
+            "operator": "OR",
+            "operands": [
+                { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" },
+                { "operator": "AND", "operands": [
+                    { "operator": "GTE", "lhs": { "operator": "ADD", "operands": ["Src.TrustScore", "Env.Bonus"] }, "rhs": 80 },
+                    { "operator": "IN", "target": "Src.Dept", "check_against": "Dst.AllowedVLANs" }
+                ]}
+            ]
+        })).unwrap();
+
+        for name in ["Src.Role", "Src.TrustScore", "Dst.AllowedVLANs", "Env.Bonus"] {
+            assert!(cond.references_attribute(name), "{}", name);
+        }
+        assert!(!cond.references_attribute("Src.Groups"));
+        assert!(!cond.references_attribute("Src"));
+        assert!(!cond.references_attribute("Admin"));
+    }
+
+    #[test]
+    fn aliases_resolve_to_canonical_attributes() {
+        let map = crate::ip_based::encoder::AttrIdMap::from_reader(std::io::Cursor::new(
+            json!({
+                "Src.Dept": { "description": { "type": "single" }, "value": { "0": "CS", "1": "Bio" } },
+                "aliases": { "Src.Department": "Src.Dept" }
+            })
+            .to_string(),
+        ))
+        .unwrap();
+        assert_eq!(map.value_to_id("Src.Department", "Bio"), Ok(1));
+
+        let mut policy = Policy::from_json_value(&json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": "deny",
+            "rules": [
+                { "id": "cs", "effect": "allow", "condition": { "operator": "EQ", "lhs": "Src.Department", "rhs": "CS" } }
+            ]
+        })).unwrap();
+        policy.resolve_aliases(&map.aliases);
+        assert!(policy.rules[0].condition.references_attribute("Src.Dept"));
+
+        let source = SourceEntity::from_json_value_with_aliases(
+            &json!({ "ip": "s", "attributes": { "Src.Department": "CS" } }),
+            &map.aliases,
+        ).unwrap();
+        let destination = DestinationEntity::from_json_value(&json!({ "ip": "d", "attributes": {} })).unwrap();
+        assert_eq!(policy.rules[0].matches(&source, &destination, &HashMap::new()), Ok(true));
+    }
+
+    #[test]
+    fn duplicate_rule_ids_are_reported() {
+        let value = json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": "deny",
+            "rules": [
+                { "id": "r1", "effect": "allow", "condition": { "operator": "TRUE" } },
+                { "id": "r2", "effect": "deny", "condition": { "operator": "TRUE" } },
+                { "id": "r1", "effect": "deny", "condition": { "operator": "FALSE" } }
+            ]
+        });
+
+        let policy = Policy::from_json_value(&value).unwrap();
+        assert_eq!(policy.duplicate_rule_ids(), vec!["r1".to_string()]);
+        assert_eq!(policy.rule_by_id("r1").unwrap().effect, Effect::Allow);
+
+        let err = Policy::from_json_value_strict(&value).unwrap_err();
+        assert!(err.contains("r1") && !err.contains("r2"), "{}", err);
+        assert!(Policy::from_json_value_strict(&json!({
+            "policy_name": "p", "description": "", "default_effect": "deny", "rules": []
+        })).is_ok());
+    }
+
+    #[test]
+    fn dest_only_or_keeps_source_branches_applicable() {
+        let destination = DestinationEntity::from_json_value(&json!({
+            "ip": "d", "attributes": { "Dst.Type": "Database" }
+        })).unwrap();
+        let web = json!({ "operator": "EQ", "lhs": "Dst.Type", "rhs": "WebServer" });
+        let file = json!({ "operator": "EQ", "lhs": "Dst.Type", "rhs": "FileServer" });
+        let admin = json!({ "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" });
+
+        let mixed = Condition::from_json_value(&json!({ "operator": "OR", "operands": [web, admin] })).unwrap();
+        assert_eq!(mixed.evaluate_dest_only(&destination), Ok(true));
+
+        let dst_only = Condition::from_json_value(&json!({ "operator": "OR", "operands": [web, file] })).unwrap();
+        assert_eq!(dst_only.evaluate_dest_only(&destination), Ok(false));
+
+        let src_only = Condition::from_json_value(&json!({ "operator": "OR", "operands": [admin] })).unwrap();
+        assert_eq!(src_only.evaluate_dest_only(&destination), Ok(true));
+    }
+
+    #[test]
+    fn depth_and_size_of_conditions() {
+        let flat = Condition::from_json_value(&json!({ "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" })).unwrap();
+        assert_eq!(flat.depth(), 1);
+        assert_eq!(flat.size(), 3);
+
+        let nested = Condition::from_json_value(&json!({
+            "operator": "AND",
+            "operands": [
+                { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" },
+                {
+                    "operator": "OR",
+                    "operands": [
+                        { "operator": "GTE", "lhs": { "operator": "ADD", "operands": ["Src.TrustScore", 5] }, "rhs": 80 },
+                        { "operator": "TRUE" }
+                    ]
+                }
+            ]
+        })).unwrap();
+        // AND -> OR -> GTE -> ADD
+        assert_eq!(nested.depth(), 4);
+        // 条件 5 (AND, EQ, OR, GTE, TRUE) + 式 6
+        assert_eq!(nested.size(), 11);
+    }
+
+    #[test]
+    fn conditions_compare_structurally() {
+        let spec = json!({
+            "operator": "AND",
+            "operands": [
+                { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" },
+                { "operator": "GTE", "lhs": "Src.TrustScore", "rhs": 80 }
+            ]
+        });
+        let a = Condition::from_json_value(&spec).unwrap();
+        let b = Condition::from_json_value(&spec).unwrap();
+        assert_eq!(a, b);
+
+        let other = Condition::from_json_value(&json!({
+            "operator": "AND",
+            "operands": [
+                { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" },
+                { "operator": "GTE", "lhs": "Src.TrustScore", "rhs": 50 }
+            ]
+        })).unwrap();
+        assert_ne!(a, other);
+    }
+
+    #[test]
+    fn not_in_negates_membership() {
+        let cond = Condition::from_json_value(&json!({
+            "operator": "NOT_IN", "target": "Src.Role", "check_against": ["Guest", "Vendor"]
+        })).unwrap();
+        let destination = DestinationEntity::from_json_value(&json!({
+            "ip": "d", "attributes": { "Dst.Type": "Database", "Dst.AllowedVLANs": ["Faculty"] }
+        })).unwrap();
+        let source = |role: &str| {
+            SourceEntity::from_json_value(&json!({ "ip": "s", "attributes": { "Src.Role": role } })).unwrap()
+        };
+        let env = HashMap::new();
+
+        // ブラックリストに含まれれば不成立、含まれなければ成立
+        assert_eq!(cond.evaluate(&source("Guest"), &destination, &env), Ok(false));
+        assert_eq!(cond.evaluate(&source("Admin"), &destination, &env), Ok(true));
+        assert_eq!(cond.evaluate_dest_only(&destination), Ok(true));
+
+        let dst_only = |vlan: &str| {
+            Condition::from_json_value(&json!({
+                "operator": "NOT_IN", "target": vlan, "check_against": "Dst.AllowedVLANs"
+            })).unwrap()
+        };
+        assert_eq!(dst_only("Faculty").evaluate_dest_only(&destination), Ok(false));
+        assert_eq!(dst_only("Guest").evaluate_dest_only(&destination), Ok(true));
+    }
+
+    #[test]
+    fn missing_attribute_falls_back_to_configured_default() {
+        let map = crate::ip_based::encoder::AttrIdMap::from_reader(r#"{
+            "Src.Role": {
+                "description": { "type": "single", "default": "Guest" },
+                "value": { "0": "Admin", "1": "Guest" }
+            }
+        }"#.as_bytes()).unwrap();
+        let cond = Condition::from_json_value(&json!({
+            "operator": "EQ", "lhs": "Src.Role", "rhs": "Guest"
+        })).unwrap();
+        let src = SourceEntity::from_json_value(&json!({
+            "ip": "10.0.0.1", "attributes": { "Src.Dept": "Sales" }
+        })).unwrap();
+        let dst = DestinationEntity::from_json_value(&json!({
+            "ip": "10.0.0.2", "attributes": {}
+        })).unwrap();
+        let env = HashMap::new();
+
+        assert!(cond.evaluate(&src, &dst, &env).is_err());
+        assert_eq!(cond.evaluate_with_defaults(&src, &dst, &env, &map.defaults), Ok(true));
+    }
+
+    #[test]
+    fn load_policies_reads_each_file_in_order() {
+        let dir = std::env::temp_dir().join(format!("pol-tree-load-policies-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, policy_name: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, json!({
+                "policy_name": policy_name,
+                "description": "",
+                "default_effect": "deny",
+                "rules": []
+            }).to_string()).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let web = write("web.json", "web");
+        let db = write("db.json", "db");
+        let broken = dir.join("broken.json");
+        std::fs::write(&broken, "{").unwrap();
+        let broken = broken.to_str().unwrap().to_string();
+
+        let policies = load_policies(&[&web, &db]).unwrap();
+        let names: Vec<&str> = policies.iter().map(|p| p.policy_name.as_str()).collect();
+        assert_eq!(names, vec!["web", "db"]);
+
+        let err = load_policies(&[&web, &broken]).err().unwrap();
+        assert!(err.starts_with(&broken), "{}", err);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_as_null_compares_absent_attributes_as_false() {
+        let cond = |v: serde_json::Value| Condition::from_json_value(&v).unwrap();
+        let is_admin = cond(json!({ "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" }));
+        let trusted = cond(json!({ "operator": "GTE", "lhs": "Src.TrustScore", "rhs": 50 }));
+        let not_banned = cond(json!({ "operator": "NOT_IN", "target": "Src.Role", "check_against": ["Banned"] }));
+        let either = cond(json!({ "operator": "OR", "operands": [
+            { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" },
+            { "operator": "EQ", "lhs": "Src.Dept", "rhs": "CS" }
+        ]}));
+        let present = SourceEntity::from_json_value(&json!({
+            "ip": "10.0.0.1", "attributes": { "Src.Role": "Admin", "Src.Dept": "CS", "Src.TrustScore": 80 }
+        })).unwrap();
+        let absent = SourceEntity::from_json_value(&json!({
+            "ip": "10.0.0.2", "attributes": { "Src.Dept": "CS" }
+        })).unwrap();
+        let dst = DestinationEntity::from_json_value(&json!({ "ip": "10.0.0.3", "attributes": {} })).unwrap();
+        let env = HashMap::new();
+        let opts = EvalOptions { missing_as_null: true, ..EvalOptions::default() };
+
+        for c in [&is_admin, &trusted, &not_banned, &either] {
+            assert_eq!(c.evaluate_with_options(&present, &dst, &env, &opts), c.evaluate(&present, &dst, &env));
+        }
+        assert_eq!(is_admin.evaluate_with_options(&absent, &dst, &env, &opts), Ok(false));
+        assert_eq!(trusted.evaluate_with_options(&absent, &dst, &env, &opts), Ok(false));
+        assert_eq!(not_banned.evaluate_with_options(&absent, &dst, &env, &opts), Ok(false));
+        assert_eq!(either.evaluate_with_options(&absent, &dst, &env, &opts), Ok(true));
+
+        // 両辺とも欠損していても一致とはみなさない
+        let same_dept = cond(json!({ "operator": "EQ", "lhs": "Src.Dept", "rhs": "Dst.OwnerDept" }));
+        let no_attrs = SourceEntity::from_json_value(&json!({ "ip": "10.0.0.4", "attributes": {} })).unwrap();
+        assert_eq!(same_dept.evaluate_with_options(&no_attrs, &dst, &env, &opts), Ok(false));
+        // 既定の設定では欠損は従来通りエラー
+        assert!(is_admin.evaluate(&absent, &dst, &env).is_err());
+        assert!(either.evaluate(&absent, &dst, &env).is_err());
+    }
+
+    #[test]
+    fn summary_of_sample_policy() {
+        let policy = Policy::load("data/ip_based_abac_rule.json").unwrap();
+        let summary = policy.summary();
+        assert_eq!(summary.rule_count, 4);
+        assert_eq!((summary.allow_rules, summary.deny_rules), (3, 1));
+        assert_eq!(summary.default_effect, Effect::Deny);
+        let expected: BTreeSet<String> = [
+            "Dst.AllowedVLANs", "Dst.OwnerDept", "Dst.Sensitivity", "Dst.Type",
+            "Src.Dept", "Src.Groups", "Src.Role", "Src.TrustScore",
+        ].iter().map(|s| s.to_string()).collect();
+        assert_eq!(summary.attributes_referenced, expected);
+        assert!(summary.max_condition_depth >= 2);
+    }
+
+    #[test]
+    fn comparing_a_set_suggests_count() {
+        let cond = Condition::from_json_value(&json!({
+            "operator": "GT", "lhs": "Src.Groups", "rhs": 2
+        })).unwrap();
+        let src = SourceEntity::from_json_value(&json!({
+            "ip": "10.0.0.1", "attributes": { "Src.Groups": ["a", "b", "c"] }
+        })).unwrap();
+        let dst = DestinationEntity::from_json_value(&json!({ "ip": "10.0.0.2", "attributes": {} })).unwrap();
+
+        let err = cond.evaluate(&src, &dst, &HashMap::new()).unwrap_err();
+        assert!(err.contains("COUNT"), "{}", err);
+    }
+
+    #[test]
+    fn malformed_inputs_are_errors_not_panics() {
+        use crate::ip_based::encoder::AttrIdMap;
+        use crate::util::rng::SplitMix64;
+
+        let rule = |condition: &str| format!(
+            r#"{{"policy_name": "p", "description": "", "default_effect": "deny",
+                "rules": [{{"id": "r", "effect": "allow", "condition": {}}}]}}"#,
+            condition
+        );
+        let deep = format!("{}{}{}",
+            r#"{"operator": "AND", "operands": ["#.repeat(70),
+            r#"{"operator": "EQ", "lhs": "Src.Role", "rhs": "x"}"#,
+            "]}".repeat(70),
+        );
+        let mut policies: Vec<String> = [
+            "", "{", "[]", "null", "42", r#"{"policy_name": 1}"#,
+            r#"{"policy_name": "p", "description": "", "default_effect": "maybe", "rules": []}"#,
+            r#"{"policy_name": "p", "description": "", "default_effect": "deny", "rules": {}}"#,
+            r#"{"policy_name": "p", "description": "", "default_effect": "deny", "rules": [null]}"#,
+        ].iter().map(|s| s.to_string()).collect();
+        policies.extend([
+            r#"{"operator": "XOR"}"#,
+            r#"{"operator": "EQ", "lhs": "Src.Role"}"#,
+            r#"{"operator": "EQ", "lhs": "Src.TrustScore", "rhs": 18446744073709551615}"#,
+            r#"{"operator": "EQ", "lhs": "Src.TrustScore", "rhs": 1.5}"#,
+            r#"{"operator": "EQ", "lhs": "Src.TrustScore", "rhs": 1e400}"#,
+            r#"{"operator": "GT", "lhs": {"operator": "COUNT"}, "rhs": 1}"#,
+            r#"{"operator": "EQ", "lhs": {"operator": "IF", "cond": {"operator": "TRUE"}, "then": 1}, "rhs": 1}"#,
+            r#"{"operator": "IN", "value": "Src.Role", "set": [1, 2]}"#,
+            r#"{"operator": "AND", "operands": "Src.Role"}"#,
+            &deep,
+        ].iter().map(|c| rule(c)));
+        for text in &policies {
+            assert!(Policy::from_reader(text.as_bytes()).is_err(), "{}", text);
+        }
+
+        for text in [
+            r#"{"Src.Role": {}}"#,
+            r#"{"Src.Role": {"description": {"type": "tree"}, "value": {}}}"#,
+            r#"{"Src.Role": {"description": {"type": "single"}, "value": {"x": "Admin"}}}"#,
+            r#"{"Src.Role": {"description": {"type": "single", "unknown_id": -1}, "value": {}}}"#,
+            r#"{"Src.Role": {"description": {"type": "single", "default": null}, "value": {}}}"#,
+            r#"{"Src.Score": {"description": {"type": "numeric"}, "value": {"min": 0, "max": 18446744073709551615}}}"#,
+            r#"{"Src.Score": {"description": {"type": "numeric"}, "value": {"min": "0", "max": 1}}}"#,
+            r#"{"aliases": {"Src.R": "Src.Missing"}}"#,
+        ] {
+            assert!(AttrIdMap::from_reader(text.as_bytes()).is_err(), "{}", text);
+        }
+
+        for value in [
+            json!({}),
+            json!({ "ip": 1 }),
+            json!({ "ip": "1", "attributes": 3 }),
+            json!({ "ip": "1", "attributes": { "Src.Role": null } }),
+            json!({ "ip": "1", "attributes": { "Src.Groups": [1, 2] } }),
+            json!({ "ip": "1", "attributes": { "Src.TrustScore": u64::MAX } }),
+            json!({ "ip": "1", "attributes": { "Src.Nope": "x" } }),
+        ] {
+            assert!(SourceEntity::from_json_value(&value).is_err(), "{}", value);
+            assert!(DestinationEntity::from_json_value(&value).is_err(), "{}", value);
+        }
+
+        // 正しいファイルを切り詰めたり 1 文字書き換えたりしても panic しない
+        let mut rng = SplitMix64::new(7);
+        for path in ["data/ip_based_abac_rule.json", "data/ip_based_abac_attr_id.json"] {
+            let text = std::fs::read_to_string(path).unwrap();
+            for _ in 0..300 {
+                let mut bytes = text.as_bytes().to_vec();
+                let i = rng.below(bytes.len() as u64) as usize;
+                if rng.below(2) == 0 {
+                    bytes.truncate(i);
+                } else {
+                    bytes[i] = b"{}[]\",:0-9e.aZ "[rng.below(15) as usize];
+                }
+                let _ = Policy::from_reader(bytes.as_slice());
+                let _ = AttrIdMap::from_reader(bytes.as_slice());
+            }
+        }
+    }
+
+    #[test]
+    fn arithmetic_overflow_is_an_error() {
+        let sum = Expression::from_json_value(&json!({ "operator": "ADD", "operands": [i64::MAX, 1] })).unwrap();
+        let product = Expression::from_json_value(&json!({ "operator": "MULTIPLY", "operands": [i64::MAX, 2] })).unwrap();
+        let src = SourceEntity::from_json_value(&json!({ "ip": "1", "attributes": {} })).unwrap();
+        let dst = DestinationEntity::from_json_value(&json!({ "ip": "2", "attributes": {} })).unwrap();
+        assert!(sum.evaluate(&src, &dst, &HashMap::new()).unwrap_err().contains("overflow"));
+        assert!(product.evaluate(&src, &dst, &HashMap::new()).unwrap_err().contains("overflow"));
+    }
+
+    #[test]
+    fn map_expressions_rewrites_attribute_references() {
+        let mut cond = Condition::from_json_value(&json!({
+            "operator": "OR", "operands": [
+                { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" },
+                { "operator": "EQ", "lhs": {
+                    "operator": "IF",
+                    "cond": { "operator": "EQ", "lhs": "Src.Role", "rhs": "Staff" },
+                    "then": 1, "else": 0
+                }, "rhs": 1 }
+            ]
+        })).unwrap();
+        let src = SourceEntity::from_json_value(&json!({
+            "ip": "10.0.0.1", "attributes": { "Src.Role": "Student", "Src.Dept": "Admin" }
+        })).unwrap();
+        let dst = DestinationEntity::from_json_value(&json!({ "ip": "10.0.0.2", "attributes": {} })).unwrap();
+        let env = HashMap::new();
+        assert_eq!(cond.evaluate(&src, &dst, &env), Ok(false));
+
+        // Src.Position は source の属性に無いので、移行先には既存の Src.Dept を使う
+        cond.map_expressions(|e| {
+            if let Expression::AttributeRef(name) = e
+                && name == "Src.Role"
+            {
+                *name = "Src.Dept".to_string();
+            }
+        });
+        assert_eq!(cond.attributes_referenced(), BTreeSet::from(["Src.Dept".to_string()]));
+        assert_eq!(cond.evaluate(&src, &dst, &env), Ok(true));
+    }
+}
@@ -12,6 +12,113 @@ pub enum SrcRequirement {
         required_ge: Vec<i64>,
         required_lt: Vec<i64>,
     },
+    /// OR の各分岐から得た要求。いずれかの分岐 (分岐内は AND) を満たせばよい
+    AnyOf { branches: Vec<Vec<SrcRequirement>> },
+}
+
+/// 数値の区間 `ge <= x < lt`。None は上限・下限なし
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumericBand {
+    pub ge: Option<i64>,
+    pub lt: Option<i64>,
+}
+
+impl NumericBand {
+    pub const UNBOUNDED: NumericBand = NumericBand { ge: None, lt: None };
+
+    /// 共通部分。空なら None
+    pub fn intersect(&self, other: &NumericBand) -> Option<NumericBand> {
+        let ge = match (self.ge, other.ge) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        let lt = match (self.lt, other.lt) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        match (ge, lt) {
+            (Some(ge), Some(lt)) if ge >= lt => None,
+            _ => Some(NumericBand { ge, lt }),
+        }
+    }
+}
+
+// 下限順に並べ、重なる・接する区間をつなげる
+fn normalize_bands(mut bands: Vec<NumericBand>) -> Vec<NumericBand> {
+    bands.sort_by_key(|b| b.ge.unwrap_or(i64::MIN));
+    let mut out: Vec<NumericBand> = Vec::new();
+    for band in bands {
+        if let Some(last) = out.last_mut()
+            && last.lt.is_none_or(|lt| band.ge.unwrap_or(i64::MIN) <= lt)
+        {
+            last.lt = match (last.lt, band.lt) {
+                (Some(a), Some(b)) => Some(a.max(b)),
+                _ => None,
+            };
+            continue;
+        }
+        out.push(band);
+    }
+    out
+}
+
+/// attr に対する数値要求を区間の和集合にまとめる。
+///
+/// 同じ階層の要求は AND として共通部分を取り、`AnyOf` の分岐は和集合を取る。
+/// 制約が無ければ `[UNBOUNDED]`、満たせなければ空を返す。
+pub fn merge_numeric_requirements(reqs: &[SrcRequirement], attr: &str) -> Vec<NumericBand> {
+    let mut bands = vec![NumericBand::UNBOUNDED];
+    for r in reqs {
+        let allowed = match r {
+            SrcRequirement::Numeric { attr: a, required_ge, required_lt } if a == attr => {
+                vec![NumericBand {
+                    ge: required_ge.iter().max().copied(),
+                    lt: required_lt.iter().min().copied(),
+                }]
+            }
+            SrcRequirement::AnyOf { branches } => normalize_bands(
+                branches
+                    .iter()
+                    .flat_map(|b| merge_numeric_requirements(b, attr))
+                    .collect(),
+            ),
+            _ => continue,
+        };
+        bands = bands
+            .iter()
+            .flat_map(|b| allowed.iter().filter_map(move |a| b.intersect(a)))
+            .collect();
+    }
+    normalize_bands(bands)
+}
+
+/// AnyOf を展開して全ての要求を並べる (分岐の区別は失われる)
+pub fn flatten_src_requirements(reqs: &[SrcRequirement]) -> Vec<&SrcRequirement> {
+    let mut out = Vec::new();
+    for r in reqs {
+        match r {
+            SrcRequirement::AnyOf { branches } => {
+                for b in branches {
+                    out.extend(flatten_src_requirements(b));
+                }
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DstRequirement {
+    Exact { attr: String, value: AttributeValue },
+    Containment { attr: String, allowed_set: Vec<String> },
+    Numeric {
+        attr: String,
+        required_ge: Vec<i64>,
+        required_lt: Vec<i64>,
+    },
+    /// OR の各分岐から得た要求。いずれかの分岐 (分岐内は AND) を満たせばよい
+    AnyOf { branches: Vec<Vec<DstRequirement>> },
 }
 
 fn dummy_source() -> SourceEntity {
@@ -22,11 +129,31 @@ fn dummy_source() -> SourceEntity {
     }
 }
 
+fn dummy_destination() -> DestinationEntity {
+    DestinationEntity {
+        ip: String::new(),
+        attributes: HashMap::new(),
+        desc: None,
+    }
+}
+
 fn eval_expr_with_dest(expr: &Expression, dest: &DestinationEntity) -> Result<AttributeValue, String> {
     let empty: HashMap<String, AttributeValue> = HashMap::new();
     expr.evaluate(&dummy_source(), dest, &empty)
 }
 
+fn eval_expr_with_source(expr: &Expression, source: &SourceEntity) -> Result<AttributeValue, String> {
+    let empty: HashMap<String, AttributeValue> = HashMap::new();
+    expr.evaluate(source, &dummy_destination(), &empty)
+}
+
+// 要求の出力が入力の並びに左右されないよう、許可値の集合は整列・重複除去しておく
+fn sorted_set(mut items: Vec<String>) -> Vec<String> {
+    items.sort();
+    items.dedup();
+    items
+}
+
 fn get_src_attr_name(expr: &Expression) -> Option<String> {
     match expr {
         Expression::AttributeRef(name) if name.starts_with("Src.") => Some(name.clone()),
@@ -34,24 +161,69 @@ fn get_src_attr_name(expr: &Expression) -> Option<String> {
     }
 }
 
+fn get_dst_attr_name(expr: &Expression) -> Option<String> {
+    match expr {
+        Expression::AttributeRef(name) if name.starts_with("Dst.") => Some(name.clone()),
+        _ => None,
+    }
+}
+
+// 比較の閾値として使える数値を取り出す。Condition::evaluate と同じく数値以外はエラーにし、
+// Null (比較が常に false) は要求なしとして None を返す
+fn numeric_threshold(value: AttributeValue) -> Result<Option<i64>, String> {
+    match value {
+        AttributeValue::Null => Ok(None),
+        v => v.as_number().map(Some).map_err(|e| format!("Comparison requires numbers: {}", e)),
+    }
+}
+
+// `x > t` を `x >= t + 1` のように閾値を 1 つずらすときの t + 1
+fn next_threshold(t: i64) -> Result<i64, String> {
+    t.checked_add(1).ok_or_else(|| format!("Threshold {} + 1 overflows i64", t))
+}
+
+/// dest を固定したときに source が満たすべき要求を集める。
+/// 両辺が Src / Env を参照する比較（`Src.SessionCount < Src.TrustScore` など）は
+/// Condition::evaluate では評価できるが、dest から決まる要求ではないので抽出しない
 pub fn collect_src_requirements(
     condition: &Condition,
     dest: &DestinationEntity,
+) -> Result<Vec<SrcRequirement>, String> {
+    collect_src(condition, dest)
+}
+
+fn collect_src(
+    condition: &Condition,
+    dest: &DestinationEntity,
 ) -> Result<Vec<SrcRequirement>, String> {
     match condition {
+        Condition::Always | Condition::Never => Ok(vec![]),
         Condition::And { operands } => {
             let mut out = Vec::new();
             for c in operands {
-                out.extend(collect_src_requirements(c, dest)?);
+                out.extend(collect_src(c, dest)?);
             }
             Ok(out)
         }
         Condition::Or { operands } => {
-            let mut out = Vec::new();
+            let mut branches = Vec::new();
             for c in operands {
-                out.extend(collect_src_requirements(c, dest)?);
+                // この dest では満たされない分岐は OR の結果に影響しない
+                if !c.evaluate_dest_only(dest)? {
+                    continue;
+                }
+                // 要求を持たない分岐 (Env だけの条件など) が 1 つでもあれば、
+                // その分岐だけで OR 全体が満たされ得るので src には何も要求できない
+                let reqs = collect_src(c, dest)?;
+                if reqs.is_empty() {
+                    return Ok(vec![]);
+                }
+                branches.push(reqs);
             }
-            Ok(out)
+            if branches.len() <= 1 {
+                return Ok(branches.pop().unwrap_or_default());
+            }
+            Ok(vec![SrcRequirement::AnyOf { branches }])
         }
         Condition::Eq { lhs, rhs } => {
             let (attr, other) = if let Some(ref attr) = get_src_attr_name(lhs) {
@@ -74,7 +246,7 @@ pub fn collect_src_requirements(
             if let Some(attr) = get_src_attr_name(lhs) {
                 if !rhs.references_src_or_env() {
                     let v = eval_expr_with_dest(rhs, dest)?;
-                    if let AttributeValue::Number(t) = v {
+                    if let Some(t) = numeric_threshold(v)? {
                         return Ok(vec![SrcRequirement::Numeric {
                             attr,
                             required_ge: vec![t],
@@ -86,11 +258,11 @@ pub fn collect_src_requirements(
             if let Some(attr) = get_src_attr_name(rhs) {
                 if !lhs.references_src_or_env() {
                     let v = eval_expr_with_dest(lhs, dest)?;
-                    if let AttributeValue::Number(t) = v {
+                    if let Some(t) = numeric_threshold(v)? {
                         return Ok(vec![SrcRequirement::Numeric {
                             attr,
-                            required_ge: vec![t],
-                            required_lt: vec![],
+                            required_ge: vec![],
+                            required_lt: vec![next_threshold(t)?],
                         }]);
                     }
                 }
@@ -101,10 +273,10 @@ pub fn collect_src_requirements(
             if let Some(attr) = get_src_attr_name(lhs) {
                 if !rhs.references_src_or_env() {
                     let v = eval_expr_with_dest(rhs, dest)?;
-                    if let AttributeValue::Number(t) = v {
+                    if let Some(t) = numeric_threshold(v)? {
                         return Ok(vec![SrcRequirement::Numeric {
                             attr,
-                            required_ge: vec![t + 1],
+                            required_ge: vec![next_threshold(t)?],
                             required_lt: vec![],
                         }]);
                     }
@@ -113,11 +285,11 @@ pub fn collect_src_requirements(
             if let Some(attr) = get_src_attr_name(rhs) {
                 if !lhs.references_src_or_env() {
                     let v = eval_expr_with_dest(lhs, dest)?;
-                    if let AttributeValue::Number(t) = v {
+                    if let Some(t) = numeric_threshold(v)? {
                         return Ok(vec![SrcRequirement::Numeric {
                             attr,
                             required_ge: vec![],
-                            required_lt: vec![t + 1],
+                            required_lt: vec![t],
                         }]);
                     }
                 }
@@ -128,7 +300,7 @@ pub fn collect_src_requirements(
             if let Some(attr) = get_src_attr_name(lhs) {
                 if !rhs.references_src_or_env() {
                     let v = eval_expr_with_dest(rhs, dest)?;
-                    if let AttributeValue::Number(t) = v {
+                    if let Some(t) = numeric_threshold(v)? {
                         return Ok(vec![SrcRequirement::Numeric {
                             attr,
                             required_ge: vec![],
@@ -140,11 +312,11 @@ pub fn collect_src_requirements(
             if let Some(attr) = get_src_attr_name(rhs) {
                 if !lhs.references_src_or_env() {
                     let v = eval_expr_with_dest(lhs, dest)?;
-                    if let AttributeValue::Number(t) = v {
+                    if let Some(t) = numeric_threshold(v)? {
                         return Ok(vec![SrcRequirement::Numeric {
                             attr,
-                            required_ge: vec![],
-                            required_lt: vec![t],
+                            required_ge: vec![next_threshold(t)?],
+                            required_lt: vec![],
                         }]);
                     }
                 }
@@ -160,12 +332,14 @@ pub fn collect_src_requirements(
                 if let AttributeValue::Set(allowed) = set_val {
                     return Ok(vec![SrcRequirement::Containment {
                         attr,
-                        allowed_set: allowed,
+                        allowed_set: sorted_set(allowed),
                     }]);
                 }
             }
             Ok(vec![])
         }
+        // 「含まれない」は許可値の集合で表せないので要求にしない (過大評価)
+        Condition::NotIn { .. } => Ok(vec![]),
         Condition::InSet { value, set } => {
             if let Some(attr) = get_src_attr_name(set) {
                 if value.references_src_or_env() {
@@ -179,7 +353,562 @@ pub fn collect_src_requirements(
                     }]);
                 }
             }
+            // Src のスカラーが dest 側の集合に含まれる。
+            // 集合が Env から来る場合は評価時まで決まらないので要求にしない
+            if let Some(attr) = get_src_attr_name(value)
+                && !set.references_src_or_env()
+                && let AttributeValue::Set(allowed) = eval_expr_with_dest(set, dest)?
+            {
+                return Ok(vec![SrcRequirement::Containment {
+                    attr,
+                    allowed_set: sorted_set(allowed),
+                }]);
+            }
+            Ok(vec![])
+        }
+    }
+}
+
+
+pub fn collect_dst_requirements(
+    condition: &Condition,
+    source: &SourceEntity,
+) -> Result<Vec<DstRequirement>, String> {
+    match condition {
+        Condition::Always | Condition::Never => Ok(vec![]),
+        Condition::And { operands } => {
+            let mut out = Vec::new();
+            for c in operands {
+                out.extend(collect_dst_requirements(c, source)?);
+            }
+            Ok(out)
+        }
+        Condition::Or { operands } => {
+            let mut branches = Vec::new();
+            for c in operands {
+                // この source では満たされない分岐は OR の結果に影響しない
+                if !c.evaluate_source_only(source)? {
+                    continue;
+                }
+                // 要求を持たない分岐が 1 つでもあれば dst には何も要求できない
+                let reqs = collect_dst_requirements(c, source)?;
+                if reqs.is_empty() {
+                    return Ok(vec![]);
+                }
+                branches.push(reqs);
+            }
+            if branches.len() <= 1 {
+                return Ok(branches.pop().unwrap_or_default());
+            }
+            Ok(vec![DstRequirement::AnyOf { branches }])
+        }
+        Condition::Eq { lhs, rhs } => {
+            let (attr, other) = if let Some(ref attr) = get_dst_attr_name(lhs) {
+                if rhs.references_dst_or_env() {
+                    return Ok(vec![]);
+                }
+                (attr.clone(), rhs)
+            } else if let Some(ref attr) = get_dst_attr_name(rhs) {
+                if lhs.references_dst_or_env() {
+                    return Ok(vec![]);
+                }
+                (attr.clone(), lhs)
+            } else {
+                return Ok(vec![]);
+            };
+            let value = eval_expr_with_source(other, source)?;
+            Ok(vec![DstRequirement::Exact { attr, value }])
+        }
+        Condition::Gte { lhs, rhs } => {
+            if let Some(attr) = get_dst_attr_name(lhs)
+                && !rhs.references_dst_or_env()
+            {
+                let v = eval_expr_with_source(rhs, source)?;
+                if let Some(t) = numeric_threshold(v)? {
+                    return Ok(vec![DstRequirement::Numeric {
+                        attr,
+                        required_ge: vec![t],
+                        required_lt: vec![],
+                    }]);
+                }
+            }
+            if let Some(attr) = get_dst_attr_name(rhs)
+                && !lhs.references_dst_or_env()
+            {
+                let v = eval_expr_with_source(lhs, source)?;
+                if let Some(t) = numeric_threshold(v)? {
+                    return Ok(vec![DstRequirement::Numeric {
+                        attr,
+                        required_ge: vec![],
+                        required_lt: vec![next_threshold(t)?],
+                    }]);
+                }
+            }
+            Ok(vec![])
+        }
+        Condition::Gt { lhs, rhs } => {
+            if let Some(attr) = get_dst_attr_name(lhs)
+                && !rhs.references_dst_or_env()
+            {
+                let v = eval_expr_with_source(rhs, source)?;
+                if let Some(t) = numeric_threshold(v)? {
+                    return Ok(vec![DstRequirement::Numeric {
+                        attr,
+                        required_ge: vec![next_threshold(t)?],
+                        required_lt: vec![],
+                    }]);
+                }
+            }
+            if let Some(attr) = get_dst_attr_name(rhs)
+                && !lhs.references_dst_or_env()
+            {
+                let v = eval_expr_with_source(lhs, source)?;
+                if let Some(t) = numeric_threshold(v)? {
+                    return Ok(vec![DstRequirement::Numeric {
+                        attr,
+                        required_ge: vec![],
+                        required_lt: vec![t],
+                    }]);
+                }
+            }
+            Ok(vec![])
+        }
+        Condition::Lt { lhs, rhs } => {
+            if let Some(attr) = get_dst_attr_name(lhs)
+                && !rhs.references_dst_or_env()
+            {
+                let v = eval_expr_with_source(rhs, source)?;
+                if let Some(t) = numeric_threshold(v)? {
+                    return Ok(vec![DstRequirement::Numeric {
+                        attr,
+                        required_ge: vec![],
+                        required_lt: vec![t],
+                    }]);
+                }
+            }
+            if let Some(attr) = get_dst_attr_name(rhs)
+                && !lhs.references_dst_or_env()
+            {
+                let v = eval_expr_with_source(lhs, source)?;
+                if let Some(t) = numeric_threshold(v)? {
+                    return Ok(vec![DstRequirement::Numeric {
+                        attr,
+                        required_ge: vec![next_threshold(t)?],
+                        required_lt: vec![],
+                    }]);
+                }
+            }
+            Ok(vec![])
+        }
+        Condition::In { target, check_against } => {
+            if let Some(attr) = get_dst_attr_name(target) {
+                if check_against.references_dst_or_env() {
+                    return Ok(vec![]);
+                }
+                let set_val = eval_expr_with_source(check_against, source)?;
+                if let AttributeValue::Set(allowed) = set_val {
+                    return Ok(vec![DstRequirement::Containment {
+                        attr,
+                        allowed_set: sorted_set(allowed),
+                    }]);
+                }
+            }
+            Ok(vec![])
+        }
+        Condition::NotIn { .. } => Ok(vec![]),
+        Condition::InSet { value, set } => {
+            if let Some(attr) = get_dst_attr_name(set) {
+                if value.references_dst_or_env() {
+                    return Ok(vec![]);
+                }
+                let v = eval_expr_with_source(value, source)?;
+                if let AttributeValue::String(s) = v {
+                    return Ok(vec![DstRequirement::Containment {
+                        attr,
+                        allowed_set: vec![s],
+                    }]);
+                }
+            }
             Ok(vec![])
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn source() -> SourceEntity {
+        SourceEntity::from_json_value(&json!({
+            "ip": "10.0.0.1",
+            "attributes": { "Src.Role": "Student", "Src.Dept": "CS", "Src.TrustScore": 50, "Src.Groups": ["Undergrad"] }
+        })).unwrap()
+    }
+
+    fn destination() -> DestinationEntity {
+        DestinationEntity::from_json_value(&json!({
+            "ip": "172.20.0.1",
+            "attributes": { "Dst.Type": "FileServer", "Dst.OwnerDept": "CS", "Dst.Sensitivity": 7, "Dst.AllowedVLANs": ["Faculty"] }
+        })).unwrap()
+    }
+
+    fn condition(value: serde_json::Value) -> Condition {
+        Condition::from_json_value(&value).unwrap()
+    }
+
+    #[test]
+    fn src_requirements_take_values_from_destination() {
+        let cond = condition(json!({
+            "operator": "AND",
+            "operands": [
+                { "operator": "EQ", "lhs": "Src.Dept", "rhs": "Dst.OwnerDept" },
+                { "operator": "GTE", "lhs": "Src.TrustScore", "rhs": 80 },
+                { "operator": "IN", "target": "Src.Groups", "check_against": "Dst.AllowedVLANs" }
+            ]
+        }));
+        let reqs = collect_src_requirements(&cond, &destination()).unwrap();
+        assert_eq!(reqs, vec![
+            SrcRequirement::Exact { attr: "Src.Dept".to_string(), value: AttributeValue::String("CS".to_string()) },
+            SrcRequirement::Numeric { attr: "Src.TrustScore".to_string(), required_ge: vec![80], required_lt: vec![] },
+            SrcRequirement::Containment { attr: "Src.Groups".to_string(), allowed_set: vec!["Faculty".to_string()] },
+        ]);
+    }
+
+    #[test]
+    fn dst_requirements_take_values_from_source() {
+        let cond = condition(json!({
+            "operator": "AND",
+            "operands": [
+                { "operator": "EQ", "lhs": "Src.Dept", "rhs": "Dst.OwnerDept" },
+                { "operator": "LT", "lhs": "Dst.Sensitivity", "rhs": "Src.TrustScore" },
+                { "operator": "IN", "value": "Src.Role", "set": "Dst.AllowedVLANs" }
+            ]
+        }));
+        let reqs = collect_dst_requirements(&cond, &source()).unwrap();
+        assert_eq!(reqs, vec![
+            DstRequirement::Exact { attr: "Dst.OwnerDept".to_string(), value: AttributeValue::String("CS".to_string()) },
+            DstRequirement::Numeric { attr: "Dst.Sensitivity".to_string(), required_ge: vec![], required_lt: vec![50] },
+            DstRequirement::Containment { attr: "Dst.AllowedVLANs".to_string(), allowed_set: vec!["Student".to_string()] },
+        ]);
+    }
+
+    #[test]
+    fn dst_or_branches_become_alternatives() {
+        let exact = |value: &str| DstRequirement::Exact {
+            attr: "Dst.Type".to_string(),
+            value: AttributeValue::String(value.to_string()),
+        };
+        let cond = condition(json!({
+            "operator": "OR",
+            "operands": [
+                { "operator": "EQ", "lhs": "Dst.Type", "rhs": "Printer" },
+                { "operator": "EQ", "lhs": "Dst.Type", "rhs": "Server" }
+            ]
+        }));
+        assert_eq!(collect_dst_requirements(&cond, &source()).unwrap(), vec![DstRequirement::AnyOf {
+            branches: vec![vec![exact("Printer")], vec![exact("Server")]],
+        }]);
+
+        // この source では成り立たない分岐は除き、制約の無い分岐があれば要求なし
+        let guarded = condition(json!({
+            "operator": "OR",
+            "operands": [
+                { "operator": "AND", "operands": [
+                    { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" },
+                    { "operator": "EQ", "lhs": "Dst.Type", "rhs": "Server" }
+                ]},
+                { "operator": "EQ", "lhs": "Dst.Type", "rhs": "Printer" }
+            ]
+        }));
+        assert_eq!(collect_dst_requirements(&guarded, &source()).unwrap(), vec![exact("Printer")]);
+        let open = condition(json!({
+            "operator": "OR",
+            "operands": [
+                { "operator": "EQ", "lhs": "Dst.Type", "rhs": "Printer" },
+                { "operator": "EQ", "lhs": "Src.Role", "rhs": "Student" }
+            ]
+        }));
+        assert_eq!(collect_dst_requirements(&open, &source()).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn dst_thresholds_fail_like_the_src_side() {
+        let overflow = condition(json!({ "operator": "GT", "lhs": "Dst.Sensitivity", "rhs": i64::MAX }));
+        assert!(collect_dst_requirements(&overflow, &source()).is_err());
+
+        let textual = condition(json!({ "operator": "GTE", "lhs": "Dst.Sensitivity", "rhs": "Src.Role" }));
+        let err = collect_dst_requirements(&textual, &source()).unwrap_err();
+        assert!(err.starts_with("Comparison requires numbers"), "{}", err);
+    }
+
+    #[test]
+    fn dst_requirements_skip_env_dependent_comparisons() {
+        let cond = condition(json!({ "operator": "GT", "lhs": "Dst.Sensitivity", "rhs": "Env.Hour" }));
+        assert!(collect_dst_requirements(&cond, &source()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn numeric_dst_attribute_drives_src_threshold() {
+        let cond = condition(json!({ "operator": "GTE", "lhs": "Src.TrustScore", "rhs": "Dst.Sensitivity" }));
+        let expected = vec![SrcRequirement::Numeric {
+            attr: "Src.TrustScore".to_string(),
+            required_ge: vec![7],
+            required_lt: vec![],
+        }];
+        assert_eq!(collect_src_requirements(&cond, &destination()).unwrap(), expected);
+
+        // 文字列の閾値は Condition::evaluate と同じくエラーにする (要求なしにはしない)
+        let textual = DestinationEntity::from_json_value(&json!({
+            "ip": "172.20.0.2", "attributes": { "Dst.Sensitivity": "7" }
+        })).unwrap();
+        let err = collect_src_requirements(&cond, &textual).unwrap_err();
+        assert!(err.starts_with("Comparison requires numbers"), "{}", err);
+        assert!(cond.evaluate(&source(), &textual, &HashMap::new()).unwrap_err().starts_with("Comparison requires numbers"));
+    }
+
+    #[test]
+    fn reversed_operands_flip_the_src_band() {
+        let numeric = |ge: Vec<i64>, lt: Vec<i64>| vec![SrcRequirement::Numeric {
+            attr: "Src.TrustScore".to_string(),
+            required_ge: ge,
+            required_lt: lt,
+        }];
+        let cases = [
+            ("GTE", numeric(vec![], vec![8])),
+            ("GT", numeric(vec![], vec![7])),
+            ("LT", numeric(vec![8], vec![])),
+        ];
+        for (op, expected) in cases {
+            let cond = condition(json!({ "operator": op, "lhs": "Dst.Sensitivity", "rhs": "Src.TrustScore" }));
+            assert_eq!(collect_src_requirements(&cond, &destination()).unwrap(), expected, "{}", op);
+        }
+
+        // 抽出した帯は評価結果と一致する
+        let env = HashMap::new();
+        for score in [6, 7, 8] {
+            let src = SourceEntity::from_json_value(&json!({
+                "ip": "10.0.0.3", "attributes": { "Src.TrustScore": score }
+            })).unwrap();
+            let cond = condition(json!({ "operator": "GTE", "lhs": "Dst.Sensitivity", "rhs": "Src.TrustScore" }));
+            assert_eq!(cond.evaluate(&src, &destination(), &env), Ok(score < 8), "{}", score);
+        }
+
+        let overflow = condition(json!({ "operator": "GT", "lhs": "Src.TrustScore", "rhs": i64::MAX }));
+        assert!(collect_src_requirements(&overflow, &destination()).is_err());
+    }
+
+    #[test]
+    fn src_vs_src_comparisons_are_evaluated_but_not_extracted() {
+        let source = SourceEntity::from_json_value(&json!({
+            "ip": "10.0.0.2",
+            "attributes": { "Src.Role": "CS", "Src.Dept": "CS", "Src.TrustScore": 50, "Src.SessionCount": 3 }
+        })).unwrap();
+        let env = HashMap::new();
+
+        let eq = condition(json!({ "operator": "EQ", "lhs": "Src.Role", "rhs": "Src.Dept" }));
+        let gte = condition(json!({ "operator": "GTE", "lhs": "Src.TrustScore", "rhs": "Src.SessionCount" }));
+        let reversed = condition(json!({ "operator": "GTE", "lhs": "Src.SessionCount", "rhs": "Src.TrustScore" }));
+        assert_eq!(eq.evaluate(&source, &destination(), &env), Ok(true));
+        assert_eq!(gte.evaluate(&source, &destination(), &env), Ok(true));
+        assert_eq!(reversed.evaluate(&source, &destination(), &env), Ok(false));
+
+        for cond in [&eq, &gte, &reversed] {
+            assert_eq!(collect_src_requirements(cond, &destination()).unwrap(), vec![]);
+        }
+    }
+
+    fn band(ge: Option<i64>, lt: Option<i64>) -> NumericBand {
+        NumericBand { ge, lt }
+    }
+
+    #[test]
+    fn and_intersects_and_or_unions_numeric_bands() {
+        let and = condition(json!({
+            "operator": "AND",
+            "operands": [
+                { "operator": "GTE", "lhs": "Src.TrustScore", "rhs": 20 },
+                { "operator": "LT", "lhs": "Src.TrustScore", "rhs": 60 },
+                { "operator": "GTE", "lhs": "Src.TrustScore", "rhs": 40 }
+            ]
+        }));
+        let reqs = collect_src_requirements(&and, &destination()).unwrap();
+        assert_eq!(merge_numeric_requirements(&reqs, "Src.TrustScore"), vec![band(Some(40), Some(60))]);
+
+        let or = condition(json!({
+            "operator": "OR",
+            "operands": [
+                { "operator": "LT", "lhs": "Src.TrustScore", "rhs": 10 },
+                { "operator": "GTE", "lhs": "Src.TrustScore", "rhs": 80 },
+                { "operator": "AND", "operands": [
+                    { "operator": "GTE", "lhs": "Src.TrustScore", "rhs": 70 },
+                    { "operator": "LT", "lhs": "Src.TrustScore", "rhs": 90 }
+                ]}
+            ]
+        }));
+        let reqs = collect_src_requirements(&or, &destination()).unwrap();
+        assert!(matches!(reqs.as_slice(), [SrcRequirement::AnyOf { branches }] if branches.len() == 3));
+        assert_eq!(
+            merge_numeric_requirements(&reqs, "Src.TrustScore"),
+            vec![band(None, Some(10)), band(Some(70), None)]
+        );
+        assert_eq!(merge_numeric_requirements(&reqs, "Src.SessionCount"), vec![NumericBand::UNBOUNDED]);
+
+        let disjoint = condition(json!({
+            "operator": "AND",
+            "operands": [
+                { "operator": "GTE", "lhs": "Src.TrustScore", "rhs": 50 },
+                { "operator": "LT", "lhs": "Src.TrustScore", "rhs": 50 }
+            ]
+        }));
+        let reqs = collect_src_requirements(&disjoint, &destination()).unwrap();
+        assert!(merge_numeric_requirements(&reqs, "Src.TrustScore").is_empty());
+    }
+
+    #[test]
+    fn or_with_unconstrained_branch_yields_no_requirement() {
+        // Env だけの分岐は src に関係なく満たされ得るので、OR 全体でも要求は無い
+        let with_env = condition(json!({
+            "operator": "OR",
+            "operands": [
+                { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" },
+                { "operator": "LT", "lhs": "Env.Hour", "rhs": 18 }
+            ]
+        }));
+        assert_eq!(collect_src_requirements(&with_env, &destination()).unwrap(), vec![]);
+
+        // dest だけで真になる分岐も同じ
+        let with_dst = condition(json!({
+            "operator": "OR",
+            "operands": [
+                { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" },
+                { "operator": "EQ", "lhs": "Dst.Type", "rhs": "FileServer" }
+            ]
+        }));
+        assert_eq!(collect_src_requirements(&with_dst, &destination()).unwrap(), vec![]);
+
+        // dest で偽になる分岐は無視され、残りの分岐の要求だけが残る
+        let with_false_dst = condition(json!({
+            "operator": "OR",
+            "operands": [
+                { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" },
+                { "operator": "EQ", "lhs": "Dst.Type", "rhs": "WebServer" }
+            ]
+        }));
+        assert_eq!(
+            collect_src_requirements(&with_false_dst, &destination()).unwrap(),
+            vec![SrcRequirement::Exact { attr: "Src.Role".to_string(), value: AttributeValue::String("Admin".to_string()) }]
+        );
+    }
+
+    #[test]
+    fn disjoint_numeric_bands_are_not_widened_when_encoding() {
+        let map = crate::ip_based::encoder::AttrIdMap::load("data/ip_based_abac_attr_id.json").unwrap();
+        let order = ["Src.TrustScore"];
+        let thresholds = HashMap::new();
+
+        let disjoint = condition(json!({
+            "operator": "OR",
+            "operands": [
+                { "operator": "LT", "lhs": "Src.TrustScore", "rhs": 10 },
+                { "operator": "GTE", "lhs": "Src.TrustScore", "rhs": 80 }
+            ]
+        }));
+        let reqs = collect_src_requirements(&disjoint, &destination()).unwrap();
+        let err = crate::ip_based::encoder::requirements_to_bit_arrays(&map, &reqs, &order, &thresholds).unwrap_err();
+        assert!(err.contains("disjoint"), "{}", err);
+
+        // 重なる区間は 1 つにまとまるので符号化できる
+        let overlapping = condition(json!({
+            "operator": "OR",
+            "operands": [
+                { "operator": "GTE", "lhs": "Src.TrustScore", "rhs": 50 },
+                { "operator": "GTE", "lhs": "Src.TrustScore", "rhs": 80 }
+            ]
+        }));
+        let reqs = collect_src_requirements(&overlapping, &destination()).unwrap();
+        assert!(crate::ip_based::encoder::requirements_to_bit_arrays(&map, &reqs, &order, &thresholds).is_ok());
+    }
+
+    #[test]
+    fn containment_sets_are_sorted_and_deduplicated() {
+        let cond = condition(json!({ "operator": "IN", "target": "Src.Groups", "check_against": ["Staff", "Faculty", "Staff"] }));
+        assert_eq!(
+            collect_src_requirements(&cond, &destination()).unwrap(),
+            vec![SrcRequirement::Containment {
+                attr: "Src.Groups".to_string(),
+                allowed_set: vec!["Faculty".to_string(), "Staff".to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    fn in_set_with_env_provided_set() {
+        let cond = condition(json!({ "operator": "IN", "value": "Dst.Type", "set": "Env.AllowedTypes" }));
+        let mut env = HashMap::new();
+        env.insert(
+            "Env.AllowedTypes".to_string(),
+            AttributeValue::Set(vec!["WebServer".to_string(), "FileServer".to_string()]),
+        );
+        assert_eq!(cond.evaluate(&source(), &destination(), &env), Ok(true));
+        env.insert("Env.AllowedTypes".to_string(), AttributeValue::Set(vec!["WebServer".to_string()]));
+        assert_eq!(cond.evaluate(&source(), &destination(), &env), Ok(false));
+        // Env が決まるまでは適用され得るものとして扱う
+        assert_eq!(cond.evaluate_dest_only(&destination()), Ok(true));
+
+        // Env の集合からは要求を作らないが、同じ AND の他の要求は残る
+        let mixed = condition(json!({
+            "operator": "AND",
+            "operands": [
+                { "operator": "IN", "value": "Src.Role", "set": "Env.AllowedRoles" },
+                { "operator": "EQ", "lhs": "Src.Dept", "rhs": "Dst.OwnerDept" }
+            ]
+        }));
+        assert_eq!(
+            collect_src_requirements(&mixed, &destination()).unwrap(),
+            vec![SrcRequirement::Exact { attr: "Src.Dept".to_string(), value: AttributeValue::String("CS".to_string()) }]
+        );
+
+        let from_dest = condition(json!({ "operator": "IN", "value": "Src.Role", "set": "Dst.AllowedVLANs" }));
+        assert_eq!(
+            collect_src_requirements(&from_dest, &destination()).unwrap(),
+            vec![SrcRequirement::Containment { attr: "Src.Role".to_string(), allowed_set: vec!["Faculty".to_string()] }]
+        );
+    }
+
+    #[test]
+    fn if_expression_picks_threshold_by_destination() {
+        let cond = condition(json!({
+            "operator": "GTE",
+            "lhs": "Src.TrustScore",
+            "rhs": {
+                "operator": "IF",
+                "cond": { "operator": "GTE", "lhs": "Dst.Sensitivity", "rhs": 5 },
+                "then": 80,
+                "else": 40
+            }
+        }));
+        let sensitive = destination();
+        let public = DestinationEntity::from_json_value(&json!({
+            "ip": "172.20.0.2", "attributes": { "Dst.Sensitivity": 2 }
+        })).unwrap();
+        let env = HashMap::new();
+
+        // TrustScore 50 は機密度の低い宛先だけ通る
+        assert_eq!(cond.evaluate(&source(), &sensitive, &env), Ok(false));
+        assert_eq!(cond.evaluate(&source(), &public, &env), Ok(true));
+
+        let threshold = |dest: &DestinationEntity| match &collect_src_requirements(&cond, dest).unwrap()[..] {
+            [SrcRequirement::Numeric { required_ge, .. }] => required_ge.clone(),
+            other => panic!("unexpected: {:?}", other),
+        };
+        assert_eq!(threshold(&sensitive), vec![80]);
+        assert_eq!(threshold(&public), vec![40]);
+
+        if let Condition::Gte { rhs, .. } = &cond {
+            assert!(rhs.references_dst_or_env());
+            assert!(!rhs.references_src_or_env());
+            assert!(rhs.references_dst());
+        }
+    }
+}
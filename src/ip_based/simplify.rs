@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use crate::ip_based::entity::{DestinationEntity, SourceEntity};
+use crate::ip_based::rule::{Condition, Expression};
+
+// 属性・環境変数を参照しない（評価結果が常に同じ）式か
+fn is_constant(expr: &Expression) -> bool {
+    match expr {
+        Expression::LiteralString(_) | Expression::LiteralNumber(_) | Expression::LiteralSet(_) => true,
+        Expression::AttributeRef(_) | Expression::EnvRef(_) => false,
+        Expression::Add { operands } | Expression::Multiply { operands } => operands.iter().all(is_constant),
+        Expression::Count { operand } => is_constant(operand),
+        // 条件側の定数判定はしない
+        Expression::If { .. } => false,
+    }
+}
+
+// 定数だけの比較を評価して Always / Never に置き換える。評価エラーになるものはそのまま残す
+fn fold_constant(cond: &Condition, lhs: &Expression, rhs: &Expression) -> Condition {
+    if !is_constant(lhs) || !is_constant(rhs) {
+        return cond.clone();
+    }
+    let source = SourceEntity { ip: String::new(), attributes: HashMap::new(), desc: None };
+    let destination = DestinationEntity { ip: String::new(), attributes: HashMap::new(), desc: None };
+    match cond.evaluate(&source, &destination, &HashMap::new()) {
+        Ok(true) => Condition::Always,
+        Ok(false) => Condition::Never,
+        Err(_) => cond.clone(),
+    }
+}
+
+impl Condition {
+    /// 定数畳み込みをした条件を返す。
+    ///
+    /// 属性・環境変数を参照しない比較（`EQ ADD(2,3) 5` など）を Always / Never に置き換え、
+    /// AND / OR から取り除く。AND に Never があれば全体が Never、OR に Always があれば全体が Always になる。
+    /// 評価順序に依存した短絡は変わるため、他の被演算子が評価エラーになる場合の結果は変わり得る。
+    pub fn simplify(&self) -> Condition {
+        match self {
+            Condition::And { operands } => {
+                let mut kept = Vec::new();
+                for c in operands {
+                    match c.simplify() {
+                        Condition::Never => return Condition::Never,
+                        Condition::Always => {}
+                        other => kept.push(other),
+                    }
+                }
+                match kept.len() {
+                    0 => Condition::Always,
+                    1 => kept.remove(0),
+                    _ => Condition::And { operands: kept },
+                }
+            }
+            Condition::Or { operands } => {
+                let mut kept = Vec::new();
+                for c in operands {
+                    match c.simplify() {
+                        Condition::Always => return Condition::Always,
+                        Condition::Never => {}
+                        other => kept.push(other),
+                    }
+                }
+                match kept.len() {
+                    0 => Condition::Never,
+                    1 => kept.remove(0),
+                    _ => Condition::Or { operands: kept },
+                }
+            }
+            Condition::Eq { lhs, rhs }
+            | Condition::Gte { lhs, rhs }
+            | Condition::Gt { lhs, rhs }
+            | Condition::Lt { lhs, rhs }
+            | Condition::In { target: lhs, check_against: rhs }
+            | Condition::NotIn { target: lhs, check_against: rhs }
+            | Condition::InSet { value: lhs, set: rhs } => fold_constant(self, lhs, rhs),
+            Condition::Always | Condition::Never => self.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn condition(value: serde_json::Value) -> Condition {
+        Condition::from_json_value(&value).unwrap()
+    }
+
+    #[test]
+    fn constant_true_and_operand_is_pruned() {
+        let role = json!({ "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" });
+        let cond = condition(json!({
+            "operator": "AND",
+            "operands": [
+                { "operator": "EQ", "lhs": { "operator": "ADD", "operands": [2, 3] }, "rhs": 5 },
+                role
+            ]
+        }));
+        assert_eq!(cond.simplify(), condition(role));
+
+        let never = condition(json!({
+            "operator": "AND",
+            "operands": [{ "operator": "GT", "lhs": 1, "rhs": 2 }, { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" }]
+        }));
+        assert!(matches!(never.simplify(), Condition::Never));
+    }
+
+    #[test]
+    fn constant_false_or_operand_is_pruned() {
+        let trust = json!({ "operator": "GTE", "lhs": "Src.TrustScore", "rhs": 80 });
+        let dept = json!({ "operator": "EQ", "lhs": "Src.Dept", "rhs": "CS" });
+        let cond = condition(json!({
+            "operator": "OR",
+            "operands": [
+                trust,
+                { "operator": "IN", "target": "x", "check_against": ["a", "b"] },
+                dept
+            ]
+        }));
+        let expected = condition(json!({ "operator": "OR", "operands": [trust, dept] }));
+        assert_eq!(cond.simplify(), expected);
+
+        // 型エラーになる定数比較は畳み込まない
+        let invalid = condition(json!({ "operator": "GTE", "lhs": "a", "rhs": 1 }));
+        assert_eq!(invalid.simplify(), invalid);
+    }
+}
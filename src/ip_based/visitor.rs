@@ -0,0 +1,107 @@
+use crate::ip_based::rule::{Condition, Expression};
+
+/// Condition / Expression の木を辿る解析のためのトレイト。
+/// 既定の実装は子ノードを順に辿るだけなので、必要なフックだけ上書きし、
+/// 子ノードも辿りたい場合は `walk_condition` / `walk_expression` を呼ぶ。
+pub trait ConditionVisitor {
+    fn visit_condition(&mut self, cond: &Condition) {
+        walk_condition(self, cond);
+    }
+
+    fn visit_expression(&mut self, expr: &Expression) {
+        walk_expression(self, expr);
+    }
+}
+
+pub fn walk_condition<V: ConditionVisitor + ?Sized>(visitor: &mut V, cond: &Condition) {
+    match cond {
+        Condition::Always | Condition::Never => {}
+        Condition::And { operands } | Condition::Or { operands } => {
+            for c in operands {
+                visitor.visit_condition(c);
+            }
+        }
+        Condition::Eq { lhs, rhs }
+        | Condition::Gte { lhs, rhs }
+        | Condition::Gt { lhs, rhs }
+        | Condition::Lt { lhs, rhs } => {
+            visitor.visit_expression(lhs);
+            visitor.visit_expression(rhs);
+        }
+        Condition::In { target, check_against } | Condition::NotIn { target, check_against } => {
+            visitor.visit_expression(target);
+            visitor.visit_expression(check_against);
+        }
+        Condition::InSet { value, set } => {
+            visitor.visit_expression(value);
+            visitor.visit_expression(set);
+        }
+    }
+}
+
+pub fn walk_expression<V: ConditionVisitor + ?Sized>(visitor: &mut V, expr: &Expression) {
+    match expr {
+        Expression::LiteralString(_)
+        | Expression::LiteralNumber(_)
+        | Expression::LiteralSet(_)
+        | Expression::AttributeRef(_)
+        | Expression::EnvRef(_) => {}
+        Expression::Add { operands } | Expression::Multiply { operands } => {
+            for e in operands {
+                visitor.visit_expression(e);
+            }
+        }
+        Expression::Count { operand } => visitor.visit_expression(operand),
+        Expression::If { cond, then, els } => {
+            visitor.visit_condition(cond);
+            visitor.visit_expression(then);
+            visitor.visit_expression(els);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Default)]
+    struct NodeCounter {
+        conditions: usize,
+        expressions: usize,
+    }
+
+    impl ConditionVisitor for NodeCounter {
+        fn visit_condition(&mut self, cond: &Condition) {
+            self.conditions += 1;
+            walk_condition(self, cond);
+        }
+
+        fn visit_expression(&mut self, expr: &Expression) {
+            self.expressions += 1;
+            walk_expression(self, expr);
+        }
+    }
+
+    #[test]
+    fn counting_visitor_sees_every_node() {
+        let cond = Condition::from_json_value(&json!({
+            "operator": "AND",
+            "operands": [
+                { "operator": "EQ", "lhs": "Src.Dept", "rhs": "Dst.OwnerDept" },
+                {
+                    "operator": "OR",
+                    "operands": [
+                        { "operator": "GTE", "lhs": { "operator": "ADD", "operands": ["Src.TrustScore", 5] }, "rhs": 80 },
+                        { "operator": "TRUE" }
+                    ]
+                }
+            ]
+        })).unwrap();
+
+        let mut counter = NodeCounter::default();
+        counter.visit_condition(&cond);
+        assert_eq!(counter.conditions, 5);
+        assert_eq!(counter.expressions, 6);
+    }
+}
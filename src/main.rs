@@ -1,5 +1,7 @@
 mod abac_lab;
 mod ip_based;
+mod example_tree;
+mod util;
 
 #[path = "pol-tree/cal_shannon_entropy.rs"]
 mod cal_shannon_entropy;
@@ -7,18 +9,15 @@ mod cal_shannon_entropy;
 mod cal_probabilities;
 
 use abac_lab::parser::Parser;
-use std::fs::File;
-use std::io::prelude::*;
 use std::collections::HashMap;
 
 use abac_lab::attr_val::*;
-use ip_based::entity::{AttributeValue, SourceEntity, DestinationEntity, SourceEntityAttributeKey, DestinationEntityAttributeKey};
+use ip_based::entity::{load_entities, AttributeValue, SourceEntity, DestinationEntity, SourceEntityAttributeKey, DestinationEntityAttributeKey};
 use ip_based::rule::*;
 use ip_based::classifier::*;
 use ip_based::encoder::*;
 use ip_based::rule_requirements::*;
-
-use serde_json::Value;
+use ip_based::output::{OutputFormat, PipelineReport, render_report};
 
 use cal_probabilities::{
     cal_source_entity_attribute_entropy,
@@ -31,22 +30,43 @@ struct LoadedData {
     policy: Policy
 }
 
+// --format json|csv|text (省略時は text)
+fn parse_format_arg() -> Result<OutputFormat, String> {
+    let mut args = std::env::args().skip(1);
+    let mut format = OutputFormat::Text;
+    while let Some(arg) = args.next() {
+        if let Some(value) = arg.strip_prefix("--format=") {
+            format = OutputFormat::parse(value)?;
+        } else if arg == "--format" {
+            let value = args.next().ok_or("--format requires a value")?;
+            format = OutputFormat::parse(&value)?;
+        } else {
+            return Err(format!("Unknown argument: {}", arg));
+        }
+    }
+    Ok(format)
+}
+
 fn main() {
+    let format = match parse_format_arg() {
+        Ok(format) => format,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
     let data = load_entities_and_policy();
+    let mut report = PipelineReport::default();
 
     let applicable_rules = list_applicable_rules_per_dest_entity(
         std::slice::from_ref(&data.policy),
         &data.destination_entities,
     );
-    
-    for (dest_ip, rules) in &applicable_rules {
-        println!("Destination IP: {}", dest_ip);
-        for rule_id in rules {
-            println!("  {}", rule_id);
-        }
-    }
+    report.applicable_rules = applicable_rules.clone();
 
     let attr_id = AttrIdMap::load("data/ip_based_abac_attr_id.json").expect("attr_id load");
+    attr_id.validate_widths().expect("attr_id widths");
 
     let source_attr_order = [
         "Src.Role",
@@ -55,17 +75,21 @@ fn main() {
         "Src.Groups"
     ];
 
-    // let trust_score_thresholds = [0i64, 50, 80];
+    let mut numeric_thresholds = HashMap::new();
+    numeric_thresholds.insert("Src.TrustScore".to_string(), vec![0, 50, 80]);
 
     for src in &data.source_entities {
         let encoded = encode_source_entity(&attr_id, src).expect("encode source");
-        let bits = encoded_source_to_bit_arrays(&attr_id, &encoded, &source_attr_order).expect("bit arrays");
-        println!("Source {}: {:?}", src.ip, bits);
+        let bits = encoded_source_to_bit_arrays_with(
+            &attr_id,
+            &encoded,
+            &source_attr_order,
+            MissingAttribute::ZeroFill,
+            &numeric_thresholds,
+        ).expect("bit arrays");
+        report.source_bits.push((src.ip.clone(), bits));
     }
 
-    let mut numeric_thresholds = HashMap::new();
-    numeric_thresholds.insert("Src.TrustScore".to_string(), vec![0, 50, 80]);
-
     for (dest_ip, rule_ids) in &applicable_rules {
         let dest_entity = data.destination_entities
             .iter()
@@ -88,53 +112,29 @@ fn main() {
                 &numeric_thresholds,
             ).expect("requirements to bits");
 
-            println!("Dest {} Rule {}: {:?}", dest_ip, rule_id, bits);
+            report.requirement_bits.push((dest_ip.clone(), rule_id.clone(), bits));
         }
     }
 
+    print!("{}", render_report(&report, format));
 }
 
 fn load_entities_and_policy() -> LoadedData {
-    println!("In File: {}", "data/ip_based_abac_entity.json");
-    let json_str = std::fs::read_to_string("data/ip_based_abac_entity.json").expect("File not found");
-    let json: Value = serde_json::from_str(&json_str).expect("JSON parse error");
-
-    let mut source_entities: Vec<SourceEntity> = Vec::new();
-    if let Some(Value::Array(source_array)) = json.get("source_entities") {
-        for entity in source_array {
-            match SourceEntity::from_json_value(entity) {
-                Ok(entity) => source_entities.push(entity),
-                Err(e) => eprintln!("Failed to parse source entity: {}", e),
-            }
-        }
-    }
+    eprintln!("In File: data/ip_based_abac_entity.json");
+    let (source_entities, destination_entities) = load_entities("data/ip_based_abac_entity.json")
+        .expect("Failed to load entities");
 
-    let mut destination_entities: Vec<DestinationEntity> = Vec::new();
-    if let Some(Value::Array(dest_array)) = json.get("destination_entities") {
-        for entity in dest_array {
-            match DestinationEntity::from_json_value(entity) {
-                Ok(entity) => destination_entities.push(entity),
-                Err(e) => eprintln!("Failed to parse destination entity: {}", e),
-            }
-        }
-    }
+    eprintln!("Loaded {} source entities", source_entities.len());
+    eprintln!("Loaded {} destination entities", destination_entities.len());
 
-    println!("Loaded {} source entities", source_entities.len());
-    println!("Loaded {} destination entities", destination_entities.len());
-
-    println!("\n=== Loading Policy ===");
-    let policy_str = std::fs::read_to_string("data/ip_based_abac_rule.json")
-        .expect("Policy file not found");
-    let policy_json: Value = serde_json::from_str(&policy_str)
-        .expect("Policy JSON parse error");
-    
-    let policy = Policy::from_json_value(&policy_json)
-        .expect("Failed to parse policy");
+    eprintln!("\n=== Loading Policy ===");
+    let policy = Policy::load("data/ip_based_abac_rule.json")
+        .expect("Failed to load policy");
     
-    println!("Policy: {}", policy.policy_name);
-    println!("Description: {}", policy.description);
-    println!("Default effect: {:?}", policy.default_effect);
-    println!("Number of rules: {}", policy.rules.len());
+    eprintln!("Policy: {}", policy.policy_name);
+    eprintln!("Description: {}", policy.description);
+    eprintln!("Default effect: {:?}", policy.default_effect);
+    eprintln!("Number of rules: {}", policy.rules.len());
 
     LoadedData {
         source_entities,
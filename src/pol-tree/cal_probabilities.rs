@@ -1,11 +1,12 @@
 use crate::abac_lab::attr_val::*;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 use crate::cal_shannon_entropy::cal_shannon_entropy_from_probabilities;
 use crate::ip_based::entity::{
     AttributeValue as IpAttributeValue, DestinationEntity, DestinationEntityAttributeKey,
     SourceEntity, SourceEntityAttributeKey,
 };
+use crate::ip_based::rule::Policy;
 
 fn attribute_value_to_key(value: &AttributeValue) -> String {
     match value {
@@ -29,141 +30,515 @@ fn ip_attribute_value_to_key(value: &IpAttributeValue) -> String {
             sorted.sort();
             format!("{{{}}}", sorted.join(", "))
         }
+        IpAttributeValue::Object(fields) => {
+            let entries: Vec<String> = fields
+                .iter()
+                .map(|(k, v)| format!("{}: {}", k, ip_attribute_value_to_key(v)))
+                .collect();
+            format!("Object{{{}}}", entries.join(", "))
+        }
+        IpAttributeValue::Null => "null".to_string(),
     }
 }
 
-pub fn cal_user_attribute_probabilities(
-    users: &[UserAttribute],
-    attribute_key: &UserAttributeKey,
-) -> Vec<f64> {
+fn count_keys<I: IntoIterator<Item = String>>(keys: I) -> Vec<(String, usize)> {
     let mut value_counts: HashMap<String, usize> = HashMap::new();
-    let mut total_count = 0;
-
-    for user in users {
-        if let Some(value) = user.attributes.get(attribute_key) {
-            let key = attribute_value_to_key(value);
-            *value_counts.entry(key).or_insert(0) += 1;
-            total_count += 1;
-        }
+    for key in keys {
+        *value_counts.entry(key).or_insert(0) += 1;
     }
+    sort_frequencies(value_counts)
+}
+
+// 値を to_key で正規化した文字列ごとに数える。abac_lab / ip_based のどちらの属性値にも使う
+fn value_frequencies<'a, V: 'a>(
+    values: impl Iterator<Item = &'a V>,
+    to_key: fn(&V) -> String,
+) -> Vec<(String, usize)> {
+    count_keys(values.map(to_key))
+}
+
+/// 正規化済みの値の列のシャノンエントロピー (bit)
+pub fn entropy_of<I: IntoIterator<Item = String>>(values: I) -> f64 {
+    cal_shannon_entropy_from_probabilities(&probabilities_from_frequencies(&count_keys(values)))
+}
 
+fn probabilities_from_frequencies(frequencies: &[(String, usize)]) -> Vec<f64> {
+    let total_count: usize = frequencies.iter().map(|(_, c)| c).sum();
     if total_count == 0 {
         return Vec::new();
     }
 
     let total_f64 = total_count as f64;
-    value_counts
-        .values()
-        .map(|&count| count as f64 / total_f64)
-        .collect() 
+    frequencies
+        .iter()
+        .map(|(_, count)| *count as f64 / total_f64)
+        .collect()
 }
 
-pub fn cal_resource_attribute_probabilities(
+pub fn user_attribute_frequencies(
+    users: &[UserAttribute],
+    attribute_key: &UserAttributeKey,
+) -> Vec<(String, usize)> {
+    value_frequencies(
+        users.iter().filter_map(|u| u.attributes.get(attribute_key)),
+        attribute_value_to_key,
+    )
+}
+
+pub fn resource_attribute_frequencies(
     resources: &[ResourceAttribute],
     attribute_key: &ResourceAttributeKey,
-) -> Vec<f64> {
-    let mut value_counts: HashMap<String, usize> = HashMap::new();
-    let mut total_count = 0;
-
-    for resource in resources {
-        if let Some(value) = resource.attributes.get(attribute_key) {
-            let key = attribute_value_to_key(value);
-            *value_counts.entry(key).or_insert(0) += 1;
-            total_count += 1;
-        }
-    }
+) -> Vec<(String, usize)> {
+    value_frequencies(
+        resources.iter().filter_map(|r| r.attributes.get(attribute_key)),
+        attribute_value_to_key,
+    )
+}
 
-    if total_count == 0 {
-        return Vec::new();
-    }
+pub fn cal_user_attribute_probabilities(
+    users: &[UserAttribute],
+    attribute_key: &UserAttributeKey,
+) -> Vec<f64> {
+    probabilities_from_frequencies(&user_attribute_frequencies(users, attribute_key))
+}
 
-    let total_f64 = total_count as f64;
-    value_counts
-        .values()
-        .map(|&count| count as f64 / total_f64)
-        .collect() 
+pub fn cal_resource_attribute_probabilities(
+    resources: &[ResourceAttribute],
+    attribute_key: &ResourceAttributeKey,
+) -> Vec<f64> {
+    probabilities_from_frequencies(&resource_attribute_frequencies(resources, attribute_key))
 }
 
 pub fn cal_user_attribute_entropy(
     users: &[UserAttribute],
     attribute_key: &UserAttributeKey,
 ) -> f64 {
-    let probabilities = cal_user_attribute_probabilities(users, attribute_key);
-    cal_shannon_entropy_from_probabilities(&probabilities)
+    entropy_of(
+        users
+            .iter()
+            .filter_map(|u| u.attributes.get(attribute_key))
+            .map(attribute_value_to_key),
+    )
 }
 
 pub fn cal_resource_attribute_entropy(
     resources: &[ResourceAttribute],
     attribute_key: &ResourceAttributeKey,
 ) -> f64 {
-    let probabilities = cal_resource_attribute_probabilities(resources, attribute_key);
-    cal_shannon_entropy_from_probabilities(&probabilities)
+    entropy_of(
+        resources
+            .iter()
+            .filter_map(|r| r.attributes.get(attribute_key))
+            .map(attribute_value_to_key),
+    )
+}
+
+/// エントロピーに重みを掛けた値が最大の (値が最もばらついている) ユーザ属性を返す。
+/// weights は属性名 ("position" など) -> 重みで、取得コストの高い属性を下げるのに使う。無い属性は 1.0。
+/// 同じ値なら UserAttributeKey::ALL の順で先のもの。どのユーザも属性を持たなければ None
+pub fn best_user_attribute_by_entropy(
+    users: &[UserAttribute],
+    weights: &HashMap<String, f64>,
+) -> Option<UserAttributeKey> {
+    let mut best: Option<(UserAttributeKey, f64)> = None;
+    for key in UserAttributeKey::ALL {
+        if !users.iter().any(|u| u.attributes.contains_key(&key)) {
+            continue;
+        }
+        let weight = weights.get(key.name()).copied().unwrap_or(1.0);
+        let entropy = cal_user_attribute_entropy(users, &key) * weight;
+        if best.as_ref().is_none_or(|(_, e)| entropy > *e) {
+            best = Some((key, entropy));
+        }
+    }
+    best.map(|(key, _)| key)
 }
 
 pub fn cal_source_entity_attribute_probabilities(
     sources: &[SourceEntity],
     attribute_key: &SourceEntityAttributeKey,
 ) -> Vec<f64> {
-    let mut value_counts: HashMap<String, usize> = HashMap::new();
-    let mut total_count = 0;
+    probabilities_from_frequencies(&source_attribute_frequencies(sources, attribute_key))
+}
 
-    for source in sources {
-        if let Some(value) = source.attributes.get(attribute_key) {
-            let key = ip_attribute_value_to_key(value);
-            *value_counts.entry(key).or_insert(0) += 1;
-            total_count += 1;
-        }
-    }
+pub fn cal_destination_entity_attribute_probabilities(
+    destinations: &[DestinationEntity],
+    attribute_key: &DestinationEntityAttributeKey,
+) -> Vec<f64> {
+    probabilities_from_frequencies(&destination_attribute_frequencies(destinations, attribute_key))
+}
 
-    if total_count == 0 {
+pub fn source_attribute_frequencies(
+    sources: &[SourceEntity],
+    attribute_key: &SourceEntityAttributeKey,
+) -> Vec<(String, usize)> {
+    value_frequencies(
+        sources.iter().filter_map(|s| s.attributes.get(attribute_key)),
+        ip_attribute_value_to_key,
+    )
+}
+
+pub fn destination_attribute_frequencies(
+    destinations: &[DestinationEntity],
+    attribute_key: &DestinationEntityAttributeKey,
+) -> Vec<(String, usize)> {
+    value_frequencies(
+        destinations.iter().filter_map(|d| d.attributes.get(attribute_key)),
+        ip_attribute_value_to_key,
+    )
+}
+
+// 出現回数の降順、同数なら値の昇順
+fn sort_frequencies(value_counts: HashMap<String, usize>) -> Vec<(String, usize)> {
+    let mut out: Vec<(String, usize)> = value_counts.into_iter().collect();
+    out.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    out
+}
+
+// (count + alpha) / (total + alpha * V)。観測されなかった値も alpha 分の確率を持つ
+fn smoothed_probabilities(
+    frequencies: &[(String, usize)],
+    alpha: f64,
+    vocabulary_size: usize,
+) -> Vec<f64> {
+    let vocabulary_size = vocabulary_size.max(frequencies.len());
+    let total: usize = frequencies.iter().map(|(_, c)| c).sum();
+    let denom = total as f64 + alpha * vocabulary_size as f64;
+    if denom <= 0.0 {
         return Vec::new();
     }
 
-    let total_f64 = total_count as f64;
-    value_counts
-        .values()
-        .map(|&count| count as f64 / total_f64)
-        .collect() 
+    let mut probabilities: Vec<f64> = frequencies
+        .iter()
+        .map(|(_, count)| (*count as f64 + alpha) / denom)
+        .collect();
+    let unseen = vocabulary_size - frequencies.len();
+    probabilities.extend(std::iter::repeat_n(alpha / denom, unseen));
+    probabilities
 }
 
-pub fn cal_destination_entity_attribute_probabilities(
+pub fn cal_source_entity_attribute_probabilities_smoothed(
+    sources: &[SourceEntity],
+    attribute_key: &SourceEntityAttributeKey,
+    alpha: f64,
+    vocabulary_size: usize,
+) -> Vec<f64> {
+    let frequencies = source_attribute_frequencies(sources, attribute_key);
+    smoothed_probabilities(&frequencies, alpha, vocabulary_size)
+}
+
+pub fn cal_destination_entity_attribute_probabilities_smoothed(
     destinations: &[DestinationEntity],
     attribute_key: &DestinationEntityAttributeKey,
+    alpha: f64,
+    vocabulary_size: usize,
 ) -> Vec<f64> {
-    let mut value_counts: HashMap<String, usize> = HashMap::new();
-    let mut total_count = 0;
-
-    for destination in destinations {
-        if let Some(value) = destination.attributes.get(attribute_key) {
-            let key = ip_attribute_value_to_key(value);
-            *value_counts.entry(key).or_insert(0) += 1;
-            total_count += 1;
-        }
-    }
+    let frequencies = destination_attribute_frequencies(destinations, attribute_key);
+    smoothed_probabilities(&frequencies, alpha, vocabulary_size)
+}
 
-    if total_count == 0 {
-        return Vec::new();
-    }
+pub fn cal_source_entity_attribute_entropy_smoothed(
+    sources: &[SourceEntity],
+    attribute_key: &SourceEntityAttributeKey,
+    alpha: f64,
+    vocabulary_size: usize,
+) -> f64 {
+    let probabilities = cal_source_entity_attribute_probabilities_smoothed(
+        sources, attribute_key, alpha, vocabulary_size,
+    );
+    cal_shannon_entropy_from_probabilities(&probabilities)
+}
 
-    let total_f64 = total_count as f64;
-    value_counts
-        .values()
-        .map(|&count| count as f64 / total_f64)
-        .collect() 
+pub fn cal_destination_entity_attribute_entropy_smoothed(
+    destinations: &[DestinationEntity],
+    attribute_key: &DestinationEntityAttributeKey,
+    alpha: f64,
+    vocabulary_size: usize,
+) -> f64 {
+    let probabilities = cal_destination_entity_attribute_probabilities_smoothed(
+        destinations, attribute_key, alpha, vocabulary_size,
+    );
+    cal_shannon_entropy_from_probabilities(&probabilities)
 }
 
 pub fn cal_source_entity_attribute_entropy(
     sources: &[SourceEntity],
     attribute_key: &SourceEntityAttributeKey,
 ) -> f64 {
-    let probabilities = cal_source_entity_attribute_probabilities(sources, attribute_key);
-    cal_shannon_entropy_from_probabilities(&probabilities)
+    entropy_of(
+        sources
+            .iter()
+            .filter_map(|s| s.attributes.get(attribute_key))
+            .map(ip_attribute_value_to_key),
+    )
 }
 
 pub fn cal_destination_entity_attribute_entropy(
     destinations: &[DestinationEntity],
     attribute_key: &DestinationEntityAttributeKey,
 ) -> f64 {
-    let probabilities = cal_destination_entity_attribute_probabilities(destinations, attribute_key);
-    cal_shannon_entropy_from_probabilities(&probabilities)
-}
\ No newline at end of file
+    entropy_of(
+        destinations
+            .iter()
+            .filter_map(|d| d.attributes.get(attribute_key))
+            .map(ip_attribute_value_to_key),
+    )
+}
+
+/// 属性 a と b の値の組が何件の source に一緒に現れるかを数える
+/// (例: どの Role がどの Dept と現れるか)。どちらかの属性を持たない source は数えない。
+/// 値の組の順に並ぶので、そのまま表として出力できる
+pub fn cooccurrence(
+    sources: &[SourceEntity],
+    a: &SourceEntityAttributeKey,
+    b: &SourceEntityAttributeKey,
+) -> BTreeMap<(String, String), usize> {
+    let mut counts = BTreeMap::new();
+    for source in sources {
+        if let (Some(va), Some(vb)) = (source.attributes.get(a), source.attributes.get(b)) {
+            *counts
+                .entry((ip_attribute_value_to_key(va), ip_attribute_value_to_key(vb)))
+                .or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+/// sources に現れる属性名をエントロピーの大きい順 (同じなら名前順) に並べる。
+/// 情報量の多い属性を先頭に置く encoder の source_attr_order として使える
+pub fn entropy_ordered_attrs(sources: &[SourceEntity]) -> Vec<String> {
+    let keys: HashSet<&SourceEntityAttributeKey> = sources
+        .iter()
+        .flat_map(|s| s.attributes.keys())
+        .collect();
+    let mut scored: Vec<(String, f64)> = keys
+        .into_iter()
+        .filter_map(|key| {
+            let name = SourceEntity::deparse_attribute_key(key).ok()?;
+            Some((name, cal_source_entity_attribute_entropy(sources, key)))
+        })
+        .collect();
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    scored.into_iter().map(|(name, _)| name).collect()
+}
+
+/// source の属性ごとに (属性名, データ上のエントロピー, 参照しているルール数) を返す。
+/// 参照ルール数の多い順、同数ならエントロピーの大きい順、さらに名前順に並べる。
+/// データに現れる属性と、ルールから参照される Src 属性の両方を対象にする
+pub fn attribute_importance(policy: &Policy, sources: &[SourceEntity]) -> Vec<(String, f64, usize)> {
+    let mut names: BTreeSet<String> = sources
+        .iter()
+        .flat_map(|s| s.attributes.keys())
+        .filter_map(|k| SourceEntity::deparse_attribute_key(k).ok())
+        .collect();
+    names.extend(
+        policy
+            .attributes_referenced()
+            .into_iter()
+            .filter(|name| SourceEntity::parse_attribute_key(name).is_ok()),
+    );
+
+    let mut out: Vec<(String, f64, usize)> = names
+        .into_iter()
+        .filter_map(|name| {
+            let key = SourceEntity::parse_attribute_key(&name).ok()?;
+            let entropy = cal_source_entity_attribute_entropy(sources, &key);
+            let rules = policy
+                .rules
+                .iter()
+                .filter(|r| r.condition.references_attribute(&name))
+                .count();
+            Some((name, entropy, rules))
+        })
+        .collect();
+    out.sort_by(|a, b| {
+        b.2.cmp(&a.2)
+            .then_with(|| b.1.total_cmp(&a.1))
+            .then_with(|| a.0.cmp(&b.0))
+    });
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn source(value: serde_json::Value) -> SourceEntity {
+        SourceEntity::from_json_value(&value).unwrap()
+    }
+
+    #[test]
+    fn nested_location_is_not_counted_as_a_set() {
+        let object = IpAttributeValue::Object(BTreeMap::from([
+            ("Country".to_string(), IpAttributeValue::String("JP".to_string())),
+        ]));
+        let set = IpAttributeValue::Set(vec!["Country: JP".to_string()]);
+        assert_eq!(ip_attribute_value_to_key(&object), "Object{Country: JP}");
+        assert_ne!(ip_attribute_value_to_key(&object), ip_attribute_value_to_key(&set));
+    }
+
+    #[test]
+    fn abac_lab_and_ip_based_entropy_agree_on_same_values() {
+        let mut parser = crate::abac_lab::parser::Parser::new();
+        let mut sources = Vec::new();
+        for (i, (dept, groups)) in [("cs", "{a b}"), ("cs", "{b a}"), ("bio", "{a}")].iter().enumerate() {
+            parser
+                .parse_line(&format!("userAttrib(u{}, department={}, crsTaken={})", i, dept, groups))
+                .unwrap();
+            let groups: Vec<&str> = groups.trim_matches(|c| c == '{' || c == '}').split(' ').collect();
+            sources.push(source(json!({
+                "ip": i.to_string(),
+                "attributes": { "Src.Dept": dept, "Src.Groups": groups }
+            })));
+        }
+
+        let user_dept = cal_user_attribute_entropy(&parser.users, &UserAttributeKey::Department);
+        let src_dept = cal_source_entity_attribute_entropy(&sources, &SourceEntityAttributeKey::Dept);
+        assert!((user_dept - src_dept).abs() < 1e-12);
+        assert!((user_dept - 0.9182958340544896).abs() < 1e-12);
+
+        assert_eq!(
+            user_attribute_frequencies(&parser.users, &UserAttributeKey::CrsTaken),
+            source_attribute_frequencies(&sources, &SourceEntityAttributeKey::Groups)
+        );
+    }
+
+    #[test]
+    fn entropy_of_even_split_is_one_bit() {
+        assert_eq!(entropy_of(["a", "b", "a", "b"].map(String::from)), 1.0);
+        assert_eq!(entropy_of(["a", "a"].map(String::from)), 0.0);
+        assert_eq!(entropy_of(Vec::new()), 0.0);
+    }
+
+    #[test]
+    fn best_user_attribute_is_most_diverse() {
+        let mut parser = crate::abac_lab::parser::Parser::new();
+        for line in [
+            "userAttrib(u1, position=student, department=cs, isChair=False)",
+            "userAttrib(u2, position=student, department=math, isChair=False)",
+            "userAttrib(u3, position=faculty, department=bio, isChair=False)",
+            "userAttrib(u4, position=faculty, department=ee, isChair=True)",
+        ] {
+            parser.parse_line(line).unwrap();
+        }
+
+        let no_weights = HashMap::new();
+        assert_eq!(best_user_attribute_by_entropy(&parser.users, &no_weights), Some(UserAttributeKey::Department));
+        assert_eq!(best_user_attribute_by_entropy(&[], &no_weights), None);
+
+        // department (2 bit) を 0.4 倍すると position (1 bit) が上になる
+        let weights = HashMap::from([("department".to_string(), 0.4)]);
+        assert_eq!(best_user_attribute_by_entropy(&parser.users, &weights), Some(UserAttributeKey::Position));
+    }
+
+    #[test]
+    fn source_frequencies_sum_to_entities_with_attribute() {
+        let sources = vec![
+            source(json!({ "ip": "1", "attributes": { "Src.Role": "Student", "Src.Groups": ["b", "a"] } })),
+            source(json!({ "ip": "2", "attributes": { "Src.Role": "Student", "Src.Groups": ["a", "b"] } })),
+            source(json!({ "ip": "3", "attributes": { "Src.Role": "Admin" } })),
+            source(json!({ "ip": "4", "attributes": { "Src.Dept": "CS" } })),
+        ];
+
+        let roles = source_attribute_frequencies(&sources, &SourceEntityAttributeKey::Role);
+        assert_eq!(roles, vec![("Student".to_string(), 2), ("Admin".to_string(), 1)]);
+        assert_eq!(roles.iter().map(|(_, c)| c).sum::<usize>(), 3);
+
+        let groups = source_attribute_frequencies(&sources, &SourceEntityAttributeKey::Groups);
+        assert_eq!(groups, vec![("{a, b}".to_string(), 2)]);
+    }
+
+    #[test]
+    fn smoothing_spreads_mass_over_unseen_values() {
+        let sources = vec![
+            source(json!({ "ip": "1", "attributes": { "Src.Role": "Student" } })),
+            source(json!({ "ip": "2", "attributes": { "Src.Role": "Student" } })),
+        ];
+        let key = SourceEntityAttributeKey::Role;
+
+        let raw = cal_source_entity_attribute_entropy(&sources, &key);
+        let smoothed = cal_source_entity_attribute_entropy_smoothed(&sources, &key, 1.0, 3);
+        assert_eq!(raw, 0.0);
+        assert!(smoothed > 0.0 && smoothed < 3f64.log2());
+
+        // (2+1)/5, 1/5, 1/5
+        let probs = cal_source_entity_attribute_probabilities_smoothed(&sources, &key, 1.0, 3);
+        assert_eq!(probs, vec![0.6, 0.2, 0.2]);
+
+        // alpha = 0 は平滑化なしと一致する
+        let unsmoothed = cal_source_entity_attribute_entropy_smoothed(&sources, &key, 0.0, 3);
+        assert_eq!(unsmoothed, raw);
+    }
+
+    #[test]
+    fn attribute_importance_ranks_referenced_attributes_first() {
+        let data: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string("data/ip_based_abac_entity.json").unwrap()).unwrap();
+        let sources: Vec<SourceEntity> = data["source_entities"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| source(v.clone()))
+            .collect();
+        let policy = Policy::from_json_value(&json!({
+            "policy_name": "p",
+            "description": "",
+            "default_effect": "deny",
+            "rules": [
+                { "id": "r1", "effect": "allow", "condition": { "operator": "EQ", "lhs": "Src.Role", "rhs": "Admin" } },
+                { "id": "r2", "effect": "allow", "condition": { "operator": "AND", "operands": [
+                    { "operator": "EQ", "lhs": "Src.Role", "rhs": "Staff" },
+                    { "operator": "IN", "value": "Faculty", "set": "Src.Groups" }
+                ] } }
+            ]
+        })).unwrap();
+
+        let report = attribute_importance(&policy, &sources);
+        let order: Vec<(&str, usize)> = report.iter().map(|(n, _, c)| (n.as_str(), *c)).collect();
+        assert_eq!(&order[..2], &[("Src.Role", 2), ("Src.Groups", 1)]);
+        assert!(order[2..].iter().all(|(_, c)| *c == 0));
+        assert_eq!(report.len(), 4);
+        let unreferenced = &report[2..];
+        assert!(unreferenced[0].1 >= unreferenced[1].1);
+    }
+
+    #[test]
+    fn entropy_ordered_attrs_sorts_by_descending_entropy() {
+        let sources = vec![
+            source(json!({ "ip": "1", "attributes": { "Src.Role": "Student", "Src.Dept": "CS", "Src.TrustScore": 10 } })),
+            source(json!({ "ip": "2", "attributes": { "Src.Role": "Student", "Src.Dept": "Bio", "Src.TrustScore": 20 } })),
+            source(json!({ "ip": "3", "attributes": { "Src.Role": "Staff", "Src.Dept": "CS", "Src.TrustScore": 30 } })),
+            source(json!({ "ip": "4", "attributes": { "Src.Role": "Student", "Src.Dept": "EE", "Src.TrustScore": 40 } })),
+        ];
+        let order = entropy_ordered_attrs(&sources);
+        assert_eq!(order, vec!["Src.TrustScore", "Src.Dept", "Src.Role"]);
+
+        let entropies: Vec<f64> = order
+            .iter()
+            .map(|name| {
+                let key = SourceEntity::parse_attribute_key(name).unwrap();
+                cal_source_entity_attribute_entropy(&sources, &key)
+            })
+            .collect();
+        assert!(entropies.windows(2).all(|w| w[0] >= w[1]), "{:?}", entropies);
+    }
+
+    #[test]
+    fn cooccurrence_counts_role_department_pairs() {
+        let sources = vec![
+            source(json!({ "ip": "1", "attributes": { "Src.Role": "Student", "Src.Dept": "CS" } })),
+            source(json!({ "ip": "2", "attributes": { "Src.Role": "Student", "Src.Dept": "CS" } })),
+            source(json!({ "ip": "3", "attributes": { "Src.Role": "Student", "Src.Dept": "Bio" } })),
+            source(json!({ "ip": "4", "attributes": { "Src.Role": "Staff", "Src.Dept": "CS" } })),
+            source(json!({ "ip": "5", "attributes": { "Src.Role": "Staff" } })),
+        ];
+        let counts = cooccurrence(&sources, &SourceEntityAttributeKey::Role, &SourceEntityAttributeKey::Dept);
+        let pair = |r: &str, d: &str| (r.to_string(), d.to_string());
+        assert_eq!(counts, BTreeMap::from([
+            (pair("Student", "CS"), 2),
+            (pair("Student", "Bio"), 1),
+            (pair("Staff", "CS"), 1),
+        ]));
+    }
+}
@@ -0,0 +1,79 @@
+use std::io::{BufRead, BufReader, Read};
+
+// 1 行を列に分割する。ダブルクォートで囲んだ列はカンマを含められ、"" は " になる
+fn split_csv_line(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', true) => in_quotes = false,
+            ('"', false) if field.is_empty() => in_quotes = true,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(format!("Unterminated quoted field: {}", line));
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+/// 1 行目をヘッダとする CSV。空の入力では header も rows も空になる
+#[derive(Debug)]
+pub struct CsvTable {
+    pub header: Vec<String>,
+    /// (エラー表示用の行番号, セル)。空行は含まず、セル数はヘッダと同じ
+    pub rows: Vec<(usize, Vec<String>)>,
+}
+
+pub fn read_csv_table<R: Read>(reader: R) -> Result<CsvTable, String> {
+    let mut lines = BufReader::new(reader).lines();
+    let header = match lines.next() {
+        Some(line) => split_csv_line(&line.map_err(|e| e.to_string())?)?,
+        None => return Ok(CsvTable { header: Vec::new(), rows: Vec::new() }),
+    };
+
+    let mut rows = Vec::new();
+    for (line_no, line) in lines.enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let cells = split_csv_line(&line)?;
+        if cells.len() != header.len() {
+            return Err(format!(
+                "Line {}: expected {} columns, got {}",
+                line_no + 2,
+                header.len(),
+                cells.len()
+            ));
+        }
+        rows.push((line_no + 2, cells));
+    }
+    Ok(CsvTable { header, rows })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_cells_keep_commas_and_blank_lines_are_skipped() {
+        let table = read_csv_table("a,b\n\"x, y\",\"say \"\"hi\"\"\"\n\n1,2\n".as_bytes()).unwrap();
+        assert_eq!(table.header, vec!["a", "b"]);
+        assert_eq!(table.rows, vec![
+            (2, vec!["x, y".to_string(), "say \"hi\"".to_string()]),
+            (4, vec!["1".to_string(), "2".to_string()]),
+        ]);
+        assert!(read_csv_table("a,b\n1\n".as_bytes()).unwrap_err().starts_with("Line 2:"));
+        assert!(read_csv_table("".as_bytes()).unwrap().header.is_empty());
+    }
+}
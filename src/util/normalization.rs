@@ -0,0 +1,41 @@
+/// 読み込み時に文字列の属性値 (集合の要素を含む) へかける正規化。既定ではすべて無効
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NormalizationConfig {
+    /// 前後の空白を取り除く
+    pub trim: bool,
+    /// 小文字にそろえる
+    pub lowercase: bool,
+    /// 連続する空白を 1 つの半角スペースにまとめる
+    pub collapse_whitespace: bool,
+}
+
+impl NormalizationConfig {
+    pub fn apply(&self, value: &str) -> String {
+        let mut out = if self.collapse_whitespace {
+            // 前後の空白は trim の指定に任せる
+            let mut collapsed = String::with_capacity(value.len());
+            let mut in_space = false;
+            for c in value.chars() {
+                if c.is_whitespace() {
+                    if !in_space {
+                        collapsed.push(' ');
+                    }
+                    in_space = true;
+                } else {
+                    collapsed.push(c);
+                    in_space = false;
+                }
+            }
+            collapsed
+        } else {
+            value.to_string()
+        };
+        if self.trim {
+            out = out.trim().to_string();
+        }
+        if self.lowercase {
+            out = out.to_lowercase();
+        }
+        out
+    }
+}